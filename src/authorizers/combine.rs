@@ -0,0 +1,99 @@
+//! Authorizers combining other authorizers: `!AnyOf` and `!AllOf`
+//!
+//! Referenced authorizers are checked in order. Allow and deny strings in
+//! `Debug` are prefixed by the name of the authorizer that made the decision.
+use futures::Future;
+use futures::future::{join_all, select_ok};
+use tk_http::server::Error;
+
+use crate::incoming::{Input, Check, AuthFuture, Deny};
+use crate::intern::Authorizer as AuthorizerName;
+
+
+fn check_one(name: &AuthorizerName, input: &mut Input)
+    -> Result<Check, Error>
+{
+    let config = input.config.clone();
+    match config.authorizers.get(name) {
+        Some(auth) => auth.check(input),
+        // Validated when config is read
+        None => unreachable!(),
+    }
+}
+
+/// Prefixes deny reason recorded by the authorizer with its name
+fn prefix_deny(name: &AuthorizerName, input: &mut Input) {
+    let reason = input.debug.get_deny().unwrap_or("").to_string();
+    input.debug.set_deny(format_args!("{}: {}", name, reason));
+}
+
+fn prefix_pending(name: &AuthorizerName, auth: AuthFuture) -> AuthFuture {
+    let name = name.clone();
+    Box::new(auth.map_err(move |deny| Deny {
+        reason: format!("{}: {}", name, deny.reason),
+        challenge: deny.challenge,
+    }))
+}
+
+pub fn any_of(names: &[AuthorizerName], input: &mut Input)
+    -> Result<Check, Error>
+{
+    let mut pending = Vec::new();
+    let mut challenge = None;
+    for name in names {
+        match check_one(name, input)? {
+            Check::Done(true) => {
+                // Earlier branches might have recorded their denial
+                input.debug.set_deny("");
+                input.debug.add_allow(format_args!("any-of {}", name));
+                return Ok(Check::Done(true));
+            }
+            Check::Done(false) => prefix_deny(name, input),
+            Check::Unauthorized(value) => {
+                prefix_deny(name, input);
+                challenge = challenge.or(Some(value));
+            }
+            Check::Pending(auth) => pending.push(prefix_pending(name, auth)),
+        }
+    }
+    if pending.len() > 0 {
+        // Denied synchronously branches are not important anymore, but the
+        // reason of the last failed asynchronous one is recorded
+        return Ok(Check::Pending(Box::new(select_ok(pending)
+            .map(|_| ())
+            .map_err(move |deny| Deny {
+                reason: deny.reason,
+                challenge: deny.challenge.or(challenge),
+            }))));
+    }
+    match challenge {
+        // Client may retry with credentials, so ask for them
+        Some(value) => Ok(Check::Unauthorized(value)),
+        None => Ok(Check::Done(false)),
+    }
+}
+
+pub fn all_of(names: &[AuthorizerName], input: &mut Input)
+    -> Result<Check, Error>
+{
+    let mut pending = Vec::new();
+    for name in names {
+        match check_one(name, input)? {
+            Check::Done(true) => {}
+            Check::Done(false) => {
+                prefix_deny(name, input);
+                return Ok(Check::Done(false));
+            }
+            Check::Unauthorized(value) => {
+                prefix_deny(name, input);
+                return Ok(Check::Unauthorized(value));
+            }
+            Check::Pending(auth) => pending.push(prefix_pending(name, auth)),
+        }
+    }
+    if pending.len() > 0 {
+        Ok(Check::Pending(Box::new(join_all(pending).map(|_| ()))))
+    } else {
+        Ok(Check::Done(true))
+    }
+}
@@ -0,0 +1,225 @@
+//! HTTP Basic authorizer checking passwords against an htpasswd file
+//!
+//! Supported hashes are bcrypt (`htpasswd -B`) and SHA1 (`htpasswd -s`).
+//! File is reread when its modification time changes. Passwords that
+//! were not checked before are verified in the default disk pool, because
+//! bcrypt is slow by design.
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, metadata};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use futures::Future;
+
+use blake2::Blake2b;
+use digest::FixedOutput;
+use digest_writer::Writer;
+use generic_array::GenericArray;
+use generic_array::typenum::U64;
+use sha1::Sha1;
+use tk_http::server::Error;
+
+use crate::authorizers::basic_credentials;
+use crate::base64;
+use crate::config::htpasswd::HtPasswd;
+use crate::incoming::{Input, Check, Deny};
+
+
+/// Limit of remembered successful checks per file
+const MAX_VERIFIED: usize = 10000;
+
+type CacheKey = GenericArray<u8, U64>;
+
+struct Passwords {
+    modified: SystemTime,
+    entries: HashMap<String, String>,
+    /// Hash from the same file which is checked for unknown logins, so
+    /// that response time doesn't reveal which users exist
+    dummy: Option<String>,
+    /// Digests of credentials that were already checked successfully
+    ///
+    /// These are allowed without sending work to the disk pool.
+    verified: Mutex<HashSet<CacheKey>>,
+}
+
+lazy_static! {
+    static ref FILES: Mutex<HashMap<PathBuf, Arc<Passwords>>> =
+        Mutex::new(HashMap::new());
+}
+
+fn read_file(path: &Path) -> io::Result<HashMap<String, String>> {
+    let mut entries = HashMap::new();
+    let file = BufReader::new(File::open(path)?);
+    for line in file.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.len() == 0 || line.starts_with('#') {
+            continue;
+        }
+        let mut pair = line.splitn(2, ':');
+        match (pair.next(), pair.next()) {
+            (Some(login), Some(hash)) => {
+                entries.insert(login.to_string(), hash.to_string());
+            }
+            _ => {
+                warn!("{:?}: invalid line {:?}", path, line);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn passwords(path: &Path) -> io::Result<Arc<Passwords>> {
+    let modified = metadata(path)?.modified()?;
+    let mut files = FILES.lock().expect("htpasswd files not poisoned");
+    if let Some(passwords) = files.get(path) {
+        if passwords.modified == modified {
+            return Ok(passwords.clone());
+        }
+    }
+    let entries = read_file(path)?;
+    let passwords = Arc::new(Passwords {
+        modified: modified,
+        dummy: entries.values().next().cloned(),
+        entries: entries,
+        verified: Mutex::new(HashSet::new()),
+    });
+    debug!("Loaded {} users from {:?}", passwords.entries.len(), path);
+    files.insert(path.to_path_buf(), passwords.clone());
+    Ok(passwords)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() &&
+        a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn verify(hash: &str, password: &str) -> bool {
+    if hash.starts_with("$2y$") || hash.starts_with("$2b$") ||
+        hash.starts_with("$2a$")
+    {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    } else if hash.starts_with("{SHA}") {
+        let mut sha = Sha1::new();
+        sha.update(password.as_bytes());
+        match base64::decode(&hash["{SHA}".len()..]) {
+            Some(expected) => {
+                constant_time_eq(&expected, &sha.digest().bytes())
+            }
+            None => false,
+        }
+    } else {
+        warn!("Unsupported password hash {:?}, \
+            only bcrypt and SHA1 are supported",
+            hash.splitn(2, '$').nth(1).unwrap_or("plain text"));
+        false
+    }
+}
+
+fn cache_key(login: &str, password: &str, hash: &str) -> CacheKey {
+    let mut digest = Writer::new(Blake2b::default());
+    for part in &[login, password, hash] {
+        digest.write_all(part.as_bytes()).expect("write to digest");
+        digest.write_all(&[0]).expect("write to digest");
+    }
+    digest.into_inner().fixed_result()
+}
+
+impl Passwords {
+    /// Returns true if same credentials were checked successfully before
+    fn is_verified(&self, login: &str, password: &str) -> bool {
+        match self.entries.get(login) {
+            Some(hash) => {
+                let key = cache_key(login, password, hash);
+                self.verified.lock().expect("not poisoned").contains(&key)
+            }
+            None => false,
+        }
+    }
+    /// Verifies password, this is slow so it's run in the disk pool
+    fn check(&self, login: &str, password: &str) -> bool {
+        let hash = match self.entries.get(login) {
+            Some(hash) => hash,
+            None => {
+                if let Some(ref dummy) = self.dummy {
+                    verify(dummy, password);
+                }
+                return false;
+            }
+        };
+        if !verify(hash, password) {
+            return false;
+        }
+        let mut verified = self.verified.lock().expect("not poisoned");
+        if verified.len() >= MAX_VERIFIED {
+            verified.clear();
+        }
+        verified.insert(cache_key(login, password, hash));
+        return true;
+    }
+}
+
+fn challenge(cfg: &HtPasswd) -> String {
+    let realm = cfg.realm.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("Basic realm=\"{}\"", realm)
+}
+
+pub fn check(cfg: &Arc<HtPasswd>, input: &mut Input)
+    -> Result<Check, Error>
+{
+    let (login, password) = match basic_credentials(input) {
+        Some(pair) => pair,
+        None => {
+            input.debug.set_deny("htpasswd no-credentials");
+            return Ok(Check::Unauthorized(challenge(cfg)));
+        }
+    };
+    let passwords = match passwords(&cfg.file) {
+        Ok(passwords) => passwords,
+        Err(e) => {
+            error!("Can't read htpasswd file {:?}: {}", cfg.file, e);
+            input.debug.set_deny("htpasswd file-error");
+            return Ok(Check::Done(false));
+        }
+    };
+    if passwords.is_verified(&login, &password) {
+        input.debug.add_allow(format_args!("htpasswd {}", login));
+        input.identity.set_attribute("login", login);
+        return Ok(Check::Done(true));
+    }
+    let identity = input.identity.clone();
+    let challenge = challenge(cfg);
+    let user = login.clone();
+    let auth = input.runtime.disk_pools.default_pool()
+        .spawn_fn(move || Ok::<_, ()>(passwords.check(&login, &password)))
+        .then(move |result| match result {
+            Ok(true) => {
+                identity.set_attribute("login", user);
+                Ok(())
+            }
+            Ok(false) | Err(()) => Err(Deny {
+                reason: format!("htpasswd {}", user),
+                challenge: Some(challenge),
+            }),
+        });
+    Ok(Check::Pending(Box::new(auth)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::verify;
+
+    #[test]
+    fn sha() {
+        assert!(verify("{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=", "secret"));
+        assert!(!verify("{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=", "secreT"));
+        assert!(!verify("{SHA}invalid", "secret"));
+    }
+
+    #[test]
+    fn plain_text() {
+        assert!(!verify("secret", "secret"));
+    }
+}
@@ -0,0 +1,469 @@
+//! JWT bearer-token authorizer
+//!
+//! Supports HS256 tokens signed with a shared secret, and RS256 and ES256
+//! tokens checked against public keys from a JWKS file. The JWKS file is
+//! reread when its modification time changes.
+use std::collections::HashMap;
+use std::fs::{File, metadata};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::from_utf8;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ring::{digest, hmac, signature};
+use serde_json::{self, Value as Json, Map};
+use tk_http::server::Error;
+use untrusted::Input as Bytes;
+
+use crate::base64;
+use crate::config::jwt::Jwt;
+use crate::cookie::cookie;
+use crate::incoming::{Input, Check};
+
+
+type Claims = Map<String, Json>;
+
+enum Key {
+    /// DER-encoded `RSAPublicKey`
+    Rsa(Vec<u8>),
+    /// Uncompressed P-256 point
+    Ec(Vec<u8>),
+}
+
+struct Keys {
+    modified: SystemTime,
+    keys: Vec<(Option<String>, Key)>,
+}
+
+lazy_static! {
+    static ref JWKS: Mutex<HashMap<PathBuf, Arc<Keys>>> =
+        Mutex::new(HashMap::new());
+}
+
+fn der(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut buf = vec![tag];
+    if body.len() < 0x80 {
+        buf.push(body.len() as u8);
+    } else if body.len() < 0x100 {
+        buf.extend(&[0x81, body.len() as u8]);
+    } else {
+        buf.extend(&[0x82, (body.len() >> 8) as u8, body.len() as u8]);
+    }
+    buf.extend(body);
+    return buf;
+}
+
+/// Encodes big-endian unsigned number as DER integer
+fn der_integer(value: &[u8]) -> Vec<u8> {
+    let skip = value.iter().take_while(|&&b| b == 0).count();
+    let value = &value[skip..];
+    if value.len() == 0 || value[0] & 0x80 != 0 {
+        let mut body = vec![0];
+        body.extend(value);
+        der(0x02, &body)
+    } else {
+        der(0x02, value)
+    }
+}
+
+/// Converts JWS `r || s` signature into ASN.1 form expected by `ring`
+fn ecdsa_signature(sig: &[u8]) -> Option<Vec<u8>> {
+    if sig.len() != 64 {
+        return None;
+    }
+    let body = [der_integer(&sig[..32]), der_integer(&sig[32..])].concat();
+    Some(der(0x30, &body))
+}
+
+fn field(key: &Json, name: &str) -> Option<Vec<u8>> {
+    key.get(name).and_then(|v| v.as_str()).and_then(base64::decode_url)
+}
+
+fn parse_jwks(data: &[u8]) -> Result<Vec<(Option<String>, Key)>, String> {
+    let jwks: Json = serde_json::from_slice(data)
+        .map_err(|e| e.to_string())?;
+    let list = jwks.get("keys").and_then(|k| k.as_array())
+        .ok_or("no `keys` array")?;
+    let mut keys = Vec::new();
+    for key in list {
+        let kid = key.get("kid").and_then(|k| k.as_str())
+            .map(|k| k.to_string());
+        if key.get("use").and_then(|u| u.as_str()).unwrap_or("sig") != "sig"
+        {
+            continue;
+        }
+        match key.get("kty").and_then(|k| k.as_str()) {
+            Some("RSA") => {
+                let (n, e) = match (field(key, "n"), field(key, "e")) {
+                    (Some(n), Some(e)) => (n, e),
+                    _ => return Err(format!("bad RSA key {:?}", kid)),
+                };
+                let body = [der_integer(&n), der_integer(&e)].concat();
+                keys.push((kid, Key::Rsa(der(0x30, &body))));
+            }
+            Some("EC") => {
+                let crv = key.get("crv").and_then(|c| c.as_str());
+                if crv != Some("P-256") {
+                    warn!("Unsupported curve of JWKS key {:?}", kid);
+                    continue;
+                }
+                match (field(key, "x"), field(key, "y")) {
+                    (Some(ref x), Some(ref y))
+                    if x.len() == 32 && y.len() == 32 => {
+                        let point = [&[0x04][..], x, y].concat();
+                        keys.push((kid, Key::Ec(point)));
+                    }
+                    _ => return Err(format!("bad EC key {:?}", kid)),
+                }
+            }
+            kty => {
+                warn!("Unsupported type {:?} of JWKS key {:?}", kty, kid);
+            }
+        }
+    }
+    Ok(keys)
+}
+
+fn keys(path: &Path) -> Result<Arc<Keys>, String> {
+    let modified = metadata(path).and_then(|m| m.modified())
+        .map_err(|e| e.to_string())?;
+    let mut files = JWKS.lock().expect("jwks not poisoned");
+    if let Some(keys) = files.get(path) {
+        if keys.modified == modified {
+            return Ok(keys.clone());
+        }
+    }
+    let mut data = Vec::new();
+    File::open(path).and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|e: io::Error| e.to_string())?;
+    let keys = Arc::new(Keys {
+        modified: modified,
+        keys: parse_jwks(&data)?,
+    });
+    debug!("Loaded {} keys from {:?}", keys.keys.len(), path);
+    files.insert(path.to_path_buf(), keys.clone());
+    Ok(keys)
+}
+
+fn check_signature(cfg: &Jwt, keys: Option<&Keys>, alg: &str,
+    kid: Option<&str>, message: &[u8], sig: &[u8])
+    -> Result<bool, &'static str>
+{
+    match alg {
+        "HS256" => {
+            let secret = cfg.secret.as_ref()
+                .ok_or("unsupported-algorithm")?;
+            let key = hmac::VerificationKey::new(&digest::SHA256,
+                secret.as_bytes());
+            Ok(hmac::verify(&key, message, sig).is_ok())
+        }
+        "RS256" | "ES256" => {
+            let keys = keys.ok_or("unsupported-algorithm")?;
+            let ecdsa = if alg == "ES256" {
+                ecdsa_signature(sig)
+            } else {
+                None
+            };
+            Ok(keys.keys.iter()
+                .filter(|&&(ref id, _)| {
+                    kid.is_none() || id.as_ref().map(|x| &x[..]) == kid
+                })
+                .any(|&(_, ref key)| match (alg, key) {
+                    ("RS256", &Key::Rsa(ref der)) => {
+                        signature::verify(
+                            &signature::RSA_PKCS1_2048_8192_SHA256,
+                            Bytes::from(der), Bytes::from(message),
+                            Bytes::from(sig)).is_ok()
+                    }
+                    ("ES256", &Key::Ec(ref point)) => match ecdsa {
+                        Some(ref sig) => signature::verify(
+                            &signature::ECDSA_P256_SHA256_ASN1,
+                            Bytes::from(point), Bytes::from(message),
+                            Bytes::from(sig)).is_ok(),
+                        None => false,
+                    },
+                    _ => false,
+                }))
+        }
+        _ => Err("unsupported-algorithm"),
+    }
+}
+
+fn decode_json(data: &str) -> Option<Json> {
+    base64::decode_url(data)
+        .and_then(|data| serde_json::from_slice(&data).ok())
+}
+
+/// Checks token and returns its claims, error is a reason for `Debug`
+fn validate(cfg: &Jwt, keys: Option<&Keys>, token: &str, now: SystemTime)
+    -> Result<Claims, &'static str>
+{
+    let mut parts = token.split('.');
+    let (header, payload, sig) =
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(h), Some(p), Some(s), None) => (h, p, s),
+            _ => return Err("malformed"),
+        };
+    let message = &token[..header.len() + 1 + payload.len()];
+    let header = decode_json(header).ok_or("malformed")?;
+    let sig = base64::decode_url(sig).ok_or("malformed")?;
+    let alg = header.get("alg").and_then(|a| a.as_str())
+        .ok_or("malformed")?;
+    let kid = header.get("kid").and_then(|k| k.as_str());
+    if !check_signature(cfg, keys, alg, kid, message.as_bytes(), &sig)? {
+        return Err("bad-signature");
+    }
+    let claims = match decode_json(payload) {
+        Some(Json::Object(claims)) => claims,
+        _ => return Err("malformed"),
+    };
+    let now = now.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as f64).unwrap_or(0.);
+    let leeway = cfg.leeway.as_secs() as f64;
+    if let Some(exp) = claims.get("exp") {
+        if now >= exp.as_f64().ok_or("malformed")? + leeway {
+            return Err("expired");
+        }
+    }
+    if let Some(nbf) = claims.get("nbf") {
+        if now + leeway < nbf.as_f64().ok_or("malformed")? {
+            return Err("not-yet-valid");
+        }
+    }
+    if let Some(ref audience) = cfg.audience {
+        let ok = match claims.get("aud") {
+            Some(&Json::String(ref aud)) => aud == audience,
+            Some(&Json::Array(ref list)) => {
+                list.iter().any(|a| a.as_str() == Some(audience))
+            }
+            _ => false,
+        };
+        if !ok {
+            return Err("wrong-audience");
+        }
+    }
+    if let Some(ref issuer) = cfg.issuer {
+        if claims.get("iss").and_then(|i| i.as_str()) != Some(issuer) {
+            return Err("wrong-issuer");
+        }
+    }
+    Ok(claims)
+}
+
+/// Returns bearer token or, if there is none, the cookie
+///
+/// Other `Authorization` schemes (i.e. `Basic`) are ignored, as they may
+/// be used by another authorizer or by the backend itself.
+fn token(cfg: &Jwt, headers: &[(&str, &[u8])]) -> Option<String> {
+    let bearer = headers.iter()
+        .filter(|&&(name, _)| name.eq_ignore_ascii_case("Authorization"))
+        .filter_map(|&(_, value)| from_utf8(value).ok())
+        .filter_map(|value| {
+            let mut parts = value.trim().splitn(2, ' ');
+            match (parts.next(), parts.next()) {
+                (Some(scheme), Some(token))
+                if scheme.eq_ignore_ascii_case("Bearer")
+                => Some(token.trim().to_string()),
+                _ => None,
+            }
+        })
+        .next();
+    if bearer.is_some() {
+        return bearer;
+    }
+    let name = cfg.cookie.as_ref()?;
+    let values = headers.iter()
+        .filter(|&&(header, _)| header.eq_ignore_ascii_case("Cookie"))
+        .map(|&(_, value)| value);
+    cookie(values, name).map(|v| v.to_string())
+}
+
+fn headers(cfg: &Jwt, claims: &Claims) -> Vec<(String, String)> {
+    let mut headers = Vec::new();
+    for (header, claim) in &cfg.claim_headers {
+        match claims.get(claim) {
+            None | Some(&Json::Null) => {}
+            Some(&Json::String(ref value)) => {
+                headers.push((header.clone(), value.clone()));
+            }
+            Some(value) => {
+                headers.push((header.clone(), value.to_string()));
+            }
+        }
+    }
+    headers.sort();
+    return headers;
+}
+
+pub fn check(cfg: &Arc<Jwt>, input: &mut Input) -> Result<Check, Error> {
+    let request_headers = input.headers.headers().collect::<Vec<_>>();
+    let token = match token(cfg, &request_headers) {
+        Some(token) => token,
+        None => {
+            input.debug.set_deny("jwt no-token");
+            return Ok(Check::Unauthorized("Bearer".into()));
+        }
+    };
+    let keys = match cfg.jwks_file {
+        Some(ref path) => match keys(path) {
+            Ok(keys) => Some(keys),
+            Err(e) => {
+                error!("Can't read JWKS file {:?}: {}", path, e);
+                input.debug.set_deny("jwt jwks-error");
+                return Ok(Check::Done(false));
+            }
+        },
+        None => None,
+    };
+    match validate(cfg, keys.as_ref().map(|k| &**k), &token,
+                   SystemTime::now())
+    {
+        Ok(claims) => {
+            match claims.get("sub").and_then(|s| s.as_str()) {
+                Some(sub) => {
+                    input.debug.add_allow(format_args!("jwt {}", sub));
+                    input.identity.set_attribute("subject", sub);
+                }
+                None => input.debug.add_allow("jwt"),
+            }
+            input.identity.add(headers(cfg, &claims));
+            Ok(Check::Done(true))
+        }
+        Err(reason) => {
+            input.debug.set_deny(format_args!("jwt {}", reason));
+            let challenge = "Bearer error=\"invalid_token\"";
+            Ok(Check::Unauthorized(challenge.into()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use ring::{digest, hmac};
+
+    use crate::base64::Base64;
+    use crate::config::jwt::Jwt;
+    use super::{validate, parse_jwks, ecdsa_signature, Key};
+    use super::token as request_token;
+
+    fn b64(data: &[u8]) -> String {
+        Base64(data).to_string()
+            .replace('+', "-").replace('/', "_").replace('=', "")
+    }
+
+    fn config() -> Jwt {
+        Jwt {
+            secret: Some("secret".into()),
+            jwks_file: None,
+            cookie: None,
+            audience: Some("swindon".into()),
+            issuer: None,
+            leeway: Duration::new(0, 0),
+            claim_headers: HashMap::new(),
+        }
+    }
+
+    fn token(header: &str, claims: &str) -> String {
+        let message = format!("{}.{}",
+            b64(header.as_bytes()), b64(claims.as_bytes()));
+        let key = hmac::SigningKey::new(&digest::SHA256, b"secret");
+        let sig = hmac::sign(&key, message.as_bytes());
+        format!("{}.{}", message, b64(sig.as_ref()))
+    }
+
+    fn check(token: &str) -> Result<(), &'static str> {
+        validate(&config(), None, token,
+            UNIX_EPOCH + Duration::new(1000, 0))
+        .map(|_| ())
+    }
+
+    #[test]
+    fn valid() {
+        let hs = r#"{"alg":"HS256","typ":"JWT"}"#;
+        assert_eq!(check(&token(hs,
+            r#"{"sub":"john","aud":"swindon","exp":1001,"nbf":1000}"#)),
+            Ok(()));
+        assert_eq!(check(&token(hs,
+            r#"{"aud":["other","swindon"]}"#)),
+            Ok(()));
+    }
+
+    #[test]
+    fn token_location() {
+        let mut cfg = config();
+        cfg.cookie = Some("jwt".into());
+        let bearer: &[u8] = b"Bearer abc";
+        let basic: &[u8] = b"Basic dXNlcjpwYXNz";
+        let cookie: &[u8] = b"a=b; jwt=def";
+        assert_eq!(request_token(&cfg,
+            &[("Cookie", cookie), ("authorization", bearer)]),
+            Some("abc".into()));
+        assert_eq!(request_token(&cfg,
+            &[("Authorization", basic), ("Cookie", cookie)]),
+            Some("def".into()));
+        assert_eq!(request_token(&config(),
+            &[("Authorization", basic), ("Cookie", cookie)]),
+            None);
+    }
+
+    #[test]
+    fn invalid() {
+        let hs = r#"{"alg":"HS256"}"#;
+        assert_eq!(check(&token(hs, r#"{"aud":"swindon","exp":1000}"#)),
+            Err("expired"));
+        assert_eq!(check(&token(hs, r#"{"aud":"swindon","nbf":1001}"#)),
+            Err("not-yet-valid"));
+        assert_eq!(check(&token(hs, r#"{"aud":"other"}"#)),
+            Err("wrong-audience"));
+        let claims = r#"{"aud":"swindon"}"#;
+        assert_eq!(check(&token(r#"{"alg":"none"}"#, claims)),
+            Err("unsupported-algorithm"));
+        assert_eq!(check(&token(r#"{"alg":"RS256"}"#, claims)),
+            Err("unsupported-algorithm"));
+        let valid = token(hs, r#"{"aud":"swindon","sub":"john"}"#);
+        let parts = valid.split('.').collect::<Vec<_>>();
+        let tampered = format!("{}.{}.{}", parts[0],
+            b64(br#"{"aud":"swindon","sub":"root"}"#), parts[2]);
+        assert_eq!(check(&tampered), Err("bad-signature"));
+        assert_eq!(check("abc"), Err("malformed"));
+    }
+
+    #[test]
+    fn jwks() {
+        let keys = parse_jwks(br#"{"keys": [
+            {"kty": "RSA", "kid": "r", "n": "wQ", "e": "AQAB"},
+            {"kty": "EC", "crv": "P-256", "kid": "e",
+             "x": "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA",
+             "y": "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA"},
+            {"kty": "oct", "k": "c2VjcmV0"}
+        ]}"#).unwrap();
+        assert_eq!(keys.len(), 2);
+        match keys[0] {
+            (Some(ref kid), Key::Rsa(ref der)) => {
+                assert_eq!(kid, "r");
+                assert_eq!(der,
+                    b"\x30\x09\x02\x02\x00\xc1\x02\x03\x01\x00\x01");
+            }
+            _ => panic!("RSA key expected"),
+        }
+        match keys[1] {
+            (_, Key::Ec(ref point)) => assert_eq!(point.len(), 65),
+            _ => panic!("EC key expected"),
+        }
+    }
+
+    #[test]
+    fn ecdsa() {
+        let mut sig = vec![0u8; 64];
+        sig[31] = 1;
+        sig[32] = 0x80;
+        let der = ecdsa_signature(&sig).unwrap();
+        assert_eq!(&der[..5], b"\x30\x26\x02\x01\x01");
+        assert_eq!(&der[5..8], b"\x02\x21\x00");
+        assert!(ecdsa_signature(&sig[1..]).is_none());
+    }
+}
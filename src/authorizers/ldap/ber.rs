@@ -0,0 +1,574 @@
+//! Minimal BER encoding of LDAPv3 messages (RFC 4511)
+//!
+//! Only the operations needed by the authorizer are supported: simple bind,
+//! search and unbind.
+use std::str;
+
+
+const BOOLEAN: u8 = 0x01;
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const ENUMERATED: u8 = 0x0a;
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+
+const BIND_REQUEST: u8 = 0x60;
+const BIND_RESPONSE: u8 = 0x61;
+const UNBIND_REQUEST: u8 = 0x42;
+const SEARCH_REQUEST: u8 = 0x63;
+const SEARCH_RESULT_ENTRY: u8 = 0x64;
+const SEARCH_RESULT_DONE: u8 = 0x65;
+const SEARCH_RESULT_REFERENCE: u8 = 0x73;
+
+const SIMPLE_AUTH: u8 = 0x80;
+const FILTER_AND: u8 = 0xa0;
+const FILTER_OR: u8 = 0xa1;
+const FILTER_NOT: u8 = 0xa2;
+const FILTER_EQUAL: u8 = 0xa3;
+const FILTER_PRESENT: u8 = 0x87;
+
+const SCOPE_SUBTREE: i64 = 2;
+const NEVER_DEREF_ALIASES: i64 = 0;
+
+/// Result code of successful operation
+pub const SUCCESS: u32 = 0;
+/// Result code of bind with wrong password
+pub const INVALID_CREDENTIALS: u32 = 49;
+
+/// Maximum size of a single message we accept from server
+pub const MAX_MESSAGE_SIZE: usize = 1 << 20;
+
+
+quick_error! {
+    #[derive(Debug, PartialEq)]
+    pub enum Error {
+        Truncated {
+            display("message is truncated")
+        }
+        TooLong {
+            display("message is too long")
+        }
+        Unexpected(tag: u8) {
+            display("unexpected element with tag 0x{:02x}", tag)
+        }
+        BadFilter(msg: &'static str) {
+            display("bad filter: {}", msg)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Filter {
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+    Equal(String, Vec<u8>),
+    Present(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LdapResult {
+    pub code: u32,
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub dn: String,
+    pub attributes: Vec<(String, Vec<Vec<u8>>)>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Response {
+    Bind(LdapResult),
+    Entry(Entry),
+    Reference,
+    SearchDone(LdapResult),
+}
+
+struct Tlv<'a> {
+    tag: u8,
+    body: &'a [u8],
+}
+
+struct FilterParser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+fn put_length(buf: &mut Vec<u8>, len: usize) {
+    if len < 0x80 {
+        buf.push(len as u8);
+    } else {
+        let bytes = (len as u64).to_be_bytes();
+        let skip = bytes.iter().take_while(|&&b| b == 0).count();
+        buf.push(0x80 | (bytes.len() - skip) as u8);
+        buf.extend_from_slice(&bytes[skip..]);
+    }
+}
+
+fn tlv(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(body.len() + 6);
+    buf.push(tag);
+    put_length(&mut buf, body.len());
+    buf.extend_from_slice(body);
+    return buf;
+}
+
+fn integer(tag: u8, value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    // minimal two's complement representation
+    let mut start = 0;
+    while start < bytes.len() - 1 &&
+        (bytes[start] == 0x00 && bytes[start+1] & 0x80 == 0 ||
+         bytes[start] == 0xff && bytes[start+1] & 0x80 != 0)
+    {
+        start += 1;
+    }
+    tlv(tag, &bytes[start..])
+}
+
+fn message(id: i32, op: &[u8]) -> Vec<u8> {
+    let mut body = integer(INTEGER, id as i64);
+    body.extend_from_slice(op);
+    tlv(SEQUENCE, &body)
+}
+
+pub fn bind_request(id: i32, dn: &str, password: &str) -> Vec<u8> {
+    let mut body = integer(INTEGER, 3);
+    body.extend(tlv(OCTET_STRING, dn.as_bytes()));
+    body.extend(tlv(SIMPLE_AUTH, password.as_bytes()));
+    message(id, &tlv(BIND_REQUEST, &body))
+}
+
+pub fn search_request(id: i32, base: &str, filter: &Filter,
+    attributes: &[&str])
+    -> Vec<u8>
+{
+    let mut body = tlv(OCTET_STRING, base.as_bytes());
+    body.extend(integer(ENUMERATED, SCOPE_SUBTREE));
+    body.extend(integer(ENUMERATED, NEVER_DEREF_ALIASES));
+    body.extend(integer(INTEGER, 0));  // size limit
+    body.extend(integer(INTEGER, 0));  // time limit
+    body.extend(tlv(BOOLEAN, &[0]));  // types only
+    body.extend(filter.encode());
+    let attrs = attributes.iter()
+        .flat_map(|a| tlv(OCTET_STRING, a.as_bytes()))
+        .collect::<Vec<_>>();
+    body.extend(tlv(SEQUENCE, &attrs));
+    message(id, &tlv(SEARCH_REQUEST, &body))
+}
+
+pub fn unbind_request(id: i32) -> Vec<u8> {
+    message(id, &tlv(UNBIND_REQUEST, &[]))
+}
+
+/// Returns length of the message by its header (tag and length bytes)
+///
+/// Returns `Ok(None)` if more header bytes are needed.
+pub fn message_length(header: &[u8]) -> Result<Option<usize>, Error> {
+    match read_header(header) {
+        Ok((_, hlen, blen)) => {
+            if hlen + blen > MAX_MESSAGE_SIZE {
+                return Err(Error::TooLong);
+            }
+            Ok(Some(hlen + blen))
+        }
+        Err(Error::Truncated) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns tag, length of the header and length of the body
+fn read_header(data: &[u8]) -> Result<(u8, usize, usize), Error> {
+    if data.len() < 2 {
+        return Err(Error::Truncated);
+    }
+    let first = data[1] as usize;
+    if first < 0x80 {
+        return Ok((data[0], 2, first));
+    }
+    let nbytes = first & 0x7f;
+    if nbytes == 0 || nbytes > 4 {
+        return Err(Error::TooLong);
+    }
+    if data.len() < 2 + nbytes {
+        return Err(Error::Truncated);
+    }
+    let len = data[2..2+nbytes].iter()
+        .fold(0usize, |acc, &b| acc << 8 | b as usize);
+    Ok((data[0], 2 + nbytes, len))
+}
+
+fn read_tlv(data: &[u8]) -> Result<(Tlv<'_>, &[u8]), Error> {
+    let (tag, hlen, blen) = read_header(data)?;
+    if data.len() < hlen + blen {
+        return Err(Error::Truncated);
+    }
+    Ok((Tlv { tag, body: &data[hlen..hlen+blen] },
+        &data[hlen+blen..]))
+}
+
+fn expect(data: &[u8], tag: u8) -> Result<(&[u8], &[u8]), Error> {
+    let (item, rest) = read_tlv(data)?;
+    if item.tag != tag {
+        return Err(Error::Unexpected(item.tag));
+    }
+    Ok((item.body, rest))
+}
+
+fn parse_integer(body: &[u8]) -> Result<i64, Error> {
+    if body.len() == 0 || body.len() > 8 {
+        return Err(Error::Truncated);
+    }
+    let init = if body[0] & 0x80 != 0 { -1i64 } else { 0 };
+    Ok(body.iter().fold(init, |acc, &b| acc << 8 | b as i64))
+}
+
+fn parse_result(body: &[u8]) -> Result<LdapResult, Error> {
+    let (code, rest) = expect(body, ENUMERATED)?;
+    let (_matched_dn, rest) = expect(rest, OCTET_STRING)?;
+    let (message, _referral) = expect(rest, OCTET_STRING)?;
+    Ok(LdapResult {
+        code: parse_integer(code)? as u32,
+        message: String::from_utf8_lossy(message).into_owned(),
+    })
+}
+
+fn parse_entry(body: &[u8]) -> Result<Entry, Error> {
+    let (dn, rest) = expect(body, OCTET_STRING)?;
+    let (mut attrs, _) = expect(rest, SEQUENCE)?;
+    let mut attributes = Vec::new();
+    while attrs.len() > 0 {
+        let (attr, rest) = expect(attrs, SEQUENCE)?;
+        attrs = rest;
+        let (name, rest) = expect(attr, OCTET_STRING)?;
+        let (mut vals, _) = expect(rest, SET)?;
+        let mut values = Vec::new();
+        while vals.len() > 0 {
+            let (val, rest) = expect(vals, OCTET_STRING)?;
+            vals = rest;
+            values.push(val.to_vec());
+        }
+        attributes.push((String::from_utf8_lossy(name).into_owned(),
+                         values));
+    }
+    Ok(Entry {
+        dn: String::from_utf8_lossy(dn).into_owned(),
+        attributes: attributes,
+    })
+}
+
+/// Parses a message received from the server, returns message id and
+/// the response
+pub fn parse_message(data: &[u8]) -> Result<(i32, Response), Error> {
+    let (body, _) = expect(data, SEQUENCE)?;
+    let (id, rest) = expect(body, INTEGER)?;
+    let id = parse_integer(id)? as i32;
+    let (op, _controls) = read_tlv(rest)?;
+    let response = match op.tag {
+        BIND_RESPONSE => Response::Bind(parse_result(op.body)?),
+        SEARCH_RESULT_ENTRY => Response::Entry(parse_entry(op.body)?),
+        SEARCH_RESULT_REFERENCE => Response::Reference,
+        SEARCH_RESULT_DONE => Response::SearchDone(parse_result(op.body)?),
+        tag => return Err(Error::Unexpected(tag)),
+    };
+    Ok((id, response))
+}
+
+/// Escapes value to be inserted into a filter string (RFC 4515)
+pub fn escape_filter_value(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '*' => result.push_str("\\2a"),
+            '(' => result.push_str("\\28"),
+            ')' => result.push_str("\\29"),
+            '\\' => result.push_str("\\5c"),
+            '\0' => result.push_str("\\00"),
+            c => result.push(c),
+        }
+    }
+    return result;
+}
+
+impl Filter {
+    pub fn equal(attribute: &str, value: &str) -> Filter {
+        Filter::Equal(attribute.to_string(), value.as_bytes().to_vec())
+    }
+    /// Parses filter string (RFC 4515)
+    ///
+    /// Only presence and equality matches and their combinations are
+    /// supported. Parenthesis around the top level item are optional.
+    pub fn parse(value: &str) -> Result<Filter, Error> {
+        let value = value.trim();
+        let value = if value.starts_with("(") {
+            value.to_string()
+        } else {
+            format!("({})", value)
+        };
+        let mut parser = FilterParser { data: value.as_bytes(), pos: 0 };
+        let filter = parser.filter()?;
+        if parser.pos != parser.data.len() {
+            return Err(Error::BadFilter("garbage after filter"));
+        }
+        Ok(filter)
+    }
+    fn encode(&self) -> Vec<u8> {
+        match *self {
+            Filter::And(ref items) => {
+                let body = items.iter()
+                    .flat_map(|f| f.encode()).collect::<Vec<_>>();
+                tlv(FILTER_AND, &body)
+            }
+            Filter::Or(ref items) => {
+                let body = items.iter()
+                    .flat_map(|f| f.encode()).collect::<Vec<_>>();
+                tlv(FILTER_OR, &body)
+            }
+            Filter::Not(ref item) => tlv(FILTER_NOT, &item.encode()),
+            Filter::Equal(ref attr, ref value) => {
+                let mut body = tlv(OCTET_STRING, attr.as_bytes());
+                body.extend(tlv(OCTET_STRING, value));
+                tlv(FILTER_EQUAL, &body)
+            }
+            Filter::Present(ref attr) => tlv(FILTER_PRESENT, attr.as_bytes()),
+        }
+    }
+}
+
+impl<'a> FilterParser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).cloned()
+    }
+    fn consume(&mut self, c: u8) -> Result<(), Error> {
+        if self.peek() != Some(c) {
+            return Err(Error::BadFilter("unbalanced parenthesis"));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+    fn filter(&mut self) -> Result<Filter, Error> {
+        self.consume(b'(')?;
+        let result = match self.peek() {
+            Some(b'&') => { self.pos += 1; Filter::And(self.list()?) }
+            Some(b'|') => { self.pos += 1; Filter::Or(self.list()?) }
+            Some(b'!') => {
+                self.pos += 1;
+                Filter::Not(Box::new(self.filter()?))
+            }
+            _ => self.item()?,
+        };
+        self.consume(b')')?;
+        Ok(result)
+    }
+    fn list(&mut self) -> Result<Vec<Filter>, Error> {
+        let mut items = Vec::new();
+        while self.peek() == Some(b'(') {
+            items.push(self.filter()?);
+        }
+        if items.len() == 0 {
+            return Err(Error::BadFilter("empty filter list"));
+        }
+        Ok(items)
+    }
+    fn item(&mut self) -> Result<Filter, Error> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            match c {
+                b'=' => break,
+                b'~' | b'>' | b'<' | b':' => {
+                    return Err(Error::BadFilter("only equality match \
+                                                 is supported"));
+                }
+                b'(' | b')' => {
+                    return Err(Error::BadFilter("no value"));
+                }
+                _ => self.pos += 1,
+            }
+        }
+        let attr = str::from_utf8(&self.data[start..self.pos])
+            .map_err(|_| Error::BadFilter("bad attribute"))?
+            .to_string();
+        if attr.len() == 0 {
+            return Err(Error::BadFilter("no attribute"));
+        }
+        self.consume(b'=')?;
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c == b')' {
+                break;
+            }
+            self.pos += 1;
+        }
+        let raw = &self.data[start..self.pos];
+        if raw == b"*" {
+            return Ok(Filter::Present(attr));
+        }
+        if raw.contains(&b'*') {
+            return Err(Error::BadFilter("substring match is not supported"));
+        }
+        Ok(Filter::Equal(attr, unescape(raw)?))
+    }
+}
+
+fn unescape(raw: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut result = Vec::with_capacity(raw.len());
+    let mut iter = raw.iter();
+    while let Some(&c) = iter.next() {
+        if c == b'\\' {
+            let hex = match (iter.next(), iter.next()) {
+                (Some(&a), Some(&b)) => [a, b],
+                _ => return Err(Error::BadFilter("bad escape")),
+            };
+            let hex = str::from_utf8(&hex)
+                .map_err(|_| Error::BadFilter("bad escape"))?;
+            result.push(u8::from_str_radix(hex, 16)
+                .map_err(|_| Error::BadFilter("bad escape"))?);
+        } else {
+            result.push(c);
+        }
+    }
+    Ok(result)
+}
+
+/// Encoders of server responses, used by the LDAP server stand-in in tests
+#[cfg(test)]
+pub mod responses {
+    use super::*;
+
+    fn result(tag: u8, id: i32, code: u32) -> Vec<u8> {
+        let body = [
+            integer(ENUMERATED, code as i64),
+            tlv(OCTET_STRING, b""),
+            tlv(OCTET_STRING, b""),
+        ].concat();
+        message(id, &tlv(tag, &body))
+    }
+
+    pub fn bind(id: i32, code: u32) -> Vec<u8> {
+        result(BIND_RESPONSE, id, code)
+    }
+
+    pub fn search_done(id: i32, code: u32) -> Vec<u8> {
+        result(SEARCH_RESULT_DONE, id, code)
+    }
+
+    pub fn entry(id: i32, dn: &str, attributes: &[(&str, &[&str])])
+        -> Vec<u8>
+    {
+        let attributes = attributes.iter().map(|&(name, values)| {
+            let values = values.iter()
+                .map(|v| tlv(OCTET_STRING, v.as_bytes()))
+                .collect::<Vec<_>>().concat();
+            tlv(SEQUENCE, &[
+                tlv(OCTET_STRING, name.as_bytes()),
+                tlv(SET, &values),
+            ].concat())
+        }).collect::<Vec<_>>().concat();
+        let body = [
+            tlv(OCTET_STRING, dn.as_bytes()),
+            tlv(SEQUENCE, &attributes),
+        ].concat();
+        message(id, &tlv(SEARCH_RESULT_ENTRY, &body))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bind() {
+        assert_eq!(bind_request(1, "cn=x", "pw"), b"\
+            \x30\x12\x02\x01\x01\
+            \x60\x0d\x02\x01\x03\x04\x04cn=x\x80\x02pw".to_vec());
+    }
+
+    #[test]
+    fn unbind() {
+        assert_eq!(unbind_request(5), b"\x30\x05\x02\x01\x05\x42\x00");
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(integer(INTEGER, 0), b"\x02\x01\x00");
+        assert_eq!(integer(INTEGER, 127), b"\x02\x01\x7f");
+        assert_eq!(integer(INTEGER, 128), b"\x02\x02\x00\x80");
+        assert_eq!(integer(INTEGER, -1), b"\x02\x01\xff");
+        assert_eq!(integer(INTEGER, 256), b"\x02\x02\x01\x00");
+        assert_eq!(parse_integer(b"\x00\x80").unwrap(), 128);
+        assert_eq!(parse_integer(b"\xff").unwrap(), -1);
+    }
+
+    #[test]
+    fn long_length() {
+        let data = vec![b'x'; 300];
+        let encoded = tlv(OCTET_STRING, &data);
+        assert_eq!(&encoded[..4], b"\x04\x82\x01\x2c");
+        assert_eq!(message_length(&encoded[..2]).unwrap(), None);
+        assert_eq!(message_length(&encoded[..4]).unwrap(), Some(304));
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!(Filter::parse("uid=john").unwrap(),
+            Filter::equal("uid", "john"));
+        assert_eq!(Filter::parse("(uid=*)").unwrap(),
+            Filter::Present("uid".into()));
+        assert_eq!(Filter::parse("(&(a=1)(!(b=2))(|(c=3)(d=\\2a)))")
+            .unwrap(),
+            Filter::And(vec![
+                Filter::equal("a", "1"),
+                Filter::Not(Box::new(Filter::equal("b", "2"))),
+                Filter::Or(vec![
+                    Filter::equal("c", "3"),
+                    Filter::equal("d", "*"),
+                ]),
+            ]));
+        assert!(Filter::parse("(uid=jo*)").is_err());
+        assert!(Filter::parse("(uid>=1)").is_err());
+        assert!(Filter::parse("(&(a=1)").is_err());
+        assert!(Filter::parse("(a=1))").is_err());
+    }
+
+    #[test]
+    fn escape() {
+        let value = escape_filter_value("cn=a*(b)\\c");
+        assert_eq!(value, "cn=a\\2a\\28b\\29\\5cc");
+        assert_eq!(Filter::parse(&format!("member={}", value)).unwrap(),
+            Filter::equal("member", "cn=a*(b)\\c"));
+    }
+
+    #[test]
+    fn search_entry() {
+        let attr = [
+            tlv(OCTET_STRING, b"cn"),
+            tlv(SET, &[tlv(OCTET_STRING, b"a"), tlv(OCTET_STRING, b"b")]
+                .concat()),
+        ].concat();
+        let entry = [
+            tlv(OCTET_STRING, b"uid=john,dc=example"),
+            tlv(SEQUENCE, &tlv(SEQUENCE, &attr)),
+        ].concat();
+        let msg = message(2, &tlv(SEARCH_RESULT_ENTRY, &entry));
+        assert_eq!(parse_message(&msg).unwrap(), (2, Response::Entry(Entry {
+            dn: "uid=john,dc=example".into(),
+            attributes: vec![
+                ("cn".into(), vec![b"a".to_vec(), b"b".to_vec()]),
+            ],
+        })));
+    }
+
+    #[test]
+    fn bind_response() {
+        let result = [
+            integer(ENUMERATED, 49),
+            tlv(OCTET_STRING, b""),
+            tlv(OCTET_STRING, b"invalid"),
+        ].concat();
+        let msg = message(1, &tlv(BIND_RESPONSE, &result));
+        assert_eq!(parse_message(&msg).unwrap(), (1, Response::Bind(
+            LdapResult { code: 49, message: "invalid".into() })));
+    }
+}
@@ -0,0 +1,168 @@
+//! Minimal asynchronous LDAP client
+//!
+//! Only the operations needed by the authorizer are supported: simple bind
+//! and search. Requests are issued one by one over a single connection.
+use std::io;
+
+use futures::Future;
+use futures::future::{Either, loop_fn, Loop, err};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::io::{read_exact, write_all};
+
+use super::ber::{self, Filter, Response, LdapResult, Entry};
+
+
+/// Limit of entries returned by a single search
+pub const MAX_ENTRIES: usize = 1000;
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        Io(err: io::Error) {
+            display("io error: {}", err)
+            from()
+        }
+        Protocol(err: ber::Error) {
+            display("protocol error: {}", err)
+            from()
+        }
+        UnexpectedResponse {
+            display("unexpected response")
+        }
+        TooManyEntries {
+            display("too many entries returned")
+        }
+        Ldap(code: u32, message: String) {
+            display("ldap error {}: {}", code, message)
+        }
+    }
+}
+
+pub type LdapFuture<T> = Box<dyn Future<Item=T, Error=Error>>;
+
+pub struct Connection<S> {
+    sock: S,
+    next_id: i32,
+}
+
+/// Reads a single LDAP message, returns it including tag and length
+pub fn read_message<S>(sock: S) -> LdapFuture<(S, Vec<u8>)>
+    where S: AsyncRead + 'static
+{
+    Box::new(read_exact(sock, vec![0u8; 2])
+        .map_err(Error::from)
+        .and_then(|(sock, mut header)| {
+            let extra = if header[1] & 0x80 != 0 {
+                (header[1] & 0x7f) as usize
+            } else {
+                0
+            };
+            if extra > 4 {
+                return Either::A(err(ber::Error::TooLong.into()));
+            }
+            Either::B(read_exact(sock, vec![0u8; extra])
+                .map_err(Error::from)
+                .and_then(move |(sock, bytes)| {
+                    header.extend(bytes);
+                    let total = match ber::message_length(&header) {
+                        Ok(Some(total)) => total,
+                        Ok(None) => unreachable!(),
+                        Err(e) => return Either::A(err(e.into())),
+                    };
+                    let body = vec![0u8; total - header.len()];
+                    Either::B(read_exact(sock, body)
+                        .map_err(Error::from)
+                        .map(move |(sock, body)| {
+                            header.extend(body);
+                            (sock, header)
+                        }))
+                }))
+        }))
+}
+
+impl<S: AsyncRead + AsyncWrite + 'static> Connection<S> {
+    pub fn new(sock: S) -> Connection<S> {
+        Connection {
+            sock: sock,
+            next_id: 1,
+        }
+    }
+    /// Sends request and reads responses up to the final one
+    fn exchange(self, request: Vec<u8>) -> LdapFuture<(Self, Vec<Response>)>
+    {
+        let id = self.next_id;
+        Box::new(write_all(self.sock, request)
+            .map_err(Error::from)
+            .and_then(move |(sock, _)| {
+                loop_fn((sock, Vec::new()), move |(sock, mut responses)| {
+                    read_message(sock).and_then(move |(sock, data)| {
+                        let (msg_id, response) = ber::parse_message(&data)?;
+                        if msg_id != id {
+                            return Err(Error::UnexpectedResponse);
+                        }
+                        let done = match response {
+                            Response::Bind(..) => true,
+                            Response::SearchDone(..) => true,
+                            Response::Entry(..) => false,
+                            Response::Reference => false,
+                        };
+                        responses.push(response);
+                        if done {
+                            Ok(Loop::Break((sock, responses)))
+                        } else if responses.len() > MAX_ENTRIES {
+                            Err(Error::TooManyEntries)
+                        } else {
+                            Ok(Loop::Continue((sock, responses)))
+                        }
+                    })
+                })
+            })
+            .map(move |(sock, responses)| {
+                let conn = Connection { sock: sock, next_id: id + 1 };
+                (conn, responses)
+            }))
+    }
+    /// Simple bind, result code is returned as is
+    pub fn bind(self, dn: &str, password: &str)
+        -> LdapFuture<(Self, LdapResult)>
+    {
+        let request = ber::bind_request(self.next_id, dn, password);
+        Box::new(self.exchange(request)
+            .and_then(|(conn, mut responses)| match responses.pop() {
+                Some(Response::Bind(result)) => Ok((conn, result)),
+                _ => Err(Error::UnexpectedResponse),
+            }))
+    }
+    /// Subtree search, any non-successful result is an error
+    pub fn search(self, base: &str, filter: &Filter, attributes: &[&str])
+        -> LdapFuture<(Self, Vec<Entry>)>
+    {
+        let request = ber::search_request(self.next_id,
+            base, filter, attributes);
+        Box::new(self.exchange(request)
+            .and_then(|(conn, mut responses)| {
+                match responses.pop() {
+                    Some(Response::SearchDone(ref res))
+                    if res.code == ber::SUCCESS => {}
+                    Some(Response::SearchDone(res)) => {
+                        return Err(Error::Ldap(res.code, res.message));
+                    }
+                    _ => return Err(Error::UnexpectedResponse),
+                }
+                let entries = responses.into_iter()
+                    .filter_map(|r| match r {
+                        Response::Entry(entry) => Some(entry),
+                        _ => None,
+                    })
+                    .collect();
+                Ok((conn, entries))
+            }))
+    }
+    /// Tells server that we're done, there is no response to unbind
+    pub fn unbind(self) -> LdapFuture<()> {
+        let request = ber::unbind_request(self.next_id);
+        Box::new(write_all(self.sock, request)
+            .map(|_| ())
+            .map_err(Error::from))
+    }
+}
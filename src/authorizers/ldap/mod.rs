@@ -0,0 +1,371 @@
+//! LDAP authorizer
+//!
+//! Credentials are taken from the `Authorization: Basic` header. The user
+//! is looked up by `login-attribute` and password is checked by binding as
+//! the found entry. Then `additional-queries` are run and their results
+//! are forwarded to the backend as headers.
+mod ber;
+mod client;
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use blake2::Blake2b;
+use digest::FixedOutput;
+use digest_writer::Writer;
+use futures::Future;
+use futures::future::{Either, ok, err};
+use futures::stream::{iter_ok, Stream};
+use generic_array::GenericArray;
+use generic_array::typenum::U64;
+use rand::{thread_rng, Rng};
+use tk_http::server::Error;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Timeout;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use crate::authorizers::basic_credentials;
+use crate::config::ldap::Ldap;
+use crate::incoming::{Input, Check, Deny};
+use self::ber::{Filter, Entry};
+use self::client::{Connection, LdapFuture};
+
+
+/// Special attribute list meaning "no attributes" (RFC 4511)
+const NO_ATTRIBUTES: &str = "1.1";
+const DEFAULT_PORT: u16 = 389;
+
+type Headers = Vec<(String, String)>;
+type CacheKey = GenericArray<u8, U64>;
+
+struct Cached {
+    expires: Instant,
+    headers: Headers,
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<HashMap<CacheKey, Cached>> =
+        Mutex::new(HashMap::new());
+}
+
+fn cache_key(cfg: &Ldap, login: &str, password: &str) -> CacheKey {
+    let mut digest = Writer::new(Blake2b::default());
+    for part in &[&cfg.destination[..], &cfg.search_base,
+                  &cfg.login_attribute, login, password]
+    {
+        digest.write_all(part.as_bytes()).expect("write to digest");
+        digest.write_all(&[0]).expect("write to digest");
+    }
+    digest.into_inner().fixed_result()
+}
+
+fn cached(key: &CacheKey) -> Option<Headers> {
+    let cache = CACHE.lock().expect("ldap cache not poisoned");
+    cache.get(key)
+        .filter(|c| c.expires > Instant::now())
+        .map(|c| c.headers.clone())
+}
+
+fn store(key: CacheKey, headers: Headers, cfg: &Ldap) {
+    let now = Instant::now();
+    let mut cache = CACHE.lock().expect("ldap cache not poisoned");
+    cache.retain(|_, c| c.expires > now);
+    cache.insert(key, Cached {
+        expires: now + cfg.cache_ttl,
+        headers: headers,
+    });
+}
+
+pub fn check(cfg: &Arc<Ldap>, input: &mut Input) -> Result<Check, Error> {
+    let (login, password) = match basic_credentials(input) {
+        Some(pair) => pair,
+        None => {
+            input.debug.set_deny("ldap no-credentials");
+            return Ok(Check::Done(false));
+        }
+    };
+    if password.len() == 0 {
+        // Most servers treat bind with empty password as anonymous bind
+        input.debug.set_deny(format_args!("ldap empty-password {}", login));
+        return Ok(Check::Done(false));
+    }
+    let key = cache_key(cfg, &login, &password);
+    if let Some(headers) = cached(&key) {
+        input.debug.add_allow(format_args!("ldap {} (cached)", login));
+        input.identity.add(headers);
+        input.identity.set_attribute("login", login);
+        return Ok(Check::Done(true));
+    }
+    let dest = match input.config.ldap_destinations.get(&cfg.destination) {
+        Some(dest) => dest,
+        None => {
+            input.debug.set_deny(
+                format_args!("no-ldap-destination {}", cfg.destination));
+            return Ok(Check::Done(false));
+        }
+    };
+    let idx = thread_rng().gen_range(0, dest.addresses.len());
+    let name = &dest.addresses[idx];
+    let timeout = Timeout::new(dest.timeout, input.handle)
+        .expect("timeout created");
+    let handle = input.handle.clone();
+    let settings = cfg.clone();
+    let cache_cfg = cfg.clone();
+    let identity = input.identity.clone();
+    let user = login.clone();
+    let auth = input.runtime.resolver.resolve_auto(name, DEFAULT_PORT)
+        .map_err(|e| e.to_string())
+        .and_then(|addr| addr.pick_one().ok_or(format!("no address")))
+        .and_then(move |addr| {
+            TcpStream::connect(&addr, &handle)
+            .map_err(move |e| format!("{}: {}", addr, e))
+        })
+        .and_then(move |sock| {
+            authenticate(Connection::new(sock), settings, login, password)
+            .map_err(|e| e.to_string())
+        })
+        .select2(timeout)
+        .then(move |res| match res {
+            Ok(Either::A((Some(headers), _))) => {
+                store(key, headers.clone(), &cache_cfg);
+                identity.add(headers);
+                identity.set_attribute("login", user);
+                Ok(())
+            }
+            Ok(Either::A((None, _))) => {
+                Err(format!("ldap invalid-credentials {}", user))
+            }
+            Ok(Either::B(((), _))) => {
+                error!("LDAP authorization timed out");
+                Err(format!("ldap timeout"))
+            }
+            Err(Either::A((e, _))) => {
+                error!("LDAP authorization error: {}", e);
+                Err(format!("ldap error"))
+            }
+            Err(Either::B((e, _))) => Err(e.to_string()),
+        });
+    Ok(Check::Pending(Box::new(auth.map_err(Deny::from))))
+}
+
+/// Checks the password and fetches identity headers
+///
+/// Returns `None` if user is not found or password is wrong.
+fn authenticate<S>(conn: Connection<S>, cfg: Arc<Ldap>,
+    login: String, password: String)
+    -> LdapFuture<Option<Headers>>
+    where S: AsyncRead + AsyncWrite + 'static
+{
+    let filter = Filter::equal(&cfg.login_attribute, &login);
+    Box::new(conn.search(&cfg.search_base, &filter, &[NO_ATTRIBUTES])
+        .and_then(move |(conn, mut entries)| {
+            if entries.len() != 1 {
+                debug!("LDAP: {} entries found for {:?}",
+                    entries.len(), login);
+                return Either::A(ok(None));
+            }
+            let dn = entries.pop().expect("one entry").dn;
+            Either::B(conn.bind(&dn, &password)
+                .and_then(move |(conn, result)| match result.code {
+                    ber::SUCCESS => {
+                        Either::A(queries(conn, cfg, login, dn).map(Some))
+                    }
+                    ber::INVALID_CREDENTIALS => Either::B(ok(None)),
+                    code => Either::B(err(
+                        client::Error::Ldap(code, result.message))),
+                }))
+        }))
+}
+
+/// Runs additional queries as the authenticated user
+fn queries<S>(conn: Connection<S>, cfg: Arc<Ldap>, login: String,
+    dn: String)
+    -> LdapFuture<Headers>
+    where S: AsyncRead + AsyncWrite + 'static
+{
+    let mut headers = Vec::new();
+    if let Some(ref name) = cfg.login_header {
+        headers.push((name.clone(), login));
+    }
+    let mut names = cfg.additional_queries.keys()
+        .cloned().collect::<Vec<_>>();
+    names.sort();
+    let escaped_dn = ber::escape_filter_value(&dn);
+    Box::new(iter_ok::<_, client::Error>(names)
+        .fold((conn, headers), move |(conn, mut headers), name| {
+            let query = &cfg.additional_queries[&name];
+            let filter = query.filter.replace("${dn}", &escaped_dn);
+            let filter = match Filter::parse(&filter) {
+                Ok(filter) => filter,
+                Err(e) => return Either::A(err(e.into())),
+            };
+            let attr = query.fetch_attribute.clone();
+            let strip = query.dn_attribute_strip_base.clone();
+            let search = if attr.eq_ignore_ascii_case("dn") {
+                conn.search(&query.search_base, &filter, &[NO_ATTRIBUTES])
+            } else {
+                conn.search(&query.search_base, &filter, &[&attr[..]])
+            };
+            Either::B(search.map(move |(conn, entries)| {
+                let values = entries.into_iter()
+                    .flat_map(|entry| values(entry, &attr))
+                    .map(|value| strip_base(value, &strip))
+                    .collect::<Vec<_>>();
+                headers.push((name, values.join(", ")));
+                (conn, headers)
+            }))
+        })
+        .and_then(|(conn, headers)| conn.unbind().then(|_| Ok(headers))))
+}
+
+fn values(entry: Entry, attribute: &str) -> Vec<String> {
+    if attribute.eq_ignore_ascii_case("dn") {
+        return vec![entry.dn];
+    }
+    entry.attributes.into_iter()
+        .filter(|&(ref name, _)| name.eq_ignore_ascii_case(attribute))
+        .flat_map(|(_, values)| values)
+        .map(|v| String::from_utf8_lossy(&v).into_owned())
+        .collect()
+}
+
+fn strip_base(value: String, base: &Option<String>) -> String {
+    match *base {
+        Some(ref base) => {
+            let suffix = format!(",{}", base);
+            if value.len() > suffix.len() &&
+                value[value.len() - suffix.len()..]
+                    .eq_ignore_ascii_case(&suffix)
+            {
+                value[..value.len() - suffix.len()].to_string()
+            } else {
+                value
+            }
+        }
+        None => value,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures::{Future, Stream};
+    use futures::future::{Either, loop_fn, Loop, ok};
+    use tokio_core::net::{TcpListener, TcpStream};
+    use tokio_core::reactor::Core;
+    use tokio_io::io::write_all;
+
+    use crate::config::ldap::{Ldap, Query};
+    use crate::intern::LdapUpstream;
+    use super::ber::{self, Filter, responses};
+    use super::client::{Connection, Error, read_message};
+    use super::authenticate;
+
+    const USERS: &str = "ou=users,dc=example";
+    const JOHN: &str = "uid=john,ou=users,dc=example";
+    const GROUPS: &str = "ou=groups,dc=example";
+
+    fn config() -> Arc<Ldap> {
+        let mut queries = HashMap::new();
+        queries.insert("X-Groups".to_string(), Query {
+            search_base: GROUPS.to_string(),
+            fetch_attribute: "dn".to_string(),
+            filter: "(&(objectClass=group)(member=${dn}))".to_string(),
+            dn_attribute_strip_base: Some(GROUPS.to_string()),
+        });
+        Arc::new(Ldap {
+            destination: LdapUpstream::from("local"),
+            search_base: USERS.to_string(),
+            login_attribute: "uid".to_string(),
+            password_attribute: None,
+            login_header: Some("X-User".to_string()),
+            additional_queries: queries,
+            cache_ttl: Duration::new(60, 0),
+        })
+    }
+
+    /// Answers requests by comparing them to expected encodings
+    fn reply(request: &[u8]) -> Option<Vec<Vec<u8>>> {
+        let id = request[4] as i32;
+        let user = ber::search_request(id, USERS,
+            &Filter::equal("uid", "john"), &["1.1"]);
+        let groups = ber::search_request(id, GROUPS,
+            &Filter::parse(&format!("(&(objectClass=group)(member={}))",
+                ber::escape_filter_value(JOHN))).unwrap(),
+            &["1.1"]);
+        if request == &user[..] {
+            Some(vec![
+                responses::entry(id, JOHN, &[]),
+                responses::search_done(id, ber::SUCCESS),
+            ])
+        } else if request == &ber::bind_request(id, JOHN, "secret")[..] {
+            Some(vec![responses::bind(id, ber::SUCCESS)])
+        } else if request[5] == 0x60 {
+            Some(vec![responses::bind(id, ber::INVALID_CREDENTIALS)])
+        } else if request == &groups[..] {
+            Some(vec![
+                responses::entry(id, &format!("cn=admins,{}", GROUPS), &[]),
+                responses::entry(id, &format!("cn=staff,{}", GROUPS), &[]),
+                responses::search_done(id, ber::SUCCESS),
+            ])
+        } else if request[5] == 0x63 {
+            Some(vec![responses::search_done(id, ber::SUCCESS)])
+        } else {
+            // unbind
+            None
+        }
+    }
+
+    fn run(login: &str, password: &str) -> Option<Vec<(String, String)>> {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(),
+            &handle).unwrap();
+        let addr = listener.local_addr().unwrap();
+        handle.spawn(listener.incoming().take(1)
+            .for_each(|(sock, _)| {
+                loop_fn(sock, |sock| {
+                    read_message(sock).and_then(|(sock, request)| {
+                        match reply(&request) {
+                            Some(msgs) => Either::A(
+                                write_all(sock, msgs.concat())
+                                .map(|(sock, _)| Loop::Continue(sock))
+                                .map_err(Error::from)),
+                            None => Either::B(ok(Loop::Break(()))),
+                        }
+                    })
+                })
+                // client may close connection without unbind
+                .then(|_| Ok(()))
+            })
+            .map_err(|e| panic!("accept error: {}", e)));
+        let auth = TcpStream::connect(&addr, &handle)
+            .map_err(Error::from)
+            .and_then(|sock| authenticate(Connection::new(sock), config(),
+                login.to_string(), password.to_string()));
+        core.run(auth).unwrap()
+    }
+
+    #[test]
+    fn success() {
+        assert_eq!(run("john", "secret"), Some(vec![
+            ("X-User".to_string(), "john".to_string()),
+            ("X-Groups".to_string(), "cn=admins, cn=staff".to_string()),
+        ]));
+    }
+
+    #[test]
+    fn wrong_password() {
+        assert_eq!(run("john", "wrong"), None);
+    }
+
+    #[test]
+    fn unknown_user() {
+        assert_eq!(run("jane", "secret"), None);
+    }
+}
@@ -1 +1,36 @@
+use std::str::from_utf8;
+
+use crate::base64;
+use crate::incoming::Input;
+
+pub mod combine;
+pub mod htpasswd;
+pub mod jwt;
+pub mod ldap;
+pub mod signed_url;
 pub mod source_ip;
+pub mod subrequest;
+
+
+/// Returns login and password from the `Authorization: Basic` header
+pub fn basic_credentials(input: &Input) -> Option<(String, String)> {
+    let value = input.headers.headers()
+        .find(|&(name, _)| name.eq_ignore_ascii_case("Authorization"))
+        .and_then(|(_, value)| from_utf8(value).ok())?;
+    let mut parts = value.trim().splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(data)) if scheme.eq_ignore_ascii_case("Basic")
+        => {
+            let data = String::from_utf8(base64::decode(data.trim())?)
+                .ok()?;
+            let mut pair = data.splitn(2, ':');
+            match (pair.next(), pair.next()) {
+                (Some(login), Some(password)) => {
+                    Some((login.to_string(), password.to_string()))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,132 @@
+//! Authorizer checking expiring links signed by HMAC-SHA256
+//!
+//! Signature is `base64url(HMAC(secret, "<expires>\n<path>"))`, where path
+//! is the request path without query string and `expires` is a unix
+//! timestamp. Both are passed in query parameters.
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ring::{digest, hmac};
+use tk_http::server::Error;
+
+use crate::base64;
+use crate::config::signed_url::SignedUrl;
+use crate::incoming::{Input, Check};
+
+
+fn query_param<'x>(query: &'x str, name: &str) -> Option<&'x str> {
+    query.split('&')
+        .map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            (kv.next().unwrap_or(""), kv.next().unwrap_or(""))
+        })
+        .find(|&(key, _)| key == name)
+        .map(|(_, value)| value)
+}
+
+fn validate(cfg: &SignedUrl, target: &str, now: SystemTime)
+    -> Result<(), &'static str>
+{
+    let target = target.splitn(2, '#').next().unwrap_or("");
+    let mut parts = target.splitn(2, '?');
+    let path = parts.next().unwrap_or("");
+    let query = parts.next().unwrap_or("");
+    let signature = query_param(query, &cfg.signature_param)
+        .ok_or("no-signature")?;
+    let expires = query_param(query, &cfg.expires_param)
+        .ok_or("no-expires")?;
+    let signature = base64::decode_url(signature)
+        .ok_or("invalid-signature")?;
+    let deadline = expires.parse::<u64>().map_err(|_| "invalid-expires")?;
+    let now = now.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs()).unwrap_or(0);
+    if now >= deadline {
+        return Err("expired");
+    }
+    let message = format!("{}\n{}", expires, path);
+    for secret in &cfg.secrets {
+        let key = hmac::VerificationKey::new(&digest::SHA256,
+                                             secret.as_bytes());
+        if hmac::verify(&key, message.as_bytes(), &signature).is_ok() {
+            return Ok(());
+        }
+    }
+    Err("bad-signature")
+}
+
+pub fn check(cfg: &Arc<SignedUrl>, input: &mut Input)
+    -> Result<Check, Error>
+{
+    let target = input.headers.path().unwrap_or("/");
+    match validate(cfg, target, SystemTime::now()) {
+        Ok(()) => {
+            input.debug.add_allow("signed-url");
+            Ok(Check::Done(true))
+        }
+        Err(reason) => {
+            input.debug.set_deny(format_args!("signed-url {}", reason));
+            Ok(Check::Done(false))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use ring::{digest, hmac};
+
+    use crate::base64::Base64;
+    use crate::config::signed_url::SignedUrl;
+    use super::validate;
+
+    fn config() -> SignedUrl {
+        SignedUrl {
+            secrets: vec!["new".into(), "old".into()],
+            signature_param: "signature".into(),
+            expires_param: "expires".into(),
+        }
+    }
+
+    fn sign(secret: &str, path: &str, expires: u64) -> String {
+        let key = hmac::SigningKey::new(&digest::SHA256, secret.as_bytes());
+        let sig = hmac::sign(&key,
+            format!("{}\n{}", expires, path).as_bytes());
+        let sig = Base64(sig.as_ref()).to_string()
+            .replace('+', "-").replace('/', "_").replace('=', "");
+        format!("{}?expires={}&signature={}", path, expires, sig)
+    }
+
+    fn check(target: &str) -> Result<(), &'static str> {
+        validate(&config(), target, UNIX_EPOCH + Duration::new(1000, 0))
+    }
+
+    #[test]
+    fn valid() {
+        assert_eq!(check(&sign("new", "/files/a.zip", 1001)), Ok(()));
+        // secret which is being rotated out
+        assert_eq!(check(&sign("old", "/files/a.zip", 1001)), Ok(()));
+        assert_eq!(check(&format!("{}&x=1",
+            sign("new", "/files/a.zip", 1001))), Ok(()));
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(check(&sign("new", "/files/a.zip", 1000)),
+            Err("expired"));
+        assert_eq!(check(&sign("other", "/files/a.zip", 1001)),
+            Err("bad-signature"));
+        assert_eq!(check(&sign("new", "/files/a.zip", 1001)
+                         .replace("a.zip", "b.zip")),
+            Err("bad-signature"));
+        assert_eq!(check(&sign("new", "/files/a.zip", 1001)
+                         .replace("expires=1001", "expires=2000")),
+            Err("bad-signature"));
+        assert_eq!(check("/files/a.zip?expires=1001"),
+            Err("no-signature"));
+        assert_eq!(check("/files/a.zip?signature=abc"),
+            Err("no-expires"));
+        assert_eq!(check("/files/a.zip?expires=x&signature=abc"),
+            Err("invalid-expires"));
+    }
+}
@@ -1,20 +1,59 @@
 use std::str::from_utf8;
 use std::sync::Arc;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
 
 use tk_http::server::{Error};
 
 use crate::config::networks::SourceIpAuthorizer;
 use crate::incoming::Input;
+use crate::local_sockets::is_unix_peer;
 
 
+/// Parses comma-separated addresses of all occurrences of the header
+///
+/// Addresses are returned in order, so the rightmost one is appended by
+/// the nearest proxy.
+fn forwarded_chain(header: &str, input: &mut Input)
+    -> Option<Vec<IpAddr>>
+{
+    let mut chain = Vec::new();
+    for (name, value) in input.headers.headers() {
+        if !name.eq_ignore_ascii_case(header) {
+            continue;
+        }
+        let items = from_utf8(value).ok().map(|x| x.split(',')
+            .map(|item| item.trim())
+            .map(|item| item.parse::<IpAddr>().ok()
+                .or_else(|| item.parse::<SocketAddr>().ok()
+                                .map(|a| a.ip())))
+            .collect::<Option<Vec<_>>>());
+        match items {
+            Some(Some(items)) => chain.extend(items),
+            _ => {
+                debug!("Invalid ip {:?} from header {}",
+                    String::from_utf8_lossy(value), name);
+                return None;
+            }
+        }
+    }
+    Some(chain)
+}
+
 pub fn check(cfg: &Arc<SourceIpAuthorizer>, input: &mut Input)
     -> Result<bool, Error>
 {
-    let forwarded = cfg.accept_forwarded_headers_from.as_ref()
-        .and_then(|netw| input.config.networks.get(netw))
+    // Peers of unix sockets have no address, access to them is limited by
+    // permissions of the socket file, so they are trusted to forward
+    // headers as long as some network is trusted
+    let unix = is_unix_peer(&input.addr);
+    let trusted = cfg.accept_forwarded_headers_from.as_ref()
+        .and_then(|netw| input.config.networks.get(netw));
+    let forwarded = trusted
         .map(|netw| {
-            if let Some(subnet) = netw.get_subnet(input.addr.ip()) {
+            if unix {
+                input.debug.add_allow("forwarded-from unix");
+                true
+            } else if let Some(subnet) = netw.get_subnet(input.addr.ip()) {
                 input.debug.add_allow(
                     format_args!("forwarded-from {}", subnet));
                 true
@@ -23,33 +62,47 @@ pub fn check(cfg: &Arc<SourceIpAuthorizer>, input: &mut Input)
             }
         })
         .unwrap_or(false);
-    let ip = match (&cfg.forwarded_ip_header, forwarded) {
-        (&Some(ref header), true) => {
-            let mut ip = None;
-            for (name, value) in input.headers.headers() {
-                if name.eq_ignore_ascii_case(header) {
-                    let parsed = from_utf8(value).ok()
-                          .and_then(|x| x.parse::<IpAddr>().ok());
-                    match parsed {
-                        Some(parsed) => ip = Some(parsed),
-                        None => {
-                            debug!("Invalid ip {:?} from header {}",
-                                String::from_utf8_lossy(value), name);
-                            input.debug.set_deny(
-                                "invalid-source-ip-from-header");
-                            // TODO(tailhook) consider returning error
-                            return Ok(false);
-                        }
+    let ip = match (&cfg.forwarded_ip_header, trusted, forwarded) {
+        (&Some(ref header), Some(trusted), true) => {
+            let chain = match forwarded_chain(header, input) {
+                Some(chain) => chain,
+                None => {
+                    input.debug.set_deny("invalid-source-ip-from-header");
+                    // TODO(tailhook) consider returning error
+                    return Ok(false);
+                }
+            };
+            // Walk from the nearest hop, skipping our own proxies. If all
+            // of them are trusted, the leftmost one is the client.
+            let mut ip = if unix { None } else { Some(input.addr.ip()) };
+            for &hop in chain.iter().rev() {
+                ip = Some(hop);
+                match trusted.get_subnet(hop) {
+                    Some(subnet) => {
+                        input.debug.add_allow(
+                            format_args!("forwarded-from {}", subnet));
                     }
+                    None => break,
                 }
             }
-            ip.unwrap_or(input.addr.ip())
+            ip
+        }
+        _ if unix => None,
+        _ => Some(input.addr.ip()),
+    };
+    let ip = match ip {
+        Some(ip) => ip,
+        None => {
+            input.debug.set_deny("unix-socket");
+            return Ok(false);
         }
-        _ => input.addr.ip(),
     };
     if let Some(netw) = input.config.networks.get(&cfg.allowed_network) {
         if let Some(subnet) = netw.get_subnet(ip) {
             input.debug.add_allow(format_args!("source-ip {}", subnet));
+            input.identity.set_attribute("network",
+                cfg.allowed_network.to_string());
+            input.identity.set_attribute("source-ip", ip.to_string());
             Ok(true)
         } else {
             input.debug.set_deny(format!("source-ip {}", ip));
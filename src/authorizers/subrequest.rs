@@ -0,0 +1,181 @@
+//! Authorizer delegating the decision to an HTTP backend
+//!
+//! Request headers are sent to the configured destination (without the
+//! request body). 2xx response allows the request, 401 and 403 deny it.
+//! Selected response headers are forwarded by `!Proxy`.
+use std::sync::Arc;
+
+use futures::{AsyncSink, Async, Future};
+use futures::future::{Either, ok};
+use futures::sync::oneshot;
+use tk_http::Version;
+use tk_http::client as http;
+use tk_http::server::{Error, RequestTarget};
+use tokio_core::reactor::Timeout;
+
+use crate::config::subrequest::Subrequest;
+use crate::http_pools::{HttpFuture, REQUESTS, FAILED_503};
+use crate::incoming::{Input, Check, Deny};
+use crate::local_sockets::PeerIp;
+
+
+/// Result of the subrequest: headers to forward or the deny reason
+type Decision = Result<Vec<(String, String)>, String>;
+
+pub struct SubrequestCodec {
+    path: String,
+    host: Option<String>,
+    headers: Vec<(String, Vec<u8>)>,
+    copy_headers: Vec<String>,
+    sender: Option<oneshot::Sender<Decision>>,
+}
+
+/// Headers that describe the request body or the connection
+fn is_skipped_header(name: &str) -> bool {
+    name.eq_ignore_ascii_case("Host") ||
+    name.eq_ignore_ascii_case("Content-Length") ||
+    name.eq_ignore_ascii_case("Transfer-Encoding") ||
+    name.eq_ignore_ascii_case("Connection") ||
+    name.eq_ignore_ascii_case("Upgrade") ||
+    name.eq_ignore_ascii_case("Expect")
+}
+
+impl<S: 'static> http::Codec<S> for SubrequestCodec {
+    type Future = HttpFuture<S>;
+    fn start_write(&mut self, mut e: http::Encoder<S>) -> Self::Future {
+        e.request_line("GET", &self.path, Version::Http11);
+        if let Some(ref host) = self.host {
+            e.add_header("Host", host).unwrap();
+        }
+        for &(ref name, ref value) in &self.headers {
+            e.add_header(name, value).unwrap();
+        }
+        e.done_headers().unwrap();
+        Box::new(ok(e.done()))
+    }
+    fn headers_received(&mut self, headers: &http::Head)
+        -> Result<http::RecvMode, http::Error>
+    {
+        let (code, _) = headers.raw_status();
+        let decision = match code {
+            200..=299 => {
+                let mut result = Vec::new();
+                for name in &self.copy_headers {
+                    for (k, v) in headers.headers() {
+                        if k.eq_ignore_ascii_case(name) {
+                            result.push((name.clone(),
+                                String::from_utf8_lossy(v).into_owned()));
+                        }
+                    }
+                }
+                Ok(result)
+            }
+            401 | 403 => Err(format!("subrequest {}", code)),
+            _ => {
+                error!("Authorization subrequest to {:?} returned {}",
+                    self.path, code);
+                Err(format!("subrequest {}", code))
+            }
+        };
+        self.sender.take().map(|tx| tx.send(decision).ok());
+        // response body is ignored
+        Ok(http::RecvMode::progressive(1))
+    }
+    fn data_received(&mut self, data: &[u8], _end: bool)
+        -> Result<Async<usize>, http::Error>
+    {
+        Ok(Async::Ready(data.len()))
+    }
+}
+
+fn request_path(cfg: &Subrequest, input: &Input) -> String {
+    // Only the path of the original request is forwarded, like for !Proxy
+    let target = match *input.headers.request_target() {
+        RequestTarget::Origin(path) => path,
+        RequestTarget::Absolute { path, .. } => path,
+        _ => "/",
+    };
+    if cfg.destination.path == "/" {
+        target.to_string()
+    } else {
+        format!("{}{}", cfg.destination.path.trim_end_matches('/'), target)
+    }
+}
+
+pub fn check(cfg: &Arc<Subrequest>, input: &mut Input)
+    -> Result<Check, Error>
+{
+    let upstream = &cfg.destination.upstream;
+    let dest = match input.config.http_destinations.get(upstream) {
+        Some(dest) => dest,
+        None => {
+            input.debug.set_deny(
+                format_args!("no-http-destination {}", upstream));
+            return Ok(Check::Done(false));
+        }
+    };
+    let mut headers = input.headers.headers()
+        .filter(|&(name, _)| !is_skipped_header(name))
+        .map(|(name, value)| (name.to_string(), value.to_vec()))
+        .collect::<Vec<_>>();
+    if let Some(ref name) = cfg.ip_header {
+        headers.retain(|&(ref k, _)| !k.eq_ignore_ascii_case(name));
+        headers.push((name.clone(),
+            PeerIp(input.addr).to_string().into_bytes()));
+    }
+    if let Some(ref name) = dest.request_id_header {
+        headers.push((name.clone(),
+            input.request_id.to_string().into_bytes()));
+    }
+    let (tx, rx) = oneshot::channel();
+    let codec = Box::new(SubrequestCodec {
+        path: request_path(cfg, input),
+        host: dest.override_host_header.clone()
+            .or_else(|| input.headers.host().map(|h| h.to_string())),
+        headers: headers,
+        copy_headers: cfg.copy_headers.clone(),
+        sender: Some(tx),
+    });
+    let mut up = input.runtime.http_pools.upstream(upstream);
+    let mut guard = up.get_mut();
+    match guard.get_mut() {
+        Some(pool) => match pool.start_send(codec) {
+            Ok(AsyncSink::Ready) => {
+                REQUESTS.incr(1);
+            }
+            Ok(AsyncSink::NotReady(())) => {
+                FAILED_503.incr(1);
+                input.debug.set_deny("subrequest queue-full");
+                return Ok(Check::Done(false));
+            }
+            Err(e) => {
+                error!("Error sending to pool {:?}: {}", upstream, e);
+                input.debug.set_deny("subrequest pool-error");
+                return Ok(Check::Done(false));
+            }
+        },
+        None => {
+            input.debug.set_deny(
+                format_args!("no-http-destination {}", upstream));
+            return Ok(Check::Done(false));
+        }
+    }
+    let timeout = Timeout::new(dest.max_request_timeout, input.handle)
+        .expect("timeout created");
+    let identity = input.identity.clone();
+    Ok(Check::Pending(Box::new(rx.select2(timeout)
+        .then(move |res| match res {
+            Ok(Either::A((Ok(headers), _))) => {
+                identity.add(headers);
+                Ok(())
+            }
+            Ok(Either::A((Err(reason), _))) => Err(reason),
+            Ok(Either::B(((), _))) => Err(format!("subrequest timeout")),
+            Err(Either::A((_canceled, _))) => {
+                // codec is dropped when backend connection fails
+                Err(format!("subrequest failed"))
+            }
+            Err(Either::B((e, _))) => Err(e.to_string()),
+        })
+        .map_err(Deny::from))))
+}
@@ -0,0 +1,276 @@
+//! Load balancing strategies other than a shared queue
+//!
+//! Each address gets its own connection pool and balancer chooses
+//! a pool for every request.
+use std::collections::BTreeMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::net::SocketAddr;
+
+use futures::Async;
+use futures::sync::oneshot::Sender;
+use tk_http::client::{Codec, Encoder, Head, RecvMode, Error};
+
+use crate::config::http_destinations::LoadBalancing;
+use crate::http_pools::PoolMetrics;
+use crate::intern::Upstream;
+use crate::metrics::{Counter, Integer, Registry, Reset, Shared};
+
+
+/// Number of points for each address on the consistent hashing ring
+const RING_POINTS: u32 = 100;
+
+lazy_static! {
+    static ref ADDRESS_METRICS: Registry<(Upstream, SocketAddr), AddrMetrics>
+        = Registry::new();
+}
+
+/// Metrics of the address, shared by the balancer, health checks and
+/// in-flight requests, so they are reused when all of them are gone
+pub type AddrMetricsRef = Shared<(Upstream, SocketAddr), AddrMetrics>;
+
+#[derive(Debug)]
+pub struct AddrMetrics {
+    pub requests: Counter,
+    pub in_flight: Integer,
+    /// 1 if address passes health checks (or they aren't configured)
+    pub healthy: Integer,
+    pub health_check_failures: Counter,
+}
+
+/// Sends request waiting for addresses to the pool of the chosen address
+pub type Pending<P> = Box<dyn FnOnce(&mut P, &AddrMetricsRef)>;
+
+struct Backend<P> {
+    pool: P,
+    metrics: AddrMetricsRef,
+}
+
+/// Chooses connection pool of a single address for each request
+pub struct Balancer<P> {
+    name: Upstream,
+    strategy: LoadBalancing,
+    backends: BTreeMap<SocketAddr, Backend<P>>,
+    ring: Vec<(u64, SocketAddr)>,
+    next: usize,
+    new_pool: Box<dyn Fn(SocketAddr) -> P>,
+    /// Shared by pools of all addresses, tracks requests queued in them
+    metrics: PoolMetrics,
+    /// Limit of requests queued for the whole destination
+    queue_size: usize,
+    /// Requests received before the first address is resolved
+    pending: Vec<(Option<u64>, Pending<P>)>,
+    /// Stops address resolution when balancer is dropped
+    #[allow(dead_code)]
+    shutdown: Sender<()>,
+}
+
+/// Codec wrapper that tracks number of in-flight requests to the address
+pub struct Tracked<C> {
+    codec: C,
+    metrics: AddrMetricsRef,
+}
+
+impl Reset for AddrMetrics {
+    fn reset(&self) {
+        self.in_flight.set(0);
+        self.healthy.set(1);
+    }
+}
+
+pub fn addr_metrics(name: &Upstream, addr: SocketAddr) -> AddrMetricsRef {
+    ADDRESS_METRICS.get(&(name.clone(), addr), || {
+        let healthy = Integer::new();
+        healthy.set(1);
+        AddrMetrics {
+            requests: Counter::new(),
+            in_flight: Integer::new(),
+            healthy: healthy,
+            health_check_failures: Counter::new(),
+        }
+    })
+}
+
+/// Returns metrics for addresses currently used by the destination
+pub fn address_metrics(name: &Upstream)
+    -> Vec<(SocketAddr, &'static AddrMetrics)>
+{
+    ADDRESS_METRICS.used().into_iter()
+        .filter(|&((ref n, _), _)| n == name)
+        .map(|((_, addr), m)| (addr, m))
+        .collect()
+}
+
+/// Hashes the key for `consistent_hash` load balancing
+pub fn hash<T: Hash>(value: T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl<P> Balancer<P> {
+    pub fn new(name: &Upstream, strategy: LoadBalancing,
+        new_pool: Box<dyn Fn(SocketAddr) -> P>, metrics: &PoolMetrics,
+        queue_size: usize, shutdown: Sender<()>)
+        -> Balancer<P>
+    {
+        Balancer {
+            name: name.clone(),
+            strategy,
+            backends: BTreeMap::new(),
+            ring: Vec::new(),
+            next: 0,
+            new_pool,
+            metrics: metrics.clone(),
+            queue_size,
+            pending: Vec::new(),
+            shutdown,
+        }
+    }
+    /// Starts pools for new addresses and stops pools of removed ones
+    pub fn set_addresses(&mut self, addresses: &[SocketAddr]) {
+        self.backends.retain(|addr, _| addresses.contains(addr));
+        for addr in addresses {
+            if !self.backends.contains_key(addr) {
+                debug!("{}: Adding address {}", self.name, addr);
+                self.backends.insert(*addr, Backend {
+                    pool: (self.new_pool)(*addr),
+                    metrics: addr_metrics(&self.name, *addr),
+                });
+            }
+        }
+        if self.strategy == LoadBalancing::consistent_hash {
+            self.ring = self.backends.keys()
+                .flat_map(|addr| {
+                    (0..RING_POINTS).map(move |i| (hash((addr, i)), *addr))
+                })
+                .collect();
+            self.ring.sort();
+        }
+        if !self.backends.is_empty() && !self.pending.is_empty() {
+            debug!("{}: Sending {} requests waiting for addresses",
+                self.name, self.pending.len());
+            for (key, send) in mem::replace(&mut self.pending, Vec::new()) {
+                if let Some((_, pool, metrics)) = self.pick(key, &[]) {
+                    send(pool, metrics);
+                }
+            }
+        }
+    }
+    fn round_robin(&mut self, exclude: &[SocketAddr]) -> Option<SocketAddr> {
+        let n = self.backends.len();
+        if n == 0 {
+            return None;
+        }
+        self.next = self.next.wrapping_add(1);
+        let start = self.next % n;
+        self.backends.keys().skip(start).chain(self.backends.keys())
+            .take(n)
+            .find(|addr| !exclude.contains(addr))
+            .cloned()
+    }
+    fn least_connections(&mut self, exclude: &[SocketAddr])
+        -> Option<SocketAddr>
+    {
+        // Start from the next address each time, so that addresses having
+        // equal number of connections are used in round robin fashion
+        let n = self.backends.len();
+        if n == 0 {
+            return None;
+        }
+        self.next = self.next.wrapping_add(1);
+        let start = self.next % n;
+        self.backends.iter().skip(start).chain(self.backends.iter())
+            .take(n)
+            .filter(|&(addr, _)| !exclude.contains(addr))
+            .min_by_key(|&(_, b)| b.metrics.in_flight.get())
+            .map(|(addr, _)| *addr)
+    }
+    /// Walks the ring from the key, so excluded address is replaced by
+    /// the next one on the ring
+    fn consistent_hash(&mut self, key: u64, exclude: &[SocketAddr])
+        -> Option<SocketAddr>
+    {
+        let n = self.ring.len();
+        let idx = match self.ring.binary_search_by_key(&key, |&(h, _)| h) {
+            Ok(idx) | Err(idx) => idx,
+        };
+        (0..n).map(|i| self.ring[(idx + i) % n].1)
+            .find(|addr| !exclude.contains(addr))
+    }
+    /// Returns true if requests queued in pools of all addresses, plus
+    /// ones waiting for addresses, reach `queue-size-for-503`
+    pub fn queue_full(&self) -> bool {
+        self.pending.len() + self.metrics.queued() >= self.queue_size
+    }
+    /// Keeps request until the first address is resolved
+    ///
+    /// Returns false if some addresses are known already, so request
+    /// can't be sent only because of `exclude`.
+    pub fn wait_for_address(&mut self, key: Option<u64>, send: Pending<P>)
+        -> bool
+    {
+        if !self.backends.is_empty() {
+            return false;
+        }
+        self.pending.push((key, send));
+        true
+    }
+    /// Returns true if there is an address not listed in `exclude`
+    pub fn has_address_except(&self, exclude: &[SocketAddr]) -> bool {
+        self.backends.keys().any(|addr| !exclude.contains(addr))
+    }
+    /// Returns pool to send request to, skipping addresses in `exclude`
+    ///
+    /// If `consistent_hash` is used but request has no key, round robin
+    /// is used for the request.
+    pub fn pick(&mut self, key: Option<u64>, exclude: &[SocketAddr])
+        -> Option<(SocketAddr, &mut P, &AddrMetricsRef)>
+    {
+        use crate::config::http_destinations::LoadBalancing::*;
+        let addr = match (self.strategy, key) {
+            (consistent_hash, Some(key)) => self.consistent_hash(key, exclude),
+            (least_connections, _) => self.least_connections(exclude),
+            _ => self.round_robin(exclude),
+        };
+        match addr {
+            Some(addr) => self.backends.get_mut(&addr)
+                .map(|b| (addr, &mut b.pool, &b.metrics)),
+            None => None,
+        }
+    }
+}
+
+impl<C> Tracked<C> {
+    pub fn new(codec: Box<C>, metrics: &AddrMetricsRef)
+        -> Box<Tracked<C>>
+    {
+        metrics.requests.incr(1);
+        metrics.in_flight.incr(1);
+        Box::new(Tracked { codec: *codec, metrics: metrics.clone() })
+    }
+}
+
+impl<C> Drop for Tracked<C> {
+    fn drop(&mut self) {
+        self.metrics.in_flight.decr(1);
+    }
+}
+
+impl<S, C: Codec<S>> Codec<S> for Tracked<C> {
+    type Future = C::Future;
+    fn start_write(&mut self, e: Encoder<S>) -> Self::Future {
+        self.codec.start_write(e)
+    }
+    fn headers_received(&mut self, headers: &Head)
+        -> Result<RecvMode, Error>
+    {
+        self.codec.headers_received(headers)
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        self.codec.data_received(data, end)
+    }
+}
@@ -68,3 +68,36 @@ impl<'a> fmt::Display for Base64<'a> {
         Ok(())
     }
 }
+
+/// Decodes base64 with the standard alphabet, padding is optional
+pub fn decode(data: &str) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(data.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for &c in data.trim_end_matches('=').as_bytes() {
+        let val = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        acc = acc << 6 | val as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Some(result)
+}
+
+/// Decodes base64 with the URL-safe alphabet (used by JWT)
+pub fn decode_url(data: &str) -> Option<Vec<u8>> {
+    if data.contains(|c| c == '+' || c == '/') {
+        return None;
+    }
+    decode(&data.replace('-', "+").replace('_', "/"))
+}
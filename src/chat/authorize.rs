@@ -3,7 +3,6 @@ use std::sync::Arc;
 use std::borrow::Cow;
 
 use futures::{AsyncSink};
-use futures::sink::Sink;
 use tk_http::Status;
 use tk_http::server::Head;
 use serde_json::{self, Value as Json};
@@ -4,7 +4,6 @@ use std::cmp;
 
 use futures::AsyncSink;
 use futures::future::{FutureResult, ok, err};
-use futures::sink::{Sink};
 use tk_http::websocket;
 use tk_http::websocket::{Error as WsError};
 use tk_http::websocket::Frame::{self, Text, Binary, Ping, Pong, Close};
@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use futures::Future;
 use futures::stream::{Stream};
-use futures::{AsyncSink, Sink};
+use futures::{AsyncSink};
 use futures::sync::oneshot::{channel as oneshot, Sender};
 use futures::sync::mpsc::{UnboundedReceiver as Receiver};
 use tokio_core::reactor::Handle;
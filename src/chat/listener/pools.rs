@@ -9,13 +9,14 @@ use tokio_core::reactor::Handle;
 
 use crate::runtime::Runtime;
 use crate::intern::SessionPoolName;
-use crate::chat::listener::spawn::{listen, WorkerData};
+use crate::chat::listener::spawn::{listen, WorkerData, WorkerAcceptor};
 use crate::chat::inactivity_handler;
 use crate::chat::processor::{Processor};
 use crate::chat::Shutdown;
 use crate::chat::replication::RemoteSender;
 use crate::config::listen::Listen;
 use crate::config::{SessionPool};
+use crate::local_sockets::LocalListeners;
 use void::Void;
 
 
@@ -28,6 +29,7 @@ pub struct SessionPools {
 
 struct Worker {
     listener_channel: slot::Sender<Listen>,
+    local_listeners: LocalListeners<WorkerAcceptor>,
     inactivity_shutter: Sender<Shutdown>,
 }
 
@@ -63,11 +65,14 @@ impl SessionPools {
 
         // Create new pools
         for (name, settings) in cfg {
-            if let Some(ref mut pool) = pools.get(name) {
+            if let Some(pool) = pools.get_mut(name) {
                 pool.listener_channel.swap(settings.listen.clone())
                     .map_err(|_| error!("Can't update addresses for {}",
                                         name))
                     .ok();
+                pool.local_listeners.update(&settings.listen,
+                    settings.max_connections, settings.listen_error_timeout,
+                    false);
                 continue;
             }
             let (tx, rx) = channel();
@@ -90,8 +95,15 @@ impl SessionPools {
                     listen_rx.map_err(|()| -> Void { unreachable!() }), 80),
                 &wdata);
 
+            let mut local_listeners = LocalListeners::new(
+                WorkerAcceptor(wdata.clone()), handle);
+            local_listeners.update(&settings.listen,
+                settings.max_connections, settings.listen_error_timeout,
+                false);
+
             pools.insert(name.clone(), Worker {
                 listener_channel: listen_tx,
+                local_listeners: local_listeners,
                 inactivity_shutter: in_shutter,
             });
         }
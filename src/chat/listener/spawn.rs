@@ -1,3 +1,4 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use futures::stream::Stream;
@@ -11,6 +12,8 @@ use ns_router::future::AddrStream;
 use crate::intern::SessionPoolName;
 use crate::config::SessionPool;
 use crate::runtime::Runtime;
+use crate::incoming::Transport;
+use crate::local_sockets::{Acceptor, Connection};
 use crate::chat::listener::codec::Handler;
 use crate::chat::processor::{ProcessorPool};
 use crate::chat::replication::RemotePool;
@@ -26,6 +29,28 @@ pub struct WorkerData {
     pub handle: Handle, // Does it belong here?
 }
 
+#[derive(Clone)]
+pub struct WorkerAcceptor(pub Arc<WorkerData>);
+
+fn http_config(worker_data: &WorkerData) -> Arc<tk_http::server::Config> {
+    tk_http::server::Config::new()
+        .inflight_request_limit(worker_data.settings.pipeline_depth)
+        // TODO(tailhook) make it configurable?
+        .inflight_request_prealoc(0)
+        .done()
+}
+
+impl Acceptor for WorkerAcceptor {
+    fn accept<S: Transport>(&self, socket: S, addr: SocketAddr)
+        -> Connection
+    {
+        let ref h1 = self.0.runtime.handle;
+        Box::new(Proto::new(socket, &http_config(&self.0),
+                Handler::new(addr, self.0.clone()), h1)
+            .map_err(|e| debug!("Chat backend protocol error: {}", e)))
+    }
+}
+
 pub fn listen(addr_stream: AddrStream, worker_data: &Arc<WorkerData>) {
     let w1 = worker_data.clone();
     let w2 = worker_data.clone();
@@ -33,11 +58,7 @@ pub fn listen(addr_stream: AddrStream, worker_data: &Arc<WorkerData>) {
     let h1 = runtime.handle.clone();
 
     // TODO(tailhook) how to update?
-    let hcfg = tk_http::server::Config::new()
-        .inflight_request_limit(worker_data.settings.pipeline_depth)
-        // TODO(tailhook) make it configurable?
-        .inflight_request_prealoc(0)
-        .done();
+    let hcfg = http_config(worker_data);
 
     worker_data.handle.spawn(
         BindMany::new(addr_stream.map(|addr| addr.addresses_at(0)), &h1)
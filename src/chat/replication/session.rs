@@ -21,13 +21,15 @@ use crate::chat::processor::Processor;
 
 use super::{ReplAction, RemoteAction, IncomingChannel, OutgoingChannel};
 use super::action::Message;
-use super::spawn::{listen, connect};
+use super::spawn::{listen, connect, ReplicationAcceptor};
+use crate::local_sockets::LocalListeners;
 
 
 pub struct ReplicationSession {
     pub remote_sender: RemoteSender,
     tx: IncomingChannel,
     listener_channel: slot::Sender<Listen>,
+    local_listeners: LocalListeners<ReplicationAcceptor>,
     reconnect_shutter: Option<Sender<()>>,
 }
 
@@ -88,10 +90,17 @@ impl ReplicationSession {
                 listen_rx.map_err(|()| -> Void { unreachable!() }), 80),
             tx.clone(), server_id, cfg, handle);
 
+        let local_listeners = LocalListeners::new(ReplicationAcceptor {
+            sender: tx.clone(),
+            server_id: server_id.clone(),
+            handle: handle.clone(),
+        }, handle);
+
         ReplicationSession {
             tx: tx.clone(),
             remote_sender: RemoteSender { queue: tx },
             listener_channel: listen_tx,
+            local_listeners: local_listeners,
             reconnect_shutter: None,
         }
     }
@@ -101,6 +110,8 @@ impl ReplicationSession {
     {
         self.listener_channel.swap(cfg.listen.clone())
             .map_err(|_| error!("Can't update replication listener")).ok();
+        self.local_listeners.update(&cfg.listen,
+            cfg.max_connections, cfg.listen_error_timeout, false);
         // stop reconnecting
         if let Some(tx) = self.reconnect_shutter.take() {
             tx.send(()).ok();
@@ -1,4 +1,5 @@
 use std::io;
+use std::net::SocketAddr;
 use std::time::Instant;
 use std::sync::Arc;
 
@@ -17,6 +18,8 @@ use tokio_core::reactor::{Timeout, Handle};
 use ns_router::future::AddrStream;
 
 use crate::config::Replication;
+use crate::incoming::Transport;
+use crate::local_sockets::{Acceptor, Connection};
 use crate::runtime::ServerId;
 use super::server::Incoming;
 use super::client::Authorizer;
@@ -24,6 +27,26 @@ use super::{IncomingChannel, ReplAction};
 use crate::chat::replication::{CONNECTIONS, FRAMES_SENT, FRAMES_RECEIVED};
 
 
+#[derive(Clone)]
+pub struct ReplicationAcceptor {
+    pub sender: IncomingChannel,
+    pub server_id: ServerId,
+    pub handle: Handle,
+}
+
+impl Acceptor for ReplicationAcceptor {
+    fn accept<S: Transport>(&self, socket: S, _addr: SocketAddr)
+        -> Connection
+    {
+        // TODO: setup proper configuration;
+        let hcfg = Config::new().done();
+        let disp = Incoming::new(self.sender.clone(), self.server_id,
+            &self.handle);
+        Box::new(Proto::new(socket, &hcfg, disp, &self.handle)
+            .map_err(|e| debug!("Http protocol error: {}", e)))
+    }
+}
+
 pub fn listen(addr_stream: AddrStream, sender: IncomingChannel,
     server_id: &ServerId, settings: &Arc<Replication>, handle: &Handle)
 {
@@ -1,9 +1,14 @@
 use std::sync::Arc;
 
-use quire::validate::{Enum, Nothing};
+use quire::validate::{Enum, Nothing, Sequence, Scalar};
 
+use crate::config::htpasswd;
+use crate::config::jwt;
 use crate::config::ldap;
 use crate::config::networks;
+use crate::config::signed_url;
+use crate::config::subrequest;
+use crate::intern::Authorizer as AuthorizerName;
 
 
 #[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -11,11 +16,25 @@ pub enum Authorizer {
     AllowAll,
     SourceIp(Arc<networks::SourceIpAuthorizer>),
     Ldap(Arc<ldap::Ldap>),
+    HtPasswd(Arc<htpasswd::HtPasswd>),
+    Subrequest(Arc<subrequest::Subrequest>),
+    Jwt(Arc<jwt::Jwt>),
+    SignedUrl(Arc<signed_url::SignedUrl>),
+    /// Allows request if any of the referenced authorizers allows it
+    AnyOf(Arc<Vec<AuthorizerName>>),
+    /// Allows request if all of the referenced authorizers allow it
+    AllOf(Arc<Vec<AuthorizerName>>),
 }
 
 pub fn validator<'x>() -> Enum<'x> {
     Enum::new()
+    .option("AllOf", Sequence::new(Scalar::new()).min_length(1))
     .option("AllowAll", Nothing)
+    .option("AnyOf", Sequence::new(Scalar::new()).min_length(1))
+    .option("HtPasswd", htpasswd::authorizer_validator())
+    .option("Jwt", jwt::authorizer_validator())
     .option("Ldap", ldap::authorizer_validator())
+    .option("SignedUrl", signed_url::authorizer_validator())
     .option("SourceIp", networks::source_ip_authorizer_validator())
+    .option("Subrequest", subrequest::authorizer_validator())
 }
@@ -7,6 +7,7 @@ use super::empty_gif;
 use super::proxy;
 use super::redirect;
 use super::self_status;
+use super::split;
 use super::static_files;
 
 
@@ -28,6 +29,8 @@ pub enum Handler {
     BaseRedirect(Arc<redirect::BaseRedirect>),
     StripWWWRedirect,
     SelfStatus(Arc<self_status::SelfStatus>),
+    /// Chooses one of the other handlers by weight
+    Split(Arc<split::Split>),
 }
 
 pub fn validator<'x>() -> Enum<'x> {
@@ -43,4 +46,5 @@ pub fn validator<'x>() -> Enum<'x> {
     .option("BaseRedirect", redirect::base_redirect())
     .option("StripWWWRedirect", Nothing)
     .option("SelfStatus", self_status::validator())
+    .option("Split", split::validator())
 }
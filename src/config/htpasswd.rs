@@ -0,0 +1,16 @@
+use std::path::PathBuf;
+
+use quire::validate::{Structure, Scalar};
+
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct HtPasswd {
+    pub file: PathBuf,
+    pub realm: String,
+}
+
+pub fn authorizer_validator<'x>() -> Structure<'x> {
+    Structure::new()
+    .member("file", Scalar::new())
+    .member("realm", Scalar::new().default("swindon"))
+}
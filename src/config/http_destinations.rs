@@ -1,17 +1,62 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
 use quire::validate::{Structure, Scalar, Enum, Numeric, Nothing};
 use quire::validate::{Sequence};
 
-#[derive(Deserialize, Debug, PartialEq, Eq)]
+use crate::config::tls;
+use crate::config::visitors::optional_duration;
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
 #[allow(non_camel_case_types)]
 pub enum LoadBalancing {
+    /// All connections to all addresses share single queue of requests
     queue,
+    round_robin,
+    least_connections,
+    consistent_hash,
+}
+
+/// Request property used to choose address for `consistent_hash`
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub enum HashKey {
+    Header(String),
+    Cookie(String),
+    ClientIp,
 }
 
-#[derive(Deserialize, Debug, PartialEq, Eq)]
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Tls {
+    /// Uses bundled Mozilla root certificates if not specified
+    pub ca_bundle: Option<PathBuf>,
+    /// Host name of the first address is used if not specified
+    pub server_name: Option<String>,
+    pub client_certificate: Option<tls::CertificateSource>,
+    /// Contents of the files above, not read from config
+    #[serde(skip)]
+    pub files: Option<Arc<tls::ClientFiles>>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct HealthCheck {
+    pub path: String,
+    #[serde(with="::quire::duration")]
+    pub interval: Duration,
+    #[serde(with="::quire::duration")]
+    pub timeout: Duration,
+    pub expected_status: u16,
+    /// Number of successful checks to consider address healthy again
+    pub rise: u32,
+    /// Number of failed checks to consider address unhealthy
+    pub fall: u32,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct Destination {
     pub load_balancing: LoadBalancing,
+    /// Client IP is used if not specified
+    pub hash_key: Option<HashKey>,
     pub queue_size_for_503: usize,
     pub backend_connections_per_ip_port: u32,
     pub in_flight_requests_per_backend_connection: usize,
@@ -22,16 +67,100 @@ pub struct Destination {
     pub max_request_timeout: Duration,
     #[serde(with="::quire::duration")]
     pub safe_pipeline_timeout: Duration,
+    /// Time to wait for response headers, `504` is returned on timeout
+    #[serde(deserialize_with="optional_duration", default)]
+    pub response_timeout: Option<Duration>,
+    /// Overrides `input-body-whole-timeout` of the server for requests
+    /// proxied to this destination
+    #[serde(deserialize_with="optional_duration", default)]
+    pub input_body_whole_timeout: Option<Duration>,
+    /// Request timeout of backend connections, not read from config
+    ///
+    /// It's the longest of `max-request-timeout` and response timeouts of
+    /// the destination and of the routes using it.
+    #[serde(skip)]
+    pub backend_request_timeout: Duration,
     pub override_host_header: Option<String>,
     pub request_id_header: Option<String>,
+    pub tls: Option<Tls>,
+    pub max_retries: u32,
+    /// Methods that are retried in addition to GET, HEAD and OPTIONS
+    pub retry_methods: Vec<String>,
+    /// Maximum percentage of retries relative to requests
+    pub retry_budget: usize,
+    pub health_check: Option<HealthCheck>,
+}
+
+impl Tls {
+    /// Files that are read by `load`
+    pub fn files(&self) -> Vec<&Path> {
+        let mut files = Vec::new();
+        files.extend(self.ca_bundle.as_ref().map(|p| p.as_path()));
+        if let Some(ref cert) = self.client_certificate {
+            files.extend(cert.files());
+        }
+        files
+    }
+    pub fn load(&self) -> Result<tls::ClientFiles, String> {
+        Ok(tls::ClientFiles {
+            ca_bundle: match self.ca_bundle {
+                Some(ref path) => tls::read_chain(path)?,
+                None => Vec::new(),
+            },
+            client_certificate: match self.client_certificate {
+                Some(ref cert) => Some(cert.load()?),
+                None => None,
+            },
+        })
+    }
+}
+
+impl Destination {
+    /// Returns `true` if request with this method can be sent again
+    pub fn is_retriable(&self, method: &str) -> bool {
+        match method {
+            "GET" | "HEAD" | "OPTIONS" => true,
+            _ => self.retry_methods.iter().any(|m| m == method),
+        }
+    }
+    /// Returns path if destination is a unix socket (`unix:/path`)
+    pub fn unix_socket(&self) -> Option<&Path> {
+        if self.addresses.len() == 1 && self.addresses[0].starts_with("unix:")
+        {
+            Some(Path::new(&self.addresses[0]["unix:".len()..]))
+        } else {
+            None
+        }
+    }
+    /// Name that is sent in SNI and validated against server certificate
+    pub fn tls_server_name(&self) -> Option<&str> {
+        self.tls.as_ref().map(|tls| {
+            tls.server_name.as_ref().map(|x| &x[..])
+            .unwrap_or_else(|| {
+                let addr = &self.addresses[0][..];
+                match addr.rfind(':') {
+                    Some(idx) => &addr[..idx],
+                    None => addr,
+                }
+            })
+        })
+    }
 }
 
 pub fn validator<'x>() -> Structure<'x> {
     Structure::new()
     .member("load_balancing", Enum::new()
         .option("queue", Nothing)
+        .option("round_robin", Nothing)
+        .option("least_connections", Nothing)
+        .option("consistent_hash", Nothing)
         .allow_plain()
         .plain_default("queue"))
+    .member("hash_key", Enum::new()
+        .option("Header", Scalar::new())
+        .option("Cookie", Scalar::new())
+        .option("ClientIp", Nothing)
+        .optional())
     .member("queue_size_for_503",
         Numeric::new().min(0).max(1 << 32).default(100_000))
     .member("backend_connections_per_ip_port",
@@ -42,6 +171,25 @@ pub fn validator<'x>() -> Structure<'x> {
     .member("keep_alive_timeout", Scalar::new().default("4 sec"))
     .member("max_request_timeout", Scalar::new().default("30 secs"))
     .member("safe_pipeline_timeout", Scalar::new().default("300 ms"))
+    .member("response_timeout", Scalar::new().optional())
+    .member("input_body_whole_timeout", Scalar::new().optional())
     .member("override_host_header", Scalar::new().optional())
     .member("request_id_header", Scalar::new().optional())
+    .member("tls", Structure::new()
+        .member("ca_bundle", Scalar::new().optional())
+        .member("server_name", Scalar::new().optional())
+        .member("client_certificate", tls::validator().optional())
+        .optional())
+    .member("max_retries", Numeric::new().min(0).max(100).default(0))
+    .member("retry_methods", Sequence::new(Scalar::new()))
+    .member("retry_budget", Numeric::new().min(0).max(100).default(20))
+    .member("health_check", Structure::new()
+        .member("path", Scalar::new())
+        .member("interval", Scalar::new().default("10 sec"))
+        .member("timeout", Scalar::new().default("2 sec"))
+        .member("expected_status",
+            Numeric::new().min(100).max(999).default(200))
+        .member("rise", Numeric::new().min(1).max(1000).default(2))
+        .member("fall", Numeric::new().min(1).max(1000).default(3))
+        .optional())
 }
@@ -0,0 +1,34 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use quire::validate::{Structure, Scalar, Mapping};
+
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct Jwt {
+    /// Secret for HS256 tokens
+    pub secret: Option<String>,
+    /// Public keys for RS256 and ES256 tokens
+    pub jwks_file: Option<PathBuf>,
+    /// Token is read from this cookie if there is no bearer token
+    pub cookie: Option<String>,
+    pub audience: Option<String>,
+    pub issuer: Option<String>,
+    /// Allowed clock difference for `exp` and `nbf` claims
+    #[serde(with="::quire::duration")]
+    pub leeway: Duration,
+    /// Maps header name to the name of the claim
+    pub claim_headers: HashMap<String, String>,
+}
+
+pub fn authorizer_validator<'x>() -> Structure<'x> {
+    Structure::new()
+    .member("secret", Scalar::new().optional())
+    .member("jwks_file", Scalar::new().optional())
+    .member("cookie", Scalar::new().optional())
+    .member("audience", Scalar::new().optional())
+    .member("issuer", Scalar::new().optional())
+    .member("leeway", Scalar::new().default("0 s"))
+    .member("claim_headers", Mapping::new(Scalar::new(), Scalar::new()))
+}
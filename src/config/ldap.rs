@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::time::Duration;
 
 use quire::validate::{Structure, Sequence, Mapping, Scalar};
 
@@ -8,6 +9,9 @@ use crate::intern::LdapUpstream;
 #[derive(Deserialize, PartialEq, Eq, Debug)]
 pub struct Destination {
     pub addresses: Vec<String>,
+    /// Timeout of the whole authorization (connect, bind and searches)
+    #[serde(with="::quire::duration")]
+    pub timeout: Duration,
 }
 
 #[derive(Deserialize, Debug, PartialEq, Eq)]
@@ -23,15 +27,20 @@ pub struct Ldap {
     pub destination: LdapUpstream,
     pub search_base: String,
     pub login_attribute: String,
-    pub password_attribute: String,
+    /// Not used, password is checked by binding as the user
+    pub password_attribute: Option<String>,
     pub login_header: Option<String>,
     pub additional_queries: HashMap<String, Query>,
+    /// Time successful authorization is cached for
+    #[serde(with="::quire::duration")]
+    pub cache_ttl: Duration,
 }
 
 
 pub fn destination_validator<'x>() -> Structure<'x> {
     Structure::new()
     .member("addresses", Sequence::new(Scalar::new()).min_length(1))
+    .member("timeout", Scalar::new().default("10 sec"))
 }
 
 pub fn authorizer_validator<'x>() -> Structure<'x> {
@@ -39,7 +48,7 @@ pub fn authorizer_validator<'x>() -> Structure<'x> {
     .member("destination", Scalar::new())
     .member("search_base", Scalar::new())
     .member("login_attribute", Scalar::new())
-    .member("password_attribute", Scalar::new())
+    .member("password_attribute", Scalar::new().optional())
     .member("login_header", Scalar::new().optional())
     .member("additional_queries", Mapping::new(
         Scalar::new(),
@@ -48,4 +57,5 @@ pub fn authorizer_validator<'x>() -> Structure<'x> {
         .member("fetch_attribute", Scalar::new())
         .member("filter", Scalar::new())
         .member("dn_attribute_strip_base", Scalar::new().optional())))
+    .member("cache_ttl", Scalar::new().default("1 min"))
 }
@@ -1,15 +1,19 @@
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use ns_router::AutoName;
-use quire::validate::{Enum, Scalar};
+use quire::validate::{Enum, Scalar, Numeric};
 
 
-#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Deserialize)]
 pub enum ListenSocket {
     Tcp(String),
-    // TODO(tailhook)
-    // Fd(u32)
-    // Unix(PathBuf)
+    /// TCP socket with TLS, certificates are chosen by SNI from the
+    /// `tls-certificates` section
+    Tls(String),
+    /// Already open listening socket passed by parent process (systemd)
+    Fd(u32),
+    Unix(PathBuf),
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
@@ -22,21 +26,57 @@ impl Listen {
     pub fn len(&self) -> usize {
         self.0.len()
     }
+    /// Returns only plain-text sockets that need name resolution
+    pub fn plain(&self) -> Listen {
+        Listen::new(self.0.iter()
+            .filter(|x| matches!(**x, ListenSocket::Tcp(..)))
+            .cloned()
+            .collect())
+    }
+    /// Returns only sockets that need TLS handshake
+    pub fn tls(&self) -> Listen {
+        Listen::new(self.0.iter()
+            .filter(|x| matches!(**x, ListenSocket::Tls(..)))
+            .cloned()
+            .collect())
+    }
+    /// Returns unix sockets and file descriptors, i.e. sockets that are
+    /// not resolved by name
+    pub fn local(&self) -> Vec<ListenSocket> {
+        self.0.iter()
+            .filter(|x| matches!(**x,
+                ListenSocket::Fd(..) | ListenSocket::Unix(..)))
+            .cloned()
+            .collect()
+    }
+}
+
+fn resolvable<'a>(sock: &'a ListenSocket) -> Option<AutoName<'a>> {
+    match *sock {
+        ListenSocket::Tcp(ref s) => Some(AutoName::Auto(s)),
+        ListenSocket::Tls(ref s) => Some(AutoName::Auto(s)),
+        ListenSocket::Fd(..) => None,
+        ListenSocket::Unix(..) => None,
+    }
 }
 
+/// Iterates over names that need to be resolved, unix sockets and file
+/// descriptors are skipped
 impl<'a> IntoIterator for &'a Listen {
     type Item = AutoName<'a>;
-    type IntoIter = ::std::iter::Map<::std::slice::Iter<'a, ListenSocket>,
-                    fn(&'a ListenSocket) -> AutoName<'a>>;
+    type IntoIter = ::std::iter::FilterMap<
+                    ::std::slice::Iter<'a, ListenSocket>,
+                    fn(&'a ListenSocket) -> Option<AutoName<'a>>>;
     fn into_iter(self) -> Self::IntoIter {
-        self.0.iter().map(|x| match *x {
-            ListenSocket::Tcp(ref s) => AutoName::Auto(s),
-        })
+        self.0.iter().filter_map(resolvable)
     }
 }
 
 pub fn validator<'x>() -> Enum<'x> {
     Enum::new()
     .option("Tcp", Scalar::new())
+    .option("Tls", Scalar::new())
+    .option("Fd", Numeric::new().min(0))
+    .option("Unix", Scalar::new())
     .default_tag("Tcp")
 }
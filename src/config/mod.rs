@@ -12,12 +12,17 @@ mod replication;
 mod session_pools;
 pub mod authorizers;
 pub mod handlers;
+pub mod htpasswd;
 pub mod http_destinations;
+pub mod jwt;
 pub mod ldap;
 pub mod listen;
 pub mod log;
 pub mod networks;
 pub mod routing;
+pub mod signed_url;
+pub mod subrequest;
+pub mod tls;
 pub mod visitors;
 // handlers
 pub mod chat;
@@ -27,6 +32,7 @@ pub mod disk;
 pub mod empty_gif;
 pub mod redirect;
 pub mod self_status;
+pub mod split;
 
 pub use self::read::Error;
 pub use self::root::{ConfigData, ConfigSource};
@@ -1,6 +1,6 @@
 use std::fmt;
 use std::str::FromStr;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
 
 use quire::validate::{Structure, Sequence, Scalar};
 use serde::de::{Deserializer, Deserialize};
@@ -11,6 +11,21 @@ use crate::intern::Network;
 #[derive(Debug, PartialEq, Eq)]
 pub struct NetworkList {
     list: Vec<Subnet>,
+    v4: Trie,
+    v6: Trie,
+}
+
+/// Binary prefix trie, values are indexes in `NetworkList::list`
+#[derive(Debug, PartialEq, Eq, Default)]
+struct Trie {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, PartialEq, Eq, Default)]
+struct Node {
+    /// Zero means no child, as root is never a child
+    children: [u32; 2],
+    subnet: Option<usize>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -37,7 +52,7 @@ pub fn validator<'x>() -> Sequence<'x> {
 impl<'a> Deserialize<'a> for NetworkList {
     fn deserialize<D: Deserializer<'a>>(d: D) -> Result<Self, D::Error> {
         let networks: Vec<Subnet> = Deserialize::deserialize(d)?;
-        Ok(NetworkList { list: networks })
+        Ok(NetworkList::new(networks))
     }
 }
 
@@ -64,12 +79,9 @@ impl FromStr for Subnet {
             let mask = item[pos+1..].parse::<u32>()
                 .map_err(|e| e.to_string())?;
             let max_mask = match ip {
-                IpAddr::V4(_) => 24,
+                IpAddr::V4(_) => 32,
                 IpAddr::V6(_) => 128,
             };
-            if mask % 8 != 0 {
-                return Err("Subnet mask must be multiple of eight".into());
-            }
             if mask > max_mask {
                 return Err(format!("Mask must be {} at max", max_mask));
             }
@@ -78,6 +90,8 @@ impl FromStr for Subnet {
             let ip = item.parse::<IpAddr>()
                 .map_err(|e| e.to_string())?;
             match ip {
+                // Kept for backwards compatibility, use `/32` to allow
+                // a single address
                 IpAddr::V4(_) => Ok(Subnet(ip, 24)),
                 IpAddr::V6(_) => Ok(Subnet(ip, 128)),
             }
@@ -85,25 +99,134 @@ impl FromStr for Subnet {
     }
 }
 
-impl NetworkList {
-    pub fn get_subnet(&self, ip: IpAddr) -> Option<&Subnet> {
-        for item in &self.list {
-            match (ip, item) {
-                (IpAddr::V4(my), &Subnet(IpAddr::V4(net), msk)) => {
-                    let bytes = (msk / 8) as usize;
-                    if my.octets()[..bytes] == net.octets()[..bytes] {
-                        return Some(item);
-                    }
-                }
-                (IpAddr::V6(my), &Subnet(IpAddr::V6(net), msk)) => {
-                    let bytes = (msk / 8) as usize;
-                    if my.octets()[..bytes] == net.octets()[..bytes] {
-                        return Some(item);
-                    }
+fn bit(addr: &[u8], index: u32) -> usize {
+    (addr[(index / 8) as usize] >> (7 - index % 8) & 1) as usize
+}
+
+impl Trie {
+    fn insert(&mut self, addr: &[u8], mask: u32, subnet: usize) {
+        if self.nodes.len() == 0 {
+            self.nodes.push(Node::default());
+        }
+        let mut cur = 0;
+        for index in 0..mask {
+            let b = bit(addr, index);
+            cur = match self.nodes[cur].children[b] {
+                0 => {
+                    self.nodes.push(Node::default());
+                    let new = self.nodes.len() - 1;
+                    self.nodes[cur].children[b] = new as u32;
+                    new
                 }
-                _ => {}
+                child => child as usize,
+            };
+        }
+        // first of the duplicate subnets wins
+        if self.nodes[cur].subnet.is_none() {
+            self.nodes[cur].subnet = Some(subnet);
+        }
+    }
+    /// Returns the longest matching prefix
+    fn find(&self, addr: &[u8]) -> Option<usize> {
+        let mut found = None;
+        let mut cur = 0;
+        for index in 0..(addr.len()*8) as u32 {
+            let node = match self.nodes.get(cur) {
+                Some(node) => node,
+                None => break,
+            };
+            found = node.subnet.or(found);
+            cur = match node.children[bit(addr, index)] {
+                0 => return found,
+                child => child as usize,
+            };
+        }
+        self.nodes.get(cur).and_then(|n| n.subnet).or(found)
+    }
+}
+
+impl NetworkList {
+    pub fn new(list: Vec<Subnet>) -> NetworkList {
+        let mut v4 = Trie::default();
+        let mut v6 = Trie::default();
+        for (idx, &Subnet(ip, mask)) in list.iter().enumerate() {
+            match ip {
+                IpAddr::V4(ip) => v4.insert(&ip.octets(), mask, idx),
+                IpAddr::V6(ip) => v6.insert(&ip.octets(), mask, idx),
             }
         }
-        return None;
+        NetworkList { list, v4, v6 }
+    }
+    /// Returns the most specific subnet containing the address
+    ///
+    /// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) are matched against
+    /// IPv4 subnets.
+    pub fn get_subnet(&self, ip: IpAddr) -> Option<&Subnet> {
+        let idx = match ip {
+            IpAddr::V4(ip) => self.v4.find(&ip.octets()),
+            IpAddr::V6(ip) => match ip.segments() {
+                [0, 0, 0, 0, 0, 0xffff, hi, lo] => {
+                    let v4 = Ipv4Addr::new((hi >> 8) as u8, hi as u8,
+                                           (lo >> 8) as u8, lo as u8);
+                    self.v4.find(&v4.octets())
+                }
+                _ => self.v6.find(&ip.octets()),
+            },
+        };
+        idx.map(|idx| &self.list[idx])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{NetworkList, Subnet};
+
+    fn list(items: &[&str]) -> NetworkList {
+        NetworkList::new(items.iter().map(|x| x.parse().unwrap()).collect())
+    }
+
+    fn find(list: &NetworkList, ip: &str) -> Option<String> {
+        list.get_subnet(ip.parse().unwrap()).map(|s| s.to_string())
+    }
+
+    #[test]
+    fn parse() {
+        assert!("10.0.0.1/32".parse::<Subnet>().is_ok());
+        assert!("10.0.0.0/22".parse::<Subnet>().is_ok());
+        assert!("10.0.0.0/33".parse::<Subnet>().is_err());
+        assert!("::1/129".parse::<Subnet>().is_err());
+    }
+
+    #[test]
+    fn ipv4() {
+        let list = list(&["10.0.0.0/8", "10.1.0.0/16", "10.1.16.0/20",
+                          "192.168.1.7/32", "172.16.4.0/22"]);
+        assert_eq!(find(&list, "10.2.3.4").unwrap(), "10.0.0.0/8");
+        assert_eq!(find(&list, "10.1.3.4").unwrap(), "10.1.0.0/16");
+        assert_eq!(find(&list, "10.1.31.4").unwrap(), "10.1.16.0/20");
+        assert_eq!(find(&list, "10.1.32.4").unwrap(), "10.1.0.0/16");
+        assert_eq!(find(&list, "192.168.1.7").unwrap(), "192.168.1.7/32");
+        assert_eq!(find(&list, "192.168.1.8"), None);
+        assert_eq!(find(&list, "172.16.7.255").unwrap(), "172.16.4.0/22");
+        assert_eq!(find(&list, "172.16.8.0"), None);
+        assert_eq!(find(&list, "::ffff:10.1.3.4").unwrap(), "10.1.0.0/16");
+        assert_eq!(find(&list, "::1"), None);
+    }
+
+    #[test]
+    fn ipv6() {
+        let list = list(&["2001:db8::/32", "2001:db8:1::/48", "::1"]);
+        assert_eq!(find(&list, "2001:db8:2::1").unwrap(), "2001:db8::/32");
+        assert_eq!(find(&list, "2001:db8:1::1").unwrap(),
+                   "2001:db8:1::/48");
+        assert_eq!(find(&list, "::1").unwrap(), "::1/128");
+        assert_eq!(find(&list, "::2"), None);
+        assert_eq!(find(&list, "10.0.0.1"), None);
+    }
+
+    #[test]
+    fn match_all() {
+        let list = list(&["0.0.0.0/0"]);
+        assert_eq!(find(&list, "1.2.3.4").unwrap(), "0.0.0.0/0");
     }
 }
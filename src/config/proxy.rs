@@ -1,6 +1,12 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+use serde::de::{Deserialize, Deserializer, Error};
+
 use super::http;
 
-use quire::validate::{Nothing, Enum, Structure, Scalar, Numeric};
+use quire::validate::{Nothing, Enum, Structure, Scalar, Numeric, Mapping};
+use quire::validate::{Sequence};
 
 #[derive(Deserialize, Debug, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
@@ -15,13 +21,68 @@ pub struct Proxy {
     pub ip_header: Option<String>,
     // NOTE: option is deprecated.
     pub request_id_header: Option<String>,
+    /// Maps header name to the identity attribute set by authorizer
+    pub identity_headers: HashMap<String, String>,
     pub destination: http::Destination,
+    /// Strip the path prefix of the matched route before forwarding
+    pub strip_prefix: bool,
+    /// Rules applied to the path in order, the first matching one wins
+    pub rewrite: Vec<PathRewrite>,
     // TODO(tailhook) this might needs to be u64
     pub max_payload_size: usize,
     pub stream_requests: bool,
     pub response_buffer_size: usize,
 }
 
+/// Regex replacement of the path sent to the backend
+#[derive(Debug)]
+pub struct PathRewrite {
+    pub pattern: Regex,
+    /// Replacement string, may refer to groups as `$1` or `${name}`
+    pub replace: String,
+}
+
+impl<'a> Deserialize<'a> for PathRewrite {
+    fn deserialize<D: Deserializer<'a>>(d: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct RewriteRaw {
+            pattern: String,
+            replace: String,
+        }
+        let raw = RewriteRaw::deserialize(d)?;
+        Ok(PathRewrite {
+            pattern: Regex::new(&raw.pattern)
+                .map_err(|e| D::Error::custom(&format!("{}", e)))?,
+            replace: raw.replace,
+        })
+    }
+}
+
+impl PartialEq for PathRewrite {
+    fn eq(&self, other: &PathRewrite) -> bool {
+        self.pattern.as_str() == other.pattern.as_str() &&
+            self.replace == other.replace
+    }
+}
+
+impl Eq for PathRewrite { }
+
+impl PathRewrite {
+    /// Returns rewritten path if pattern matches, query is kept as is
+    pub fn apply(&self, path: &str) -> Option<String> {
+        let (path, query) = match path.find(|c| c == '?' || c == '#') {
+            Some(idx) => path.split_at(idx),
+            None => (path, ""),
+        };
+        if self.pattern.is_match(path) {
+            Some(self.pattern.replace(path, &self.replace[..]).into_owned()
+                 + query)
+        } else {
+            None
+        }
+    }
+}
+
 pub fn validator<'x>() -> Structure<'x> {
     Structure::new()
     .member("mode", Enum::new()
@@ -30,10 +91,15 @@ pub fn validator<'x>() -> Structure<'x> {
         .plain_default("forward"))
     .member("ip_header", Scalar::new().optional())
     .member("request_id_header", Scalar::new().optional())
+    .member("identity_headers", Mapping::new(Scalar::new(), Scalar::new()))
     .member("max_payload_size",
         Numeric::new().min(0).max(1 << 40).default(10 << 20))
     .member("stream_requests", Scalar::new().default(false))
     .member("response_buffer_size",
         Numeric::new().min(0).max(1 << 40).default(10 << 20))
     .member("destination", http::destination_validator())
+    .member("strip_prefix", Scalar::new().default(false))
+    .member("rewrite", Sequence::new(Structure::new()
+        .member("pattern", Scalar::new())
+        .member("replace", Scalar::new())))
 }
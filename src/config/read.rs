@@ -1,4 +1,5 @@
 use std::cell::RefCell;
+use std::cmp::max;
 use std::collections::HashMap;
 use std::fmt;
 use std::fs::{File, Metadata, metadata};
@@ -18,8 +19,11 @@ use crate::config::root::{config_validator, mixin_validator};
 use super::Handler;
 use crate::config::static_files::Mode;
 use crate::config::log;
+use crate::config::http_destinations::LoadBalancing;
 use crate::intern::{LogFormatName, Authorizer as AuthorizerName, HandlerName};
-use crate::routing::RoutingTable;
+use crate::config::routing::{Host, HostPath};
+use crate::routing::{RoutingTable, Pattern, placeholders};
+use crate::tls;
 
 
 quick_error! {
@@ -221,9 +225,153 @@ pub fn read_config<P: AsRef<Path>>(filename: P)
         mix_in(&incl_path, prefix,
             &mut src.disk_pools, mixin.disk_pools, "disk-pools")?;
     }
+    // Certificates are re-read on config reload, so we track their
+    // modification time as if they were included files
+    for (name, cert) in &src.tls_certificates {
+        for path in cert.files() {
+            files.push((
+                path.to_path_buf(),
+                format!("<tls-certificate {}>", name),
+                metadata(path)?,
+            ));
+        }
+    }
+    for (name, dest) in &src.http_destinations {
+        if let Some(ref tls) = dest.tls {
+            for path in tls.files() {
+                files.push((
+                    path.to_path_buf(),
+                    format!("<http-destination {}>", name),
+                    metadata(path)?,
+                ));
+            }
+        }
+    }
     return Ok((postprocess_config(src)?, files));
 }
 
+/// Checks that combined authorizers reference only existing authorizers
+/// and there are no cycles
+fn check_authorizer_refs(authorizers: &HashMap<AuthorizerName, Authorizer>,
+    name: &AuthorizerName, path: &mut Vec<AuthorizerName>)
+    -> Result<(), Error>
+{
+    if path.contains(name) {
+        path.push(name.clone());
+        err!("authorizers form a cycle: {}", path.iter()
+            .map(|x| &x[..]).collect::<Vec<_>>().join(" -> "));
+    }
+    let refs = match authorizers.get(name) {
+        Some(&Authorizer::AnyOf(ref refs)) => refs,
+        Some(&Authorizer::AllOf(ref refs)) => refs,
+        Some(_) => return Ok(()),
+        None => {
+            err!("{:?}: unknown authorizer {:?}",
+                path.last().expect("root authorizer exists"), name);
+        }
+    };
+    path.push(name.clone());
+    for item in refs.iter() {
+        check_authorizer_refs(authorizers, item, path)?;
+    }
+    path.pop();
+    Ok(())
+}
+
+/// Backend connections must not time out before the longest response
+/// timeout of the routes using the destination
+fn set_backend_request_timeouts(cfg: &mut ConfigData) {
+    let mut longest = HashMap::new();
+    for route in cfg.routing.routes() {
+        let timeout = match route.options.response_timeout {
+            Some(timeout) => timeout,
+            None => continue,
+        };
+        let handlers = Some(&route.handler).into_iter()
+            .chain(route.variants.iter().map(|v| &v.handler));
+        for handler in handlers {
+            if let Handler::Proxy(ref proxy) = *handler {
+                let value = longest.entry(proxy.destination.upstream.clone())
+                    .or_insert(timeout);
+                *value = max(*value, timeout);
+            }
+        }
+    }
+    for (name, dest) in cfg.http_destinations.iter_mut() {
+        let timeout = Some(dest.max_request_timeout).into_iter()
+            .chain(dest.response_timeout)
+            .chain(longest.get(name).cloned())
+            .max().expect("max request timeout is set");
+        Arc::make_mut(dest).backend_request_timeout = timeout;
+    }
+}
+
+/// Returns path template of the handler which may contain captures
+fn handler_template(handler: &Handler) -> Option<&str> {
+    match *handler {
+        Handler::Proxy(ref proxy) => Some(&proxy.destination.path),
+        Handler::Static(ref config) => config.path.to_str(),
+        Handler::SingleFile(ref config) => config.path.to_str(),
+        _ => None,
+    }
+}
+
+/// Checks that there are no patterns matching exactly the same paths and
+/// that every capture used by the handler is defined by the route
+///
+/// Other overlapping patterns are allowed, they are ordered in `PathTable`
+fn check_route_patterns(src: &ConfigSource) -> Result<(), Error> {
+    let mut shapes = HashMap::new();
+    for (&HostPath(Host(star, ref host), ref path, ref cond), rdef)
+        in &src.routing
+    {
+        let star = match (star, &host[..]) {
+            (true, "") => "*",
+            (true, _) => "*.",
+            (false, _) => "",
+        };
+        let pattern = match *path {
+            Some(ref path) if Pattern::is_pattern(path) => {
+                let pattern = Pattern::new(path).map_err(Error::Routing)?;
+                let key = (star, host.clone(), cond.clone(),
+                           pattern.shape().to_string());
+                if let Some(other) = shapes.insert(key, path.clone()) {
+                    let (a, b) = if &other < path {
+                        (&other, path)
+                    } else {
+                        (path, &other)
+                    };
+                    return Err(Error::Routing(format!(
+                        "Ambiguous routes {star}{host}{} and {star}{host}{}",
+                        a, b, star=star, host=host)));
+                }
+                Some(pattern)
+            }
+            _ => None,
+        };
+        let mut handlers = vec![&rdef.handler];
+        let handler = src.handlers.get(&rdef.handler);
+        if let Some(&Handler::Split(ref split)) = handler {
+            handlers.extend(split.variants.iter().map(|v| &v.handler));
+        }
+        let groups = pattern.as_ref().map(|p| p.groups())
+            .unwrap_or_else(Vec::new);
+        for hname in handlers {
+            let template = src.handlers.get(hname)
+                .and_then(handler_template).unwrap_or("");
+            for name in placeholders(template) {
+                if !groups.iter().any(|g| g == name) {
+                    return Err(Error::Routing(format!(
+                        "Handler {:?} uses {{{}}}, but route {}{}{} \
+                         has no such group", &hname[..], name, star, host,
+                         path.as_ref().map(|x| &x[..]).unwrap_or(""))));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 pub fn postprocess_config(mut src: ConfigSource)
     -> Result<ConfigData, Error>
 {
@@ -249,8 +397,41 @@ pub fn postprocess_config(mut src: ConfigSource)
                                Authorizer::AllowAll);
     }
 
+    let mut tls_certificates = HashMap::new();
+    for (name, cert) in &src.tls_certificates {
+        let cert = cert.load()
+            .map_err(|e| format!("tls certificate {:?}: {}", name, e))?;
+        tls_certificates.insert(name.clone(), Arc::new(cert));
+    }
+
+    for (name, dest) in src.http_destinations.iter_mut() {
+        if let Some(ref tls) = dest.tls {
+            let files = tls.load()
+                .map_err(|e| {
+                    format!("http destination {:?}: {}", name, e)
+                })?;
+            let mut tls = tls.clone();
+            tls.files = Some(Arc::new(files));
+            tls::client_config(&tls)
+                .map_err(|e| {
+                    format!("http destination {:?}: {}", name, e)
+                })?;
+            Arc::make_mut(dest).tls = Some(tls);
+        }
+    }
+
+    check_route_patterns(&src)?;
+    for rdef in src.routing.values() {
+        if let Some(ref fmt) = rdef.log {
+            if !src.log_formats.contains_key(fmt) {
+                err!("Log format {:?} used in routing is not defined",
+                     &fmt[..]);
+            }
+        }
+    }
     let mut cfg = ConfigData {
         routing: RoutingTable::new(&src)?,
+        tls_certificates: tls_certificates,
 
         listen: src.listen,
         max_connections: src.max_connections,
@@ -381,8 +562,66 @@ pub fn postprocess_config(mut src: ConfigSource)
             _ => {}
         }
     }
+    for (name, auth) in &cfg.authorizers {
+        match *auth {
+            Authorizer::Subrequest(ref sub) => {
+                let u = &sub.destination.upstream;
+                if !cfg.http_destinations.contains_key(u) {
+                    err!("{:?}: unknown http destination {:?}", name, u)
+                }
+            }
+            Authorizer::AnyOf(..) | Authorizer::AllOf(..) => {
+                check_authorizer_refs(&cfg.authorizers, name,
+                    &mut Vec::new())?;
+            }
+            Authorizer::Jwt(ref jwt) => {
+                if jwt.secret.is_none() && jwt.jwks_file.is_none() {
+                    err!("{:?}: either `secret` or `jwks-file` \
+                          must be specified", name)
+                }
+            }
+            _ => {}
+        }
+    }
+    if cfg.listen.tls().len() > 0 && cfg.tls_certificates.len() == 0 {
+        err!("{} TLS sockets are listed in `listen` but no \
+              `tls-certificates` are configured", cfg.listen.tls().len());
+    }
+    if cfg.replication.listen.tls().len() > 0 {
+        return Err("TLS is not supported for replication, yet"
+            .to_string().into());
+    }
+    for (name, dest) in &cfg.http_destinations {
+        if dest.unix_socket().is_some() {
+            if cfg!(not(unix)) {
+                err!("{:?}: unix sockets are not supported \
+                      on this platform", name);
+            }
+            if dest.tls.is_some() {
+                err!("{:?}: TLS is not supported for unix sockets", name);
+            }
+            if dest.load_balancing != LoadBalancing::queue {
+                err!("{:?}: only `queue` load balancing is supported \
+                      for unix sockets", name);
+            }
+        } else if dest.addresses.iter().any(|a| a.starts_with("unix:")) {
+            err!("{:?}: unix socket must be the only address \
+                  of the destination", name);
+        }
+        if let Some(server_name) = dest.tls_server_name() {
+            if !tls::valid_server_name(server_name) {
+                err!("{:?}: {:?} is not a valid server name for TLS, \
+                      use `server-name` setting to specify one",
+                      name, server_name);
+            }
+        }
+    }
+    set_backend_request_timeouts(&mut cfg);
     // TODO: verify session_pool inactivity handlers
     for (name, s) in &cfg.session_pools {
+        if s.listen.tls().len() > 0 {
+            err!("{:?}: TLS is not supported for session pools, yet", name);
+        }
         for dest in &s.inactivity_handlers {
             if !cfg.http_destinations.contains_key(&dest.upstream) {
                 err!("{:?}: unknown http destination {:?}",
@@ -20,6 +20,7 @@ use crate::config::log;
 use crate::config::networks;
 use crate::config::disk::{self, Disk};
 use crate::config::replication::{self, Replication};
+use crate::config::tls;
 use crate::routing::RoutingTable;
 
 
@@ -61,6 +62,7 @@ pub struct ConfigSource {
     pub output_body_whole_timeout: Duration,
 
     pub routing: HashMap<HostPath, RouteDef>,
+    pub tls_certificates: HashMap<String, tls::CertificateSource>,
 
     pub handlers: HashMap<HandlerName, Handler>,
     pub authorizers: HashMap<AuthorizerName, Authorizer>,
@@ -102,6 +104,7 @@ pub struct ConfigData {
     pub output_body_whole_timeout: Duration,
 
     pub routing: RoutingTable,
+    pub tls_certificates: HashMap<String, Arc<tls::Certificate>>,
 
     pub handlers: HashMap<HandlerName, Handler>,
     pub authorizers: HashMap<AuthorizerName, Authorizer>,
@@ -167,6 +170,8 @@ pub fn config_validator<'a>() -> Structure<'a> {
     .member("output_body_whole_timeout", Scalar::new().default("1 hour"))
 
     .member("routing", routing::validator())
+    .member("tls_certificates",
+        Mapping::new(Scalar::new(), tls::validator()))
 
     .member("replication", replication::validator())
     .member("debug_routing", Scalar::new().default(false))
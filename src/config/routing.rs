@@ -1,11 +1,15 @@
+use std::fmt;
 use std::str::FromStr;
+use std::time::Duration;
+
+use humantime::parse_duration;
 use regex::Regex;
 
 use serde::de::{Deserializer, Deserialize};
 use quire::validate::{Mapping, Scalar};
 
 use crate::config::visitors::FromStrVisitor;
-use crate::intern::{HandlerName, Authorizer};
+use crate::intern::{HandlerName, Authorizer, LogFormatName};
 
 lazy_static! {
     static ref ROUTING_RE: Regex = Regex::new(
@@ -18,18 +22,51 @@ lazy_static! {
 pub struct RouteDef {
     pub handler: HandlerName,
     pub authorizer: Option<Authorizer>,
+    /// Log format set by `->name`
+    pub log: Option<LogFormatName>,
+    pub options: RouteOptions,
+}
+
+/// Per-route settings set by `key=value` in route definition
+///
+/// Unset values are inherited from the parent routes and then
+/// fall back to the settings of the handler.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct RouteOptions {
+    /// Overrides `max-payload-size` of the handler buffering request body
+    pub max_payload_size: Option<usize>,
+    /// Overrides `response-timeout` of the http destination
+    pub response_timeout: Option<Duration>,
+    /// Overrides `input-body-whole-timeout` of the server and of the http
+    /// destination
+    pub input_body_whole_timeout: Option<Duration>,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash)]
 pub struct Host(pub bool, pub String);
 
+/// Request method and headers required by the route, all must match
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Default)]
+pub struct Conditions {
+    pub methods: Vec<String>,
+    pub headers: Vec<Condition>,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Condition {
+    /// Header is present, and has the value if one is specified
+    Header(String, Option<String>),
+    /// Cookie is present, and has the value if one is specified
+    Cookie(String, Option<String>),
+}
+
 #[derive(Debug, PartialEq, Eq, Hash)]
-pub struct HostPath(pub Host, pub Option<String>);
+pub struct HostPath(pub Host, pub Option<String>, pub Conditions);
 
 impl<'a> Deserialize<'a> for HostPath {
     fn deserialize<D: Deserializer<'a>>(d: D) -> Result<Self, D::Error> {
         d.deserialize_str(FromStrVisitor::new(
-            "hostname or hostname/path"))
+            "[METHOD] hostname[/path] [conditions]"))
     }
 }
 
@@ -48,9 +85,79 @@ impl FromStr for Host {
 }
 
 
+impl Conditions {
+    pub fn is_empty(&self) -> bool {
+        self.methods.len() == 0 && self.headers.len() == 0
+    }
+}
+
+impl fmt::Display for Conditions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut sep = "";
+        if self.methods.len() > 0 {
+            write!(f, "{}", self.methods.join(","))?;
+            sep = " ";
+        }
+        for cond in &self.headers {
+            let (kind, name, value) = match *cond {
+                Condition::Header(ref n, ref v) => ("header", n, v),
+                Condition::Cookie(ref n, ref v) => ("cookie", n, v),
+            };
+            write!(f, "{}[{} {}", sep, kind, name)?;
+            if let Some(ref value) = *value {
+                write!(f, "={}", value)?;
+            }
+            write!(f, "]")?;
+            sep = " ";
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+    fn from_str(val: &str) -> Result<Condition, String> {
+        let mut parts = val.trim().splitn(2, char::is_whitespace);
+        let kind = parts.next().unwrap_or("");
+        let mut pair = parts.next().unwrap_or("").trim().splitn(2, '=');
+        let name = pair.next().unwrap_or("").trim();
+        let value = pair.next().map(|v| v.trim().to_string());
+        if name.len() == 0 {
+            return Err(format!("Condition {:?} has no name", val));
+        }
+        match kind {
+            "header" => Ok(Condition::Header(name.to_string(), value)),
+            "cookie" => Ok(Condition::Cookie(name.to_string(), value)),
+            _ => Err(format!("Unknown condition {:?}, \
+                              `header` or `cookie` expected", kind)),
+        }
+    }
+}
+
 impl FromStr for HostPath {
     type Err = String;
     fn from_str(val: &str) -> Result<HostPath, String> {
+        let mut conditions = Conditions::default();
+        let mut val = val.trim();
+        if let Some(i) = val.find(char::is_whitespace) {
+            let methods = &val[..i];
+            if methods.chars().all(|c| c.is_ascii_uppercase() || c == ',') {
+                conditions.methods = methods.split(',')
+                    .filter(|m| m.len() > 0)
+                    .map(|m| m.to_string())
+                    .collect();
+                val = val[i..].trim_start();
+            }
+        }
+        while val.ends_with(']') {
+            let start = val.rfind('[')
+                .ok_or_else(|| format!("Unmatched ] in {:?}", val))?;
+            conditions.headers.insert(0, val[start+1..val.len()-1].parse()?);
+            val = val[..start].trim_end();
+        }
+        if val.contains(char::is_whitespace) {
+            return Err(format!("Unexpected whitespace in {:?}", val));
+        }
         let (host, path) = if let Some(i) = val.find('/') {
             if &val[i..] == "/" {
                 (&val[..i], None)
@@ -64,7 +171,7 @@ impl FromStr for HostPath {
         } else {
             (val, None)
         };
-        Ok(HostPath(host.parse().unwrap(), path))
+        Ok(HostPath(host.parse().unwrap(), path, conditions))
     }
 }
 
@@ -75,7 +182,61 @@ pub fn validator<'x>() -> Mapping<'x> {
 
 impl<'a> Deserialize<'a> for RouteDef {
     fn deserialize<D: Deserializer<'a>>(d: D) -> Result<Self, D::Error> {
-        d.deserialize_str(FromStrVisitor::new("route [@authorizer]"))
+        d.deserialize_str(FromStrVisitor::new(
+            "route [@authorizer] [->log-format] [key=value]..."))
+    }
+}
+
+fn parse_size(val: &str) -> Result<usize, String> {
+    let (num, mult) = match val.chars().last() {
+        Some('k') | Some('K') => (&val[..val.len()-1], 1 << 10),
+        Some('M') => (&val[..val.len()-1], 1 << 20),
+        Some('G') => (&val[..val.len()-1], 1 << 30),
+        _ => (val, 1),
+    };
+    num.parse::<usize>().ok()
+        .and_then(|n| n.checked_mul(mult))
+        .ok_or_else(|| format!("Invalid size {:?}", val))
+}
+
+fn parse_timeout(val: &str) -> Result<Duration, String> {
+    parse_duration(val)
+        .map_err(|e| format!("Invalid timeout {:?}: {}", val, e))
+}
+
+impl RouteOptions {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "max-payload-size" => {
+                self.max_payload_size = Some(parse_size(value)?);
+            }
+            "response-timeout" => {
+                self.response_timeout = Some(parse_timeout(value)?);
+            }
+            "input-body-whole-timeout" => {
+                self.input_body_whole_timeout = Some(parse_timeout(value)?);
+            }
+            _ => return Err(format!("Unknown route option {:?}", key)),
+        }
+        Ok(())
+    }
+    /// Fills options which are not set from the parent route
+    pub fn inherit(&mut self, from: &RouteOptions) {
+        if self.max_payload_size.is_none() {
+            self.max_payload_size = from.max_payload_size;
+        }
+        if self.response_timeout.is_none() {
+            self.response_timeout = from.response_timeout;
+        }
+        if self.input_body_whole_timeout.is_none() {
+            self.input_body_whole_timeout = from.input_body_whole_timeout;
+        }
+    }
+    /// Returns true if there is nothing to inherit
+    pub fn is_complete(&self) -> bool {
+        self.max_payload_size.is_some() &&
+        self.response_timeout.is_some() &&
+        self.input_body_whole_timeout.is_some()
     }
 }
 
@@ -85,6 +246,9 @@ impl FromStr for RouteDef {
         let mut val = val.trim();
         let mut handler = None;
         let mut authorizer = None;
+        let mut log = None;
+        let mut options = RouteOptions::default();
+        let mut keys = Vec::new();
         while val.len() > 0 {
             if let Some(m) = ROUTING_RE.captures(val) {
                 if let Some(dest) = m.get(5) {
@@ -101,10 +265,21 @@ impl FromStr for RouteDef {
                     } else {
                         authorizer = Some(auth.as_str().parse().unwrap());
                     }
-                } else if let Some(_) = m.get(2) {
-                    panic!("Logs are not implemented yet");
+                } else if let Some(fmt) = m.get(2) {
+                    if let Some(old) = log {
+                        return Err(format!("Two log formats {:?} and {:?}",
+                            old, fmt.as_str()));
+                    } else {
+                        log = Some(fmt.as_str().parse().unwrap());
+                    }
                 } else if let Some(name) = m.get(3) {
-                    panic!("Key {:?} is not implemented yet", name);
+                    let name = name.as_str();
+                    if keys.contains(&name) {
+                        return Err(format!("Option {:?} is set twice",
+                            name));
+                    }
+                    keys.push(name);
+                    options.set(name, m.get(4).unwrap().as_str())?;
                 }
                 val = &val[m.get(0).unwrap().end()..];
             } else {
@@ -115,6 +290,8 @@ impl FromStr for RouteDef {
             return Ok(RouteDef {
                 handler: dest,
                 authorizer: authorizer,
+                log: log,
+                options: options,
             })
         } else {
             return Err(String::from("handler is required"));
@@ -125,14 +302,17 @@ impl FromStr for RouteDef {
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
+    use std::time::Duration;
     use string_intern::Symbol;
-    use super::RouteDef;
+    use super::{RouteDef, RouteOptions};
 
     #[test]
     fn parse_dest() {
         assert_eq!(RouteDef::from_str("handler").unwrap(), RouteDef {
             handler: Symbol::from("handler"),
             authorizer: None,
+            log: None,
+            options: RouteOptions::default(),
         });
     }
 
@@ -141,25 +321,70 @@ mod test {
         assert_eq!(RouteDef::from_str("handler@auth").unwrap(), RouteDef {
             handler: Symbol::from("handler"),
             authorizer: Some(Symbol::from("auth")),
+            log: None,
+            options: RouteOptions::default(),
         });
         assert_eq!(RouteDef::from_str("handler   @auth").unwrap(),
             RouteDef {
                 handler: Symbol::from("handler"),
                 authorizer: Some(Symbol::from("auth")),
+                log: None,
+                options: RouteOptions::default(),
             });
         assert_eq!(RouteDef::from_str("handler @auth").unwrap(), RouteDef {
             handler: Symbol::from("handler"),
             authorizer: Some(Symbol::from("auth")),
+            log: None,
+            options: RouteOptions::default(),
         });
     }
+
+    #[test]
+    fn parse_log_and_options() {
+        assert_eq!(
+            RouteDef::from_str("handler ->access max-payload-size=2k")
+                .unwrap(),
+            RouteDef {
+                handler: Symbol::from("handler"),
+                authorizer: None,
+                log: Some(Symbol::from("access")),
+                options: RouteOptions {
+                    max_payload_size: Some(2048),
+                    response_timeout: None,
+                    input_body_whole_timeout: None,
+                },
+            });
+        assert_eq!(
+            RouteDef::from_str("handler response-timeout=5m").unwrap()
+                .options.response_timeout,
+            Some(Duration::from_secs(300)));
+        assert_eq!(
+            RouteDef::from_str("handler input-body-whole-timeout=10min")
+                .unwrap().options.input_body_whole_timeout,
+            Some(Duration::from_secs(600)));
+        assert_eq!(
+            RouteDef::from_str("handler @auth ->access").unwrap(),
+            RouteDef {
+                handler: Symbol::from("handler"),
+                authorizer: Some(Symbol::from("auth")),
+                log: Some(Symbol::from("access")),
+                options: RouteOptions::default(),
+            });
+        assert!(RouteDef::from_str("handler ->a ->b").is_err());
+        assert!(RouteDef::from_str("handler unknown-key=1").is_err());
+        assert!(RouteDef::from_str("handler max-payload-size=x").is_err());
+        assert!(RouteDef::from_str("handler response-timeout=5").is_err());
+        assert!(RouteDef::from_str(
+            "handler max-payload-size=1 max-payload-size=2").is_err());
+    }
 }
 
 #[cfg(test)]
 mod parse_test {
-    use super::{HostPath, Host};
+    use super::{HostPath, Host, Conditions, Condition};
 
     fn parse_host_path(s: String) -> (Host, Option<String>) {
-        let HostPath(host, path) = s.parse().unwrap();
+        let HostPath(host, path, _) = s.parse().unwrap();
         return (host, path);
     }
 
@@ -206,4 +431,28 @@ mod parse_test {
         assert!(path.is_none());
     }
 
+    #[test]
+    fn conditions() {
+        let HostPath(host, path, cond) = "POST,PUT example.com/upload"
+            .parse().unwrap();
+        assert_eq!(host, Host(false, "example.com".into()));
+        assert_eq!(path, Some("/upload".into()));
+        assert_eq!(cond, Conditions {
+            methods: vec!["POST".into(), "PUT".into()],
+            headers: vec![],
+        });
+
+        let HostPath(_, path, cond) =
+            "example.com [header X-Canary] [cookie beta=yes]"
+            .parse().unwrap();
+        assert!(path.is_none());
+        assert_eq!(cond.headers, vec![
+            Condition::Header("X-Canary".into(), None),
+            Condition::Cookie("beta".into(), Some("yes".into())),
+        ]);
+        assert_eq!(cond.to_string(), "[header X-Canary] [cookie beta=yes]");
+
+        assert!("example.com [query x]".parse::<HostPath>().is_err());
+        assert!("get example.com".parse::<HostPath>().is_err());
+    }
 }
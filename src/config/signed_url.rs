@@ -0,0 +1,17 @@
+use quire::validate::{Structure, Sequence, Scalar};
+
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct SignedUrl {
+    /// Any of the secrets is accepted, so they can be rotated
+    pub secrets: Vec<String>,
+    pub signature_param: String,
+    pub expires_param: String,
+}
+
+pub fn authorizer_validator<'x>() -> Structure<'x> {
+    Structure::new()
+    .member("secrets", Sequence::new(Scalar::new()).min_length(1))
+    .member("signature_param", Scalar::new().default("signature"))
+    .member("expires_param", Scalar::new().default("expires"))
+}
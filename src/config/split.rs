@@ -0,0 +1,31 @@
+use quire::validate::{Structure, Sequence, Scalar, Numeric, Enum, Nothing};
+
+use crate::config::http_destinations::HashKey;
+use crate::intern::HandlerName;
+
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct Split {
+    pub variants: Vec<Variant>,
+    /// Variant is chosen randomly for each request if not specified
+    pub sticky: Option<HashKey>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct Variant {
+    pub handler: HandlerName,
+    pub weight: u32,
+}
+
+pub fn validator<'x>() -> Structure<'x> {
+    Structure::new()
+    .member("variants", Sequence::new(Structure::new()
+        .member("handler", Scalar::new())
+        .member("weight", Numeric::new().min(0).max(1_000_000)))
+        .min_length(1))
+    .member("sticky", Enum::new()
+        .option("Header", Scalar::new())
+        .option("Cookie", Scalar::new())
+        .option("ClientIp", Nothing)
+        .optional())
+}
@@ -0,0 +1,19 @@
+use quire::validate::{Structure, Scalar, Sequence};
+
+use crate::config::http;
+
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct Subrequest {
+    pub destination: http::Destination,
+    pub ip_header: Option<String>,
+    /// Headers of successful response that are forwarded by `!Proxy`
+    pub copy_headers: Vec<String>,
+}
+
+pub fn authorizer_validator<'x>() -> Structure<'x> {
+    Structure::new()
+    .member("destination", http::destination_validator())
+    .member("ip_header", Scalar::new().optional())
+    .member("copy_headers", Sequence::new(Scalar::new()))
+}
@@ -0,0 +1,91 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use quire::validate::{Structure, Scalar};
+use rustls::{Certificate as Cert, PrivateKey};
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::sign;
+
+
+/// Certificate as written in config file
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct CertificateSource {
+    pub certificate: PathBuf,
+    pub private_key: PathBuf,
+}
+
+/// Certificate with the chain and the private key read from disk
+///
+/// We keep the contents in the config, so that the config is considered
+/// changed when certificate is renewed, and listeners are updated.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Certificate {
+    pub source: CertificateSource,
+    pub chain: Vec<Cert>,
+    pub key: PrivateKey,
+}
+
+/// Files of the TLS settings of http destination read from disk
+///
+/// Kept in the config for the same reason as `Certificate`, so that
+/// connection pools are recreated when files are changed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ClientFiles {
+    /// Empty if bundled root certificates are used
+    pub ca_bundle: Vec<Cert>,
+    pub client_certificate: Option<Certificate>,
+}
+
+pub fn validator<'x>() -> Structure<'x> {
+    Structure::new()
+    .member("certificate", Scalar::new())
+    .member("private_key", Scalar::new())
+}
+
+pub fn read_chain(path: &Path) -> Result<Vec<Cert>, String> {
+    let f = File::open(path)
+        .map_err(|e| format!("can't open {:?}: {}", path, e))?;
+    let chain = certs(&mut BufReader::new(f))
+        .map_err(|()| format!("can't parse certificates in {:?}", path))?;
+    if chain.len() == 0 {
+        return Err(format!("no certificates found in {:?}", path));
+    }
+    Ok(chain)
+}
+
+fn read_key(path: &Path) -> Result<PrivateKey, String> {
+    let f = File::open(path)
+        .map_err(|e| format!("can't open {:?}: {}", path, e))?;
+    let mut keys = pkcs8_private_keys(&mut BufReader::new(f))
+        .map_err(|()| format!("can't parse private key in {:?}", path))?;
+    if keys.len() == 0 {
+        let f = File::open(path)
+            .map_err(|e| format!("can't open {:?}: {}", path, e))?;
+        keys = rsa_private_keys(&mut BufReader::new(f))
+            .map_err(|()| format!("can't parse private key in {:?}", path))?;
+    }
+    match keys.len() {
+        0 => Err(format!("no private key found in {:?}", path)),
+        1 => Ok(keys.pop().unwrap()),
+        _ => Err(format!("multiple private keys found in {:?}", path)),
+    }
+}
+
+impl CertificateSource {
+    pub fn files(&self) -> Vec<&Path> {
+        vec![&self.certificate, &self.private_key]
+    }
+    pub fn load(&self) -> Result<Certificate, String> {
+        let key = read_key(&self.private_key)?;
+        sign::RSASigningKey::new(&key)
+            .map_err(|()| format!("unsupported private key type in {:?}, \
+                                   only RSA keys are supported",
+                                  self.private_key))?;
+        Ok(Certificate {
+            source: self.clone(),
+            chain: read_chain(&self.certificate)?,
+            key: key,
+        })
+    }
+}
@@ -1,8 +1,10 @@
 use std::fmt;
 use std::marker::PhantomData;
 use std::str::FromStr;
+use std::time::Duration;
 
-use serde::de;
+use humantime::parse_duration;
+use serde::de::{self, Deserialize, Deserializer};
 
 
 pub struct FromStrVisitor<T>(&'static str, PhantomData<T>)
@@ -27,3 +29,14 @@ impl<'a, T: FromStr> de::Visitor<'a> for FromStrVisitor<T>
         s.parse().map_err(|e| E::custom(e))
     }
 }
+
+/// Deserializes duration which may be null, e.g. `30 secs`
+pub fn optional_duration<'a, D>(d: D) -> Result<Option<Duration>, D::Error>
+    where D: Deserializer<'a>
+{
+    match Option::<String>::deserialize(d)? {
+        Some(val) => parse_duration(&val).map(Some)
+            .map_err(|e| de::Error::custom(e)),
+        None => Ok(None),
+    }
+}
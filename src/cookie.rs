@@ -0,0 +1,36 @@
+//! Parsing of the `Cookie` request header
+use std::str::from_utf8;
+
+
+/// Returns value of the first cookie named `name`
+///
+/// `headers` are values of all `Cookie` headers of the request.
+pub fn cookie<'x, I>(headers: I, name: &str) -> Option<&'x str>
+    where I: IntoIterator<Item=&'x [u8]>
+{
+    headers.into_iter()
+    .filter_map(|value| from_utf8(value).ok())
+    .flat_map(|value| value.split(';'))
+    .filter_map(|pair| {
+        let mut pair = pair.splitn(2, '=');
+        match (pair.next(), pair.next()) {
+            (Some(k), Some(v)) if k.trim() == name => Some(v.trim()),
+            _ => None,
+        }
+    })
+    .next()
+}
+
+#[cfg(test)]
+mod test {
+    use super::cookie;
+
+    #[test]
+    fn parse() {
+        let headers: &[&[u8]] = &[b"a=1; uid=12345", b"uid=2;b= x "];
+        assert_eq!(cookie(headers.iter().cloned(), "uid"), Some("12345"));
+        assert_eq!(cookie(headers.iter().cloned(), "b"), Some("x"));
+        assert_eq!(cookie(headers.iter().cloned(), "id"), None);
+        assert_eq!(cookie(Vec::<&[u8]>::new(), "uid"), None);
+    }
+}
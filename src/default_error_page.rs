@@ -22,10 +22,32 @@ pub fn serve_error_page<S: 'static, C: IntoContext>(status: Status, ctx: C)
     reply(ctx, move |e| Box::new(error_page(status, e)))
 }
 
+/// Serves 401 page with the `WWW-Authenticate` header
+pub fn serve_unauthorized<S: 'static, C: IntoContext>(challenge: String,
+    ctx: C)
+    -> Request<S>
+{
+    reply(ctx, move |e| Box::new(unauthorized_page(&challenge, e)))
+}
+
+pub fn unauthorized_page<S: 'static>(challenge: &str, mut e: Encoder<S>)
+    -> FutureResult<EncoderDone<S>, Error>
+{
+    e.status(Status::Unauthorized);
+    e.add_header("WWW-Authenticate", challenge);
+    page_body(Status::Unauthorized, e)
+}
+
 pub fn error_page<S: 'static>(status: Status, mut e: Encoder<S>)
     -> FutureResult<EncoderDone<S>, Error>
 {
     e.status(status);
+    page_body(status, e)
+}
+
+fn page_body<S: 'static>(status: Status, mut e: Encoder<S>)
+    -> FutureResult<EncoderDone<S>, Error>
+{
     if status.response_has_body() {
         let status_var = StatusVar(status);
         let mut ctx = Context::new();
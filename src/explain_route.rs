@@ -0,0 +1,97 @@
+//! Implementation of the `--explain-route` command-line option
+use crate::config::Config;
+use crate::routing::{route, explain, parse_host, RequestInfo, Route};
+
+
+/// Request described by command-line arguments
+struct FakeRequest {
+    method: String,
+    headers: Vec<(String, String)>,
+}
+
+impl RequestInfo for FakeRequest {
+    fn method(&self) -> &str {
+        &self.method
+    }
+    fn header(&self, name: &str) -> Vec<&[u8]> {
+        self.headers.iter()
+            .filter(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| v.as_bytes())
+            .collect()
+    }
+}
+
+fn parse_header(header: &str) -> Result<(String, String), String> {
+    let mut pair = header.splitn(2, ':');
+    match (pair.next(), pair.next()) {
+        (Some(name), Some(value)) if name.trim().len() > 0 => {
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!("Invalid header {:?}, `Name: value` expected",
+                         header)),
+    }
+}
+
+fn describe(route: &Route) -> String {
+    let mut result = format!("{} @{}", route.handler_name,
+                             route.authorizer_name);
+    if let Some(ref log) = route.log_format {
+        result.push_str(&format!(" ->{}", log));
+    }
+    if !route.conditions.is_empty() {
+        result.push_str(&format!(" ({})", route.conditions));
+    }
+    result
+}
+
+/// Prints how request to `target` (like `example.com/some/path`) is routed
+pub fn explain_route(cfg: &Config, target: &str, method: &str,
+    headers: &[String])
+    -> Result<(), String>
+{
+    let request = FakeRequest {
+        method: method.to_uppercase(),
+        headers: headers.iter().map(|h| parse_header(h))
+            .collect::<Result<_, _>>()?,
+    };
+    let (host, path) = match target.find('/') {
+        Some(idx) => (&target[..idx], &target[idx..]),
+        None => (target, "/"),
+    };
+    let host = parse_host(host);
+
+    let exp = explain(host, path, &request, &cfg.routing);
+    match exp.host {
+        Some(name) => println!("Host: {} (matches {:?})", host, name),
+        None => println!("Host: {} (no matching host)", host),
+    }
+    if exp.candidates.len() > 0 {
+        println!("Candidates:");
+    }
+    for (rpath, route, reason) in exp.candidates {
+        println!("  {:<30} {:<30} {}", rpath, describe(route),
+                 reason.unwrap_or("<- matched"));
+    }
+    match route(host, path, &request, &cfg.routing) {
+        Some((route, prefix, suffix, captures)) => {
+            println!("Handler: {}", route.handler_name);
+            for variant in &route.variants {
+                println!("  variant: {} (weight {})",
+                         variant.name, variant.weight);
+            }
+            println!("Authorizer: {}", route.authorizer_name);
+            if let Some(ref log) = route.log_format {
+                println!("Log format: {}", log);
+            }
+            println!("Prefix: {:?}", prefix);
+            println!("Suffix: {:?}", suffix);
+            for (name, value) in captures.iter() {
+                println!("Capture {{{}}}: {:?}", name, value);
+            }
+        }
+        None => {
+            println!("No route, request is served with 404 Not Found");
+        }
+    }
+    Ok(())
+}
@@ -1,3 +1,9 @@
+use std::path::{Path, PathBuf};
+use std::str::from_utf8;
+
+use crate::routing::Captures;
+
+
 pub fn decode_component(buf: &mut Vec<u8>, component: &str) -> Result<(), ()>
 {
     let mut chariter = component.as_bytes().iter();
@@ -26,3 +32,30 @@ fn from_hex(b: u8) -> Result<u8, ()> {
         _ => Err(())
     }
 }
+
+/// Substitutes groups captured by the route pattern into file path
+///
+/// Values are percent-decoded and must not refer to a parent directory.
+pub fn expand_path(template: &Path, captures: &Captures)
+    -> Result<PathBuf, ()>
+{
+    let template = match template.to_str() {
+        Some(template) if template.contains('{') => template,
+        _ => return Ok(template.to_path_buf()),
+    };
+    captures.expand_with(template, |value| {
+        let mut buf = Vec::with_capacity(value.len());
+        for (idx, cmp) in value.split('/').enumerate() {
+            let start = buf.len();
+            if idx > 0 {
+                buf.push(b'/');
+            }
+            decode_component(&mut buf, cmp)?;
+            match &buf[start..] {
+                b"" | b"/" | b"." | b"/." | b".." | b"/.." => return Err(()),
+                _ => {}
+            }
+        }
+        from_utf8(&buf).map(|x| x.to_string()).map_err(|_| ())
+    }).map(PathBuf::from)
+}
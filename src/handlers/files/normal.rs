@@ -9,7 +9,7 @@ use http_file_headers::{Input as HeadersInput, Output};
 use crate::config::static_files::{Static, Mode};
 use crate::default_error_page::{serve_error_page};
 use crate::incoming::{Input, Request, Transport};
-use crate::handlers::files::decode::decode_component;
+use crate::handlers::files::decode::{decode_component, expand_path};
 use crate::handlers::files::pools::get_pool;
 use crate::handlers::files::common::{reply_file, NotFile};
 use crate::handlers::files::index::generate_index;
@@ -130,5 +130,5 @@ pub fn path(settings: &Static, inp: &Input) -> Result<PathBuf, ()> {
 
     // only valid utf-8 supported so far
     let utf8 = from_utf8(&buf).map_err(|_| ())?;
-    Ok(settings.path.join(utf8))
+    Ok(expand_path(&settings.path, &inp.captures)?.join(utf8))
 }
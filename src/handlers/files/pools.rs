@@ -65,6 +65,12 @@ impl DiskPools {
             meter: meter.clone(),
         })))
     }
+    /// Pool used when disk pool is not specified
+    ///
+    /// It's also used for other blocking work, like checking passwords.
+    pub fn default_pool(&self) -> CpuPool {
+        self.0.read().expect("readlock for pools").default.clone()
+    }
     pub fn update(&self, config: &HashMap<DiskPoolName, config::Disk>) {
         let pools = &mut *self.0.write().expect("writelock for pools");
         for (name, props) in config {
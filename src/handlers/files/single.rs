@@ -9,6 +9,7 @@ use crate::default_error_page::{serve_error_page};
 use crate::incoming::{Input, Request, Transport};
 use crate::handlers::files::pools::get_pool;
 use crate::handlers::files::common::{reply_file, NotFile};
+use crate::handlers::files::decode::expand_path;
 
 
 pub fn serve_file<S: Transport>(settings: &Arc<SingleFile>, mut inp: Input)
@@ -18,21 +19,24 @@ pub fn serve_file<S: Transport>(settings: &Arc<SingleFile>, mut inp: Input)
         // Star or authority
         return serve_error_page(Status::Forbidden, inp);
     };
-    inp.debug.set_fs_path(&settings.path);
+    let path = match expand_path(&settings.path, &inp.captures) {
+        Ok(path) => path,
+        Err(()) => return serve_error_page(Status::Forbidden, inp),
+    };
+    inp.debug.set_fs_path(&path);
     let pool = get_pool(&inp.runtime, &settings.pool);
     let settings = settings.clone();
-    let settings2 = settings.clone();
 
     let hinp = HeadersInput::from_headers(&settings.headers_config,
         inp.headers.method(), inp.headers.headers());
     let fut = pool.spawn_fn(move || {
-        hinp.probe_file(&settings2.path)
+        hinp.probe_file(&path)
         .map(|x| (x, ()))
         .map_err(|e| {
             if e.kind() == io::ErrorKind::PermissionDenied {
                 (NotFile::Status(Status::Forbidden), ())
             } else {
-                error!("Error reading file {:?}: {}", settings2.path, e);
+                error!("Error reading file {:?}: {}", path, e);
                 (NotFile::Status(Status::InternalServerError), ())
             }
         })
@@ -1,14 +1,15 @@
 use std::sync::Arc;
 use crate::proxy::frontend::Codec;
+use crate::proxy::websocket;
 
 use tk_http::Status;
 use tk_http::server::RequestTarget::Authority;
 use crate::config::proxy::Proxy;
-use crate::incoming::{Request, Input};
+use crate::incoming::{Request, Input, Transport};
 use crate::default_error_page::serve_error_page;
 
 
-pub fn serve<S: 'static>(settings: &Arc<Proxy>, inp: Input)
+pub fn serve<S: Transport>(settings: &Arc<Proxy>, inp: Input)
     -> Request<S>
 {
     if inp.headers.host().is_none() {
@@ -19,5 +20,9 @@ pub fn serve<S: 'static>(settings: &Arc<Proxy>, inp: Input)
         // Can't proxy without Host
         return serve_error_page(Status::BadRequest, inp)
     }
-    Box::new(Codec::new(settings, inp))
+    match inp.headers.get_websocket_upgrade() {
+        Ok(Some(ws)) => websocket::serve(settings, ws.accept, inp),
+        Ok(None) => Box::new(Codec::new(settings, inp)),
+        Err(()) => serve_error_page(Status::BadRequest, inp),
+    }
 }
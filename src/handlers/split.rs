@@ -0,0 +1,201 @@
+//! Chooses one of the handlers of the `!Split` by weight
+use std::net::IpAddr;
+
+use libcantal::{Collection, Visitor};
+use rand::{thread_rng, Rng};
+
+use crate::balancer;
+use crate::config::Handler;
+use crate::config::http_destinations::HashKey;
+use crate::config::split::Split;
+use crate::cookie::cookie;
+use crate::intern::HandlerName;
+use crate::metrics::{Counter, Metric, Registry, Shared};
+use crate::routing::RequestInfo;
+
+
+lazy_static! {
+    static ref VARIANT_METRICS: Registry<(HandlerName, HandlerName), Counter>
+        = Registry::new();
+}
+
+/// Requests served by the variant, keyed by split and variant name
+type VariantMetrics = Shared<(HandlerName, HandlerName), Counter>;
+
+/// Handler referenced by the `!Split`, resolved with the routing table
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub name: HandlerName,
+    pub handler: Handler,
+    pub weight: u32,
+    metrics: VariantMetrics,
+}
+
+pub struct Metrics;
+
+impl PartialEq for Variant {
+    fn eq(&self, other: &Variant) -> bool {
+        self.name == other.name && self.handler == other.handler &&
+            self.weight == other.weight
+    }
+}
+
+impl Eq for Variant {}
+
+impl Variant {
+    pub fn new(split: &HandlerName, name: &HandlerName, handler: Handler,
+        weight: u32)
+        -> Variant
+    {
+        let metrics = VARIANT_METRICS.get(&(split.clone(), name.clone()),
+                                          Counter::new);
+        Variant {
+            name: name.clone(),
+            handler: handler,
+            weight: weight,
+            metrics: metrics,
+        }
+    }
+}
+
+impl Collection for Metrics {
+    fn visit<'x>(&'x self, v: &mut dyn Visitor<'x>) {
+        for ((split, name), requests) in VARIANT_METRICS.used() {
+            let group = format!("frontend.split.{}.{}", split, name);
+            v.metric(&Metric(&group, "requests"), requests);
+        }
+    }
+}
+
+pub fn metrics() -> Metrics {
+    Metrics
+}
+
+/// Returns hash of the sticky key of the request
+///
+/// `addr` is the address of the peer, `X-Forwarded-For` or similar headers
+/// are not taken into account, as the split is chosen before authorization.
+fn sticky_key<R: RequestInfo>(key: &HashKey, request: &R, addr: IpAddr)
+    -> Option<u64>
+{
+    match *key {
+        HashKey::ClientIp => Some(balancer::hash(addr)),
+        HashKey::Header(ref name) => {
+            request.header(name).first().map(|v| balancer::hash(v))
+        }
+        HashKey::Cookie(ref name) => {
+            cookie(request.header("Cookie"), name)
+            .map(|v| balancer::hash(v.as_bytes()))
+        }
+    }
+}
+
+/// Chooses a variant for the request
+///
+/// Requests having the same sticky key get the same variant as long as
+/// weights are not changed. Returns `None` if all weights are zero.
+pub fn select<'x, R: RequestInfo>(settings: &Split, variants: &'x [Variant],
+    request: &R, addr: IpAddr)
+    -> Option<&'x Variant>
+{
+    let total = variants.iter().map(|v| v.weight as u64).sum::<u64>();
+    if total == 0 {
+        return None;
+    }
+    let key = settings.sticky.as_ref()
+        .and_then(|key| sticky_key(key, request, addr))
+        .unwrap_or_else(|| thread_rng().gen());
+    let mut point = key % total;
+    for variant in variants {
+        if point < variant.weight as u64 {
+            variant.metrics.incr(1);
+            return Some(variant);
+        }
+        point -= variant.weight as u64;
+    }
+    unreachable!();
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use crate::config::Handler;
+    use crate::config::http_destinations::HashKey;
+    use crate::config::split::{Split, Variant as VariantDef};
+    use crate::intern::HandlerName;
+    use crate::routing::RequestInfo;
+    use super::{Variant, select};
+
+    struct Req(Vec<(&'static str, &'static str)>);
+
+    impl RequestInfo for Req {
+        fn method(&self) -> &str {
+            "GET"
+        }
+        fn header(&self, name: &str) -> Vec<&[u8]> {
+            self.0.iter()
+                .filter(|&&(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|&(_, v)| v.as_bytes())
+                .collect()
+        }
+    }
+
+    fn split(weights: &[(&'static str, u32)], sticky: Option<HashKey>)
+        -> (Split, Vec<Variant>)
+    {
+        let name = HandlerName::from("test-split");
+        let cfg = Split {
+            variants: weights.iter().map(|&(n, w)| VariantDef {
+                handler: HandlerName::from(n),
+                weight: w,
+            }).collect(),
+            sticky: sticky,
+        };
+        let variants = weights.iter().map(|&(n, w)| {
+            Variant::new(&name, &HandlerName::from(n), Handler::HttpBin, w)
+        }).collect();
+        (cfg, variants)
+    }
+
+    fn addr(x: &str) -> IpAddr {
+        x.parse().unwrap()
+    }
+
+    #[test]
+    fn weights() {
+        let (cfg, vars) = split(&[("a", 0), ("b", 3), ("c", 0)], None);
+        for _ in 0..10 {
+            let v = select(&cfg, &vars, &Req(vec![]), addr("127.0.0.1"));
+            assert_eq!(&v.unwrap().name[..], "b");
+        }
+        let (cfg, vars) = split(&[("a", 0)], None);
+        assert!(select(&cfg, &vars, &Req(vec![]), addr("127.0.0.1"))
+                .is_none());
+    }
+
+    #[test]
+    fn sticky_cookie() {
+        let (cfg, vars) = split(&[("a", 1), ("b", 1)],
+                                Some(HashKey::Cookie("uid".into())));
+        let req = Req(vec![("Cookie", "x=1; uid=12345")]);
+        let first = select(&cfg, &vars, &req, addr("127.0.0.1")).unwrap();
+        for i in 0..10 {
+            let ip = addr(&format!("10.0.0.{}", i));
+            assert_eq!(select(&cfg, &vars, &req, ip).unwrap().name,
+                       first.name);
+        }
+    }
+
+    #[test]
+    fn sticky_ip() {
+        let (cfg, vars) = split(&[("a", 1), ("b", 1)],
+                                Some(HashKey::ClientIp));
+        let first = select(&cfg, &vars, &Req(vec![]), addr("10.1.2.3"))
+            .unwrap();
+        for _ in 0..10 {
+            assert_eq!(select(&cfg, &vars, &Req(vec![]), addr("10.1.2.3"))
+                       .unwrap().name, first.name);
+        }
+    }
+}
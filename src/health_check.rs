@@ -0,0 +1,341 @@
+//! Active health checks of http destinations
+//!
+//! Every resolved address is checked periodically by a separate connection.
+//! Address streams of connection pools are filtered so that unhealthy
+//! addresses are taken out of rotation.
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use abstract_ns::Address;
+use futures::{Async, Future, Sink, Stream, Poll};
+use futures::future::{Either, ok, poll_fn};
+use futures::sync::mpsc::{unbounded, UnboundedSender, UnboundedReceiver};
+use futures::sync::oneshot::{self, channel as oneshot_channel, Sender};
+use tk_http::Version;
+use tk_http::client::{self as http, Proto};
+use tokio_core::reactor::{Handle, Interval, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+use void::Void;
+
+use crate::balancer::{AddrMetricsRef, addr_metrics};
+use crate::config::http_destinations::HealthCheck;
+use crate::http_pools::HttpFuture;
+use crate::intern::Upstream;
+use crate::metrics::{Counter, Integer};
+
+lazy_static! {
+    pub static ref CHECKS: Counter = Counter::new();
+    pub static ref CHECK_FAILURES: Counter = Counter::new();
+    pub static ref UNHEALTHY: Integer = Integer::new();
+}
+
+/// Single check: status code returned by backend or an error message
+pub type CheckFuture = Box<dyn Future<Item=u16, Error=String>>;
+
+struct AddrHealth {
+    healthy: bool,
+    /// Number of consecutive checks contradicting current state
+    counter: u32,
+    metrics: AddrMetricsRef,
+}
+
+/// Health of all addresses of a destination
+pub struct Health {
+    name: Upstream,
+    settings: HealthCheck,
+    addresses: Mutex<HashMap<SocketAddr, AddrHealth>>,
+    subscribers: Mutex<Vec<UnboundedSender<()>>>,
+}
+
+/// Address stream where unhealthy addresses are filtered out
+///
+/// If all addresses are unhealthy, all of them are used, as there is
+/// no better option anyway.
+pub struct HealthyAddresses<S> {
+    addresses: S,
+    changes: UnboundedReceiver<()>,
+    health: Arc<Health>,
+    current: Option<Address>,
+}
+
+/// Keeps health checks running, stops them when dropped
+pub struct Checker {
+    #[allow(dead_code)]
+    shutdown: Sender<()>,
+}
+
+/// Codec for the health check request, response body is ignored
+pub struct CheckCodec {
+    path: String,
+    host: String,
+    sender: Option<oneshot::Sender<u16>>,
+}
+
+impl Health {
+    pub fn new(name: &Upstream, settings: &HealthCheck) -> Arc<Health> {
+        Arc::new(Health {
+            name: name.clone(),
+            settings: settings.clone(),
+            addresses: Mutex::new(HashMap::new()),
+            subscribers: Mutex::new(Vec::new()),
+        })
+    }
+    pub fn filter<S>(self: &Arc<Self>, addresses: S) -> HealthyAddresses<S>
+        where S: Stream<Item=Address, Error=Void>
+    {
+        let (tx, rx) = unbounded();
+        self.subscribers.lock().expect("health not poisoned").push(tx);
+        HealthyAddresses {
+            addresses: addresses,
+            changes: rx,
+            health: self.clone(),
+            current: None,
+        }
+    }
+    fn is_healthy(&self, addr: &SocketAddr) -> bool {
+        self.addresses.lock().expect("health not poisoned")
+            .get(addr).map(|x| x.healthy).unwrap_or(true)
+    }
+    fn set_addresses(&self, addresses: &HashSet<SocketAddr>) {
+        let mut all = self.addresses.lock().expect("health not poisoned");
+        all.retain(|addr, state| {
+            let keep = addresses.contains(addr);
+            if !keep && !state.healthy {
+                UNHEALTHY.decr(1);
+                state.metrics.healthy.set(1);
+            }
+            keep
+        });
+        for addr in addresses {
+            all.entry(*addr).or_insert_with(|| AddrHealth {
+                healthy: true,
+                counter: 0,
+                metrics: addr_metrics(&self.name, *addr),
+            });
+        }
+    }
+    fn report(&self, addr: SocketAddr, result: Result<u16, String>) {
+        CHECKS.incr(1);
+        let ok = match result {
+            Ok(status) if status == self.settings.expected_status => true,
+            Ok(status) => {
+                debug!("{}: Health check of {} returned status {}",
+                    self.name, addr, status);
+                false
+            }
+            Err(e) => {
+                debug!("{}: Health check of {} failed: {}",
+                    self.name, addr, e);
+                false
+            }
+        };
+        let changed = {
+            let mut all = self.addresses.lock().expect("health not poisoned");
+            let state = match all.get_mut(&addr) {
+                Some(state) => state,
+                None => return,  // address is already removed
+            };
+            if !ok {
+                CHECK_FAILURES.incr(1);
+                state.metrics.health_check_failures.incr(1);
+            }
+            if ok == state.healthy {
+                state.counter = 0;
+                false
+            } else {
+                state.counter += 1;
+                let threshold = if ok {
+                    self.settings.rise
+                } else {
+                    self.settings.fall
+                };
+                if state.counter >= threshold {
+                    state.healthy = ok;
+                    state.counter = 0;
+                    if ok {
+                        info!("{}: Address {} is healthy again",
+                            self.name, addr);
+                        UNHEALTHY.decr(1);
+                        state.metrics.healthy.set(1);
+                    } else {
+                        warn!("{}: Address {} is unhealthy", self.name, addr);
+                        UNHEALTHY.incr(1);
+                        state.metrics.healthy.set(0);
+                    }
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+        if changed {
+            self.subscribers.lock().expect("health not poisoned")
+                .retain(|s| s.unbounded_send(()).is_ok());
+        }
+    }
+}
+
+impl Drop for Health {
+    fn drop(&mut self) {
+        let all = self.addresses.lock().expect("health not poisoned");
+        for state in all.values() {
+            if !state.healthy {
+                UNHEALTHY.decr(1);
+                state.metrics.healthy.set(1);
+            }
+        }
+    }
+}
+
+impl<S: Stream<Item=Address, Error=Void>> Stream for HealthyAddresses<S> {
+    type Item = Address;
+    type Error = Void;
+    fn poll(&mut self) -> Poll<Option<Address>, Void> {
+        let mut changed = false;
+        loop {
+            match self.addresses.poll()? {
+                Async::Ready(Some(addr)) => {
+                    self.current = Some(addr);
+                    changed = true;
+                }
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => break,
+            }
+        }
+        while let Ok(Async::Ready(Some(()))) = self.changes.poll() {
+            changed = true;
+        }
+        match self.current {
+            Some(ref addr) if changed => {
+                let all = addr.at(0).addresses().collect::<Vec<_>>();
+                let healthy = all.iter()
+                    .filter(|a| self.health.is_healthy(a))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let result = if healthy.len() > 0 { healthy } else { all };
+                Ok(Async::Ready(Some(result.into_iter().collect())))
+            }
+            _ => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// Starts checking every address yielded by `addresses` stream
+pub fn spawn_checker<S>(health: &Arc<Health>, addresses: S,
+    check: Box<dyn Fn(SocketAddr) -> CheckFuture>, handle: &Handle)
+    -> Checker
+    where S: Stream<Item=Address, Error=Void> + 'static
+{
+    let (tx, rx) = oneshot_channel();
+    let health = health.clone();
+    let check = Rc::new(check);
+    let h1 = handle.clone();
+    let name = health.name.clone();
+    let mut running = HashMap::<SocketAddr, Sender<()>>::new();
+    handle.spawn(addresses
+        .for_each(move |addr| {
+            let addresses = addr.at(0).addresses().collect::<HashSet<_>>();
+            health.set_addresses(&addresses);
+            // dropping the sender stops checks of removed address
+            running.retain(|addr, _| addresses.contains(addr));
+            for addr in addresses {
+                if !running.contains_key(&addr) {
+                    let stop = spawn_address(addr, &health, &check, &h1);
+                    running.insert(addr, stop);
+                }
+            }
+            Ok(())
+        })
+        .select(rx.then(|_| Ok::<(), Void>(())))
+        .map(move |_| debug!("{}: Health checks stopped", name))
+        .map_err(|_| unreachable!()));
+    Checker { shutdown: tx }
+}
+
+fn spawn_address(addr: SocketAddr, health: &Arc<Health>,
+    check: &Rc<Box<dyn Fn(SocketAddr) -> CheckFuture>>, handle: &Handle)
+    -> Sender<()>
+{
+    let (tx, rx) = oneshot_channel();
+    let health = health.clone();
+    let check = check.clone();
+    let h1 = handle.clone();
+    handle.spawn(Interval::new(health.settings.interval, &handle)
+        .expect("interval created")
+        .map_err(|e| error!("Interval error: {}", e))
+        .for_each(move |()| {
+            let health = health.clone();
+            let timeout = Timeout::new(health.settings.timeout, &h1)
+                .expect("timeout created");
+            check(addr)
+            .select2(timeout)
+            .then(move |res| {
+                let res = match res {
+                    Ok(Either::A((status, _))) => Ok(status),
+                    Ok(Either::B(((), _))) => Err(format!("timed out")),
+                    Err(Either::A((e, _))) => Err(e),
+                    Err(Either::B((e, _))) => Err(e.to_string()),
+                };
+                health.report(addr, res);
+                Ok(())
+            })
+        })
+        .select(rx.map_err(|_| ()))
+        .map(|_| ())
+        .map_err(|_| ()));
+    return tx;
+}
+
+/// Sends health check request over the connection
+pub fn request<S, F>(connect: F, path: &str, host: &str) -> CheckFuture
+    where S: AsyncRead + AsyncWrite + 'static,
+          F: Future<Item=Proto<S, CheckCodec>, Error=http::Error> + 'static,
+{
+    let (tx, rx) = oneshot_channel();
+    let codec = CheckCodec {
+        path: path.to_string(),
+        host: host.to_string(),
+        sender: Some(tx),
+    };
+    Box::new(connect
+        .and_then(move |proto| proto.send(codec))
+        .map_err(|e| e.to_string())
+        .and_then(move |mut proto| {
+            let mut rx = rx;
+            poll_fn(move || {
+                // connection only makes progress while it's polled
+                proto.poll_complete().map_err(|e| e.to_string())?;
+                match rx.poll() {
+                    Ok(Async::Ready(status)) => Ok(Async::Ready(status)),
+                    Ok(Async::NotReady) => Ok(Async::NotReady),
+                    Err(_) => Err(format!("connection closed")),
+                }
+            })
+        }))
+}
+
+impl<S: 'static> http::Codec<S> for CheckCodec {
+    type Future = HttpFuture<S>;
+    fn start_write(&mut self, mut e: http::Encoder<S>) -> Self::Future {
+        e.request_line("GET", &self.path, Version::Http11);
+        e.add_header("Host", &self.host).unwrap();
+        e.add_header("User-Agent", format!(
+            "swindon/{}", env!("CARGO_PKG_VERSION"))).unwrap();
+        e.done_headers().unwrap();
+        Box::new(ok(e.done()))
+    }
+    fn headers_received(&mut self, headers: &http::Head)
+        -> Result<http::RecvMode, http::Error>
+    {
+        let (code, _) = headers.raw_status();
+        self.sender.take().map(|tx| tx.send(code).ok());
+        Ok(http::RecvMode::progressive(1))
+    }
+    fn data_received(&mut self, data: &[u8], _end: bool)
+        -> Result<Async<usize>, http::Error>
+    {
+        Ok(Async::Ready(data.len()))
+    }
+}
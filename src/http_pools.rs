@@ -1,26 +1,42 @@
-use std::sync::{Arc, RwLock, RwLockWriteGuard};
+use std::cmp::{min, max};
+use std::sync::{Arc, Mutex, RwLock, RwLockWriteGuard};
 use std::collections::HashMap;
 use std::net::SocketAddr;
+#[cfg(unix)] use std::path::{Path, PathBuf};
 
+use abstract_ns::Address;
+use futures::{AsyncSink, Future, Sink};
+use futures::future;
+use futures::stream::{self, Stream};
+use futures::sync::oneshot::channel as oneshot;
+use libcantal::{Collection, Visitor};
 use ns_router::{Router};
+use rand::{thread_rng, Rng};
+use rustls::{ClientConfig, ClientSession};
 use tk_http::client::{Codec, Config as HConfig, Proto, Error, EncoderDone};
 use tk_pool::metrics::Collect;
 use tk_pool::config::{NewErrorLog, NewMetrics};
 use tk_pool::error_log::{ShutdownReason, ErrorLog};
-use tokio_core::net::TcpStream;
-use tokio_core::reactor::Handle;
 use tk_pool::queue::Pool;
 use tk_pool::pool_for;
-use futures::future::FutureResult;
-use libcantal::{Collection, Visitor};
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+use tokio_rustls::{ClientConfigExt, TlsStream as RustlsStream};
+#[cfg(unix)] use tokio_uds::UnixStream;
+use void::Void;
+use webpki::DNSNameRef;
 
+use crate::balancer::{AddrMetricsRef, Balancer, Tracked, address_metrics};
+use crate::health_check::{self, Health, Checker, spawn_checker};
 use crate::intern::Upstream;
-use crate::config::http_destinations::Destination;
+use crate::config::http_destinations::{Destination, LoadBalancing};
 use crate::metrics::{Counter, List, Metric, Integer};
+use crate::tls;
 
 lazy_static! {
     pub static ref REQUESTS: Counter = Counter::new();
     pub static ref FAILED_503: Counter = Counter::new();
+    pub static ref TIMEOUTS_504: Counter = Counter::new();
 
     pub static ref CONNECTING: Integer = Integer::new();
     pub static ref CONNECTED: Integer = Integer::new();
@@ -36,6 +52,8 @@ lazy_static! {
     pub static ref BLACKLIST_REMOVED: Counter = Counter::new();
     pub static ref REQUESTS_QUEUED: Counter = Counter::new();
     pub static ref REQUESTS_FORWARDED: Counter = Counter::new();
+    pub static ref RETRIES: Counter = Counter::new();
+    pub static ref RETRIES_OVER_BUDGET: Counter = Counter::new();
 
     pub static ref POOLS: Integer = Integer::new();
     pub static ref POOLS_STARTED: Counter = Counter::new();
@@ -44,16 +62,99 @@ lazy_static! {
 
 /// Future that is used for sending a client request
 ///
-/// While we only support fully buffered requests it's fine to use
-/// FutureResult, but we will probably change it to something
-pub type HttpFuture<S> = FutureResult<EncoderDone<S>, Error>;
-pub type PoolInner = Pool<
+/// It's boxed because streaming request bodies are written by a long
+/// running future, while other requests are written at once
+pub type HttpFuture<S> = Box<dyn Future<Item=EncoderDone<S>, Error=Error>>;
+pub type TlsStream = RustlsStream<TcpStream, ClientSession>;
+type AddressStream = Box<dyn Stream<Item=Address, Error=Void>>;
+pub type PlainPool = Pool<
     Box<dyn Codec<TcpStream, Future=HttpFuture<TcpStream>>+Send>,
     PoolMetrics>;
+pub type TlsPool = Pool<
+    Box<dyn Codec<TlsStream, Future=HttpFuture<TlsStream>>+Send>,
+    PoolMetrics>;
+#[cfg(unix)]
+pub type UnixPool = Pool<
+    Box<dyn Codec<UnixStream, Future=HttpFuture<UnixStream>>+Send>,
+    PoolMetrics>;
+
+/// Codec that can be sent to a pool of any kind
+#[cfg(unix)]
+pub trait AnyCodec: Send + 'static
+    + Codec<TcpStream, Future=HttpFuture<TcpStream>>
+    + Codec<TlsStream, Future=HttpFuture<TlsStream>>
+    + Codec<UnixStream, Future=HttpFuture<UnixStream>>
+{}
+
+#[cfg(unix)]
+impl<C> AnyCodec for C
+    where C: Send + 'static,
+          C: Codec<TcpStream, Future=HttpFuture<TcpStream>>,
+          C: Codec<TlsStream, Future=HttpFuture<TlsStream>>,
+          C: Codec<UnixStream, Future=HttpFuture<UnixStream>>,
+{}
+
+/// Codec that can be sent to a pool of any kind
+#[cfg(not(unix))]
+pub trait AnyCodec: Send + 'static
+    + Codec<TcpStream, Future=HttpFuture<TcpStream>>
+    + Codec<TlsStream, Future=HttpFuture<TlsStream>>
+{}
+
+#[cfg(not(unix))]
+impl<C> AnyCodec for C
+    where C: Send + 'static,
+          C: Codec<TcpStream, Future=HttpFuture<TcpStream>>,
+          C: Codec<TlsStream, Future=HttpFuture<TlsStream>>,
+{}
+
+/// Connection pool, transport depends on the destination settings
+pub enum PoolInner {
+    Plain(PlainPool),
+    Tls(TlsPool),
+    #[cfg(unix)]
+    Unix(UnixPool),
+    /// Separate pool for each address, for load balancing other than queue
+    BalancedPlain(Balancer<PlainPool>),
+    BalancedTls(Balancer<TlsPool>),
+}
+
+/// Where a connection which is not pooled (i.e. a websocket) is opened
+pub enum Target {
+    Tcp(SocketAddr, Option<Arc<ClientConfig>>),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+/// Balance of the retry budget is kept in hundredths of a retry
+const RETRY_UNIT: usize = 100;
+/// Budget accumulated while there are no failures, also initial value
+const MAX_RETRY_BALANCE: usize = 10*RETRY_UNIT;
+
+/// Limits number of retries relative to number of requests
+///
+/// This makes sure that when all backends fail, retries don't multiply
+/// the load on them.
+struct RetryBudget {
+    percent: usize,
+    balance: usize,
+}
 
 pub struct HttpPool {
     pool: PoolInner,
     metrics: PoolMetrics,
+    retry_budget: RetryBudget,
+    /// Settings the pool is created with, pool is recreated when they
+    /// change (including request timeout, which depends on the routes, and
+    /// contents of the TLS certificates)
+    dest: Arc<Destination>,
+    /// Client TLS settings, shared with websocket connections
+    tls_config: Option<Arc<ClientConfig>>,
+    /// Current healthy addresses, used for websocket connections
+    addresses: Arc<Mutex<Vec<SocketAddr>>>,
+    /// Health checks are stopped when the pool is removed
+    #[allow(dead_code)]
+    checker: Option<Checker>,
 }
 
 pub struct UpstreamRef<'a> {
@@ -68,8 +169,7 @@ pub struct UpstreamGuard<'a> {
 
 #[derive(Clone)]
 pub struct HttpPools {
-    plain: Arc<RwLock<HashMap<Upstream, HttpPool>>>,
-    // TODO(tailhook) https pools
+    pools: Arc<RwLock<HashMap<Upstream, HttpPool>>>,
 }
 
 #[derive(Clone, Debug)]
@@ -96,6 +196,8 @@ struct Metrics {
     blacklist_removed: Counter,
     requests_queued: Counter,
     requests_forwarded: Counter,
+    retries: Counter,
+    retries_over_budget: Counter,
 }
 
 impl Metrics {
@@ -119,6 +221,8 @@ impl Metrics {
             blacklist_removed: Counter::new(),
             requests_queued: Counter::new(),
             requests_forwarded: Counter::new(),
+            retries: Counter::new(),
+            retries_over_budget: Counter::new(),
         }
     }
 }
@@ -143,6 +247,16 @@ impl Collection for PoolMetrics {
         v.metric(&M(&g, "blacklist_removed"), &s.blacklist_removed);
         v.metric(&M(&g, "requests_queued"), &s.requests_queued);
         v.metric(&M(&g, "requests_forwarded"), &s.requests_forwarded);
+        v.metric(&M(&g, "retries"), &s.retries);
+        v.metric(&M(&g, "retries_over_budget"), &s.retries_over_budget);
+        for (addr, m) in address_metrics(&s.name) {
+            let g = format!("http.pools.{}.{}", s.name, addr);
+            v.metric(&M(&g, "requests"), &m.requests);
+            v.metric(&M(&g, "in_flight"), &m.in_flight);
+            v.metric(&M(&g, "healthy"), &m.healthy);
+            v.metric(&M(&g, "health_check_failures"),
+                &m.health_check_failures);
+        }
     }
 }
 
@@ -150,6 +264,30 @@ impl PoolMetrics {
     fn new(name: &Upstream) -> PoolMetrics {
         PoolMetrics(Arc::new(Metrics::new(name)))
     }
+    /// Number of requests queued in all pools sharing these metrics
+    pub fn queued(&self) -> usize {
+        max(self.0.request_queue.get(), 0) as usize
+    }
+}
+
+impl RetryBudget {
+    fn new(percent: usize) -> RetryBudget {
+        RetryBudget {
+            percent: percent,
+            balance: MAX_RETRY_BALANCE,
+        }
+    }
+    fn deposit(&mut self) {
+        self.balance = min(self.balance + self.percent, MAX_RETRY_BALANCE);
+    }
+    fn withdraw(&mut self) -> bool {
+        if self.balance >= RETRY_UNIT {
+            self.balance -= RETRY_UNIT;
+            true
+        } else {
+            false
+        }
+    }
 }
 
 impl Collect for PoolMetrics {
@@ -211,6 +349,13 @@ impl Collect for PoolMetrics {
     }
     fn pool_closed(&self) {
         // TODO(tailhook) decrement global counters?
+        // Note: pools counter is decremented when metrics are dropped,
+        // because balancers have multiple pools sharing the same metrics
+    }
+}
+
+impl Drop for Metrics {
+    fn drop(&mut self) {
         POOLS.decr(1);
         POOLS_STOPPED.incr(1);
     }
@@ -219,7 +364,7 @@ impl Collect for PoolMetrics {
 impl HttpPools {
     pub fn new() -> HttpPools {
         HttpPools {
-            plain: Arc::new(RwLock::new(HashMap::new())),
+            pools: Arc::new(RwLock::new(HashMap::new())),
         }
     }
     pub fn upstream<'x>(&'x self, dest: &'x Upstream) -> UpstreamRef<'x> {
@@ -231,49 +376,461 @@ impl HttpPools {
     pub fn update(&self, cfg: &HashMap<Upstream, Arc<Destination>>,
         resolver: &Router, handle: &Handle)
     {
-        let mut plain = self.plain.write().expect("pools not poisoned");
+        let mut pools = self.pools.write().expect("pools not poisoned");
         let mut to_delete = Vec::new();
-        for k in plain.keys() {
-            if !cfg.contains_key(k) {
-                to_delete.push(k.clone());
+        for (k, pool) in pools.iter() {
+            match cfg.get(k) {
+                Some(dest) if *dest == pool.dest => {}
+                _ => to_delete.push(k.clone()),
             }
         }
         for k in to_delete {
-            plain.remove(&k);
+            pools.remove(&k);
         }
         for (k, dest) in cfg {
-            // TODO(tailhook) compare destinations
-            if !plain.contains_key(k) {
-                let h2 = handle.clone();
+            if !pools.contains_key(k) {
                 let conn_config = HConfig::new()
                     .inflight_request_limit(
                         dest.in_flight_requests_per_backend_connection)
                     .keep_alive_timeout(dest.keep_alive_timeout)
                     .safe_pipeline_timeout(dest.safe_pipeline_timeout)
-                    .max_request_timeout(dest.max_request_timeout)
+                    .max_request_timeout(dest.backend_request_timeout)
                     .done();
                 let metrics = PoolMetrics::new(k);
-                let pool = pool_for(move |addr| {
-                        Proto::connect_tcp(addr, &conn_config, &h2)
-                    })
-                    .connect_to(resolver.subscribe_many(&dest.addresses, 80))
-                    .lazy_uniform_connections(
-                        dest.backend_connections_per_ip_port as u32)
-                    .with_queue_size(
-                        dest.queue_size_for_503)
-                    .metrics(metrics.clone())
-                    .errors(PoolLog(k.clone()))
-                    .spawn_on(handle);
-                plain.insert(k.clone(), HttpPool { pool, metrics });
+                let tls_config = match dest.tls {
+                    Some(ref tls_settings) => {
+                        match tls::client_config(tls_settings) {
+                            Ok(cfg) => Some(Arc::new(cfg)),
+                            Err(e) => {
+                                error!("{}: Can't initialize TLS: {}", k, e);
+                                continue;
+                            }
+                        }
+                    }
+                    None => None,
+                };
+                let health = dest.health_check.as_ref()
+                    .map(|settings| Health::new(k, settings));
+                let checker = health.as_ref().map(|health| {
+                    health_checker(health, dest, tls_config.clone(),
+                        &conn_config, resolver, handle)
+                });
+                let connections = dest.backend_connections_per_ip_port as u32;
+                let queue_size = dest.queue_size_for_503;
+                let current = Arc::new(Mutex::new(Vec::new()));
+                let pool = match (dest.unix_socket(), tls_config.clone(),
+                                  dest.load_balancing)
+                {
+                    #[cfg(unix)]
+                    (Some(path), _, _) => {
+                        let path = path.to_path_buf();
+                        let h2 = handle.clone();
+                        PoolInner::Unix(pool_for(move |_addr| {
+                                connect_unix(&path, &conn_config, &h2)
+                            })
+                            .connect_to(unix_address())
+                            .lazy_uniform_connections(connections)
+                            .with_queue_size(queue_size)
+                            .metrics(metrics.clone())
+                            .errors(PoolLog(k.clone()))
+                            .spawn_on(handle))
+                    }
+                    #[cfg(not(unix))]
+                    (Some(_), _, _) => unreachable!("checked in config"),
+                    (None, Some(tls_config), LoadBalancing::queue) => {
+                        let server_name = dest.tls_server_name()
+                            .expect("tls is enabled").to_string();
+                        let h2 = handle.clone();
+                        PoolInner::Tls(pool_for(move |addr| {
+                                connect_tls(addr, &tls_config, &server_name,
+                                    &conn_config, &h2)
+                            })
+                            .connect_to(addresses(dest, 443, &health,
+                                &current, resolver))
+                            .lazy_uniform_connections(connections)
+                            .with_queue_size(queue_size)
+                            .metrics(metrics.clone())
+                            .errors(PoolLog(k.clone()))
+                            .spawn_on(handle))
+                    }
+                    (None, Some(tls_config), _) => {
+                        let server_name = dest.tls_server_name()
+                            .expect("tls is enabled").to_string();
+                        let m1 = metrics.clone();
+                        let name = k.clone();
+                        let h1 = handle.clone();
+                        PoolInner::BalancedTls(self.balancer(k, dest,
+                            &metrics, addresses(dest, 443, &health, &current,
+                                resolver),
+                            handle, Box::new(move |addr| {
+                                let tls_config = tls_config.clone();
+                                let server_name = server_name.clone();
+                                let conn_config = conn_config.clone();
+                                let h2 = h1.clone();
+                                pool_for(move |addr| {
+                                    connect_tls(addr, &tls_config,
+                                        &server_name, &conn_config, &h2)
+                                })
+                                .connect_to(fixed_address(addr))
+                                .lazy_uniform_connections(connections)
+                                .with_queue_size(queue_size)
+                                .metrics(m1.clone())
+                                .errors(PoolLog(name.clone()))
+                                .spawn_on(&h1)
+                            })))
+                    }
+                    (None, None, LoadBalancing::queue) => {
+                        let h2 = handle.clone();
+                        PoolInner::Plain(pool_for(move |addr| {
+                                Proto::connect_tcp(addr, &conn_config, &h2)
+                            })
+                            .connect_to(addresses(dest, 80, &health,
+                                &current, resolver))
+                            .lazy_uniform_connections(connections)
+                            .with_queue_size(queue_size)
+                            .metrics(metrics.clone())
+                            .errors(PoolLog(k.clone()))
+                            .spawn_on(handle))
+                    }
+                    (None, None, _) => {
+                        let m1 = metrics.clone();
+                        let name = k.clone();
+                        let h1 = handle.clone();
+                        PoolInner::BalancedPlain(self.balancer(k, dest,
+                            &metrics, addresses(dest, 80, &health, &current,
+                                resolver),
+                            handle, Box::new(move |addr| {
+                                let conn_config = conn_config.clone();
+                                let h2 = h1.clone();
+                                pool_for(move |addr| {
+                                    Proto::connect_tcp(addr, &conn_config, &h2)
+                                })
+                                .connect_to(fixed_address(addr))
+                                .lazy_uniform_connections(connections)
+                                .with_queue_size(queue_size)
+                                .metrics(m1.clone())
+                                .errors(PoolLog(name.clone()))
+                                .spawn_on(&h1)
+                            })))
+                    }
+                };
+                pools.insert(k.clone(), HttpPool {
+                    pool, metrics,
+                    retry_budget: RetryBudget::new(dest.retry_budget),
+                    dest: dest.clone(),
+                    tls_config,
+                    addresses: current,
+                    checker,
+                });
+            }
+        }
+    }
+    /// Creates balancer and starts resolving addresses for it
+    fn balancer<P>(&self, name: &Upstream, dest: &Destination,
+        metrics: &PoolMetrics, addresses: AddressStream, handle: &Handle,
+        new_pool: Box<dyn Fn(SocketAddr) -> P>)
+        -> Balancer<P>
+    {
+        let (tx, rx) = oneshot();
+        let pools = self.clone();
+        let name2 = name.clone();
+        handle.spawn(addresses
+            .for_each(move |addr| {
+                let addresses = addr.at(0).addresses().collect::<Vec<_>>();
+                pools.set_addresses(&name2, &addresses);
+                Ok(())
+            })
+            .select(rx.then(|_| Ok::<(), Void>(())))
+            .map(|_| ())
+            .map_err(|_| ()));
+        Balancer::new(name, dest.load_balancing, new_pool, metrics,
+            dest.queue_size_for_503, tx)
+    }
+    /// Chooses where to connect for a connection which is not pooled
+    /// (i.e. a websocket)
+    ///
+    /// Balanced pools choose address by their strategy, `queue` pools
+    /// choose a random one. Unhealthy addresses are skipped in both cases.
+    pub fn connect_to(&self, name: &Upstream, key: Option<u64>)
+        -> Result<Target, String>
+    {
+        let mut pools = self.pools.write().expect("pools not poisoned");
+        let pool = pools.get_mut(name)
+            .ok_or_else(|| format!("no pool for {:?}", name))?;
+        #[cfg(unix)]
+        {
+            if let Some(path) = pool.dest.unix_socket() {
+                return Ok(Target::Unix(path.to_path_buf()));
+            }
+        }
+        let addr = match pool.pool {
+            PoolInner::BalancedPlain(ref mut b) => {
+                b.pick(key, &[]).map(|(addr, _, _)| addr)
+            }
+            PoolInner::BalancedTls(ref mut b) => {
+                b.pick(key, &[]).map(|(addr, _, _)| addr)
+            }
+            _ => {
+                let addresses = pool.addresses.lock()
+                    .expect("addresses not poisoned");
+                thread_rng().choose(&addresses).cloned()
+            }
+        };
+        match addr {
+            Some(addr) => Ok(Target::Tcp(addr, pool.tls_config.clone())),
+            None => Err(format!("no address for {:?}", name)),
+        }
+    }
+    fn set_addresses(&self, name: &Upstream, addresses: &[SocketAddr]) {
+        let mut pools = self.pools.write().expect("pools not poisoned");
+        match pools.get_mut(name).map(|p| &mut p.pool) {
+            Some(&mut PoolInner::BalancedPlain(ref mut b)) => {
+                b.set_addresses(addresses);
+            }
+            Some(&mut PoolInner::BalancedTls(ref mut b)) => {
+                b.set_addresses(addresses);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn connect_tls<C: Codec<TlsStream>>(addr: SocketAddr,
+    tls_config: &Arc<ClientConfig>, server_name: &str,
+    conn_config: &Arc<HConfig>, handle: &Handle)
+    -> Box<dyn Future<Item=Proto<TlsStream, C>, Error=Error>>
+{
+    let tls_config = tls_config.clone();
+    let server_name = server_name.to_string();
+    let conn_config = conn_config.clone();
+    let handle = handle.clone();
+    Box::new(TcpStream::connect(&addr, &handle)
+        .and_then(move |sock| {
+            let name = DNSNameRef::try_from_ascii_str(&server_name)
+                .expect("server name is validated in config");
+            tls_config.connect_async(name, sock)
+        })
+        .map(move |sock| Proto::new(sock, &handle, &conn_config))
+        .map_err(Error::custom))
+}
+
+/// Resolves destination addresses, skipping unhealthy ones
+///
+/// Addresses are also stored in `current` on every update.
+fn addresses(dest: &Destination, default_port: u16,
+    health: &Option<Arc<Health>>, current: &Arc<Mutex<Vec<SocketAddr>>>,
+    resolver: &Router)
+    -> AddressStream
+{
+    let stream = resolver.subscribe_many(&dest.addresses, default_port);
+    let stream: AddressStream = match *health {
+        Some(ref health) => Box::new(health.filter(stream)),
+        None => Box::new(stream),
+    };
+    let current = current.clone();
+    Box::new(stream.map(move |addr| {
+        *current.lock().expect("addresses not poisoned") =
+            addr.at(0).addresses().collect();
+        addr
+    }))
+}
+
+/// Starts health checks using the same transport as the pool
+fn health_checker(health: &Arc<Health>, dest: &Destination,
+    tls_config: Option<Arc<ClientConfig>>, conn_config: &Arc<HConfig>,
+    resolver: &Router, handle: &Handle)
+    -> Checker
+{
+    let settings = dest.health_check.as_ref().expect("health check enabled");
+    let path = settings.path.clone();
+    let host = dest.override_host_header.clone()
+        .unwrap_or_else(|| health_check_host(dest));
+    let conn_config = conn_config.clone();
+    let h1 = handle.clone();
+    match (dest.unix_socket(), tls_config) {
+        #[cfg(unix)]
+        (Some(socket), _) => {
+            let socket = socket.to_path_buf();
+            spawn_checker(health, unix_address(), Box::new(move |_addr| {
+                health_check::request(
+                    connect_unix(&socket, &conn_config, &h1),
+                    &path, &host)
+            }), handle)
+        }
+        #[cfg(not(unix))]
+        (Some(_), _) => unreachable!("checked in config"),
+        (None, Some(tls_config)) => {
+            let server_name = dest.tls_server_name()
+                .expect("tls is enabled").to_string();
+            spawn_checker(health,
+                resolver.subscribe_many(&dest.addresses, 443),
+                Box::new(move |addr| {
+                    health_check::request(
+                        connect_tls(addr, &tls_config, &server_name,
+                            &conn_config, &h1),
+                        &path, &host)
+                }), handle)
+        }
+        (None, None) => {
+            spawn_checker(health,
+                resolver.subscribe_many(&dest.addresses, 80),
+                Box::new(move |addr| {
+                    health_check::request(
+                        Proto::connect_tcp(addr, &conn_config, &h1),
+                        &path, &host)
+                }), handle)
+        }
+    }
+}
+
+/// Host header for health check requests: the first address without port
+fn health_check_host(dest: &Destination) -> String {
+    if dest.unix_socket().is_some() {
+        return "localhost".to_string();
+    }
+    let addr = &dest.addresses[0][..];
+    match addr.rfind(':') {
+        Some(idx) => addr[..idx].to_string(),
+        None => addr.to_string(),
+    }
+}
+
+/// Address stream that yields single address and never updates it
+fn fixed_address(addr: SocketAddr)
+    -> impl Stream<Item=Address, Error=Void>
+{
+    let addr = Some(addr).into_iter().collect::<Address>();
+    stream::once(Ok(addr))
+        .chain(future::empty::<Address, Void>().into_stream())
+}
+
+/// Address stream for unix socket destinations
+///
+/// Pool needs some address to connect to, so we give it a fake one.
+/// The connector knows the real path.
+#[cfg(unix)]
+fn unix_address() -> impl Stream<Item=Address, Error=Void> {
+    fixed_address(SocketAddr::from(([127, 0, 0, 1], 0)))
+}
+
+#[cfg(unix)]
+fn connect_unix<C: Codec<UnixStream> + 'static>(path: &Path,
+    conn_config: &Arc<HConfig>, handle: &Handle)
+    -> Box<dyn Future<Item=Proto<UnixStream, C>, Error=Error>>
+{
+    let sock = UnixStream::connect(path, handle)
+        .map(|sock| Proto::new(sock, handle, conn_config))
+        .map_err(|e| Error::custom(format!("{:?}: {}", path, e)));
+    Box::new(future::result(sock))
+}
+
+impl PoolInner {
+    /// Sends request to the pool
+    ///
+    /// Unlike `Sink::start_send` doesn't return codec back, if pool is full
+    pub fn start_send<C: AnyCodec>(&mut self, codec: Box<C>)
+        -> Result<AsyncSink<()>, String>
+    {
+        self.start_send_keyed(codec, None, &[]).map(|(x, _)| x)
+    }
+    /// Sends request to the pool, `key` is used by `consistent_hash`
+    /// load balancing
+    ///
+    /// Balanced pools skip addresses listed in `exclude` and return the
+    /// address request is sent to. The `queue` pool chooses connection by
+    /// itself, so address is unknown and `exclude` is ignored.
+    pub fn start_send_keyed<C: AnyCodec>(&mut self, codec: Box<C>,
+        key: Option<u64>, exclude: &[SocketAddr])
+        -> Result<(AsyncSink<()>, Option<SocketAddr>), String>
+    {
+        match *self {
+            PoolInner::Plain(ref mut pool) => {
+                pool.start_send(codec)
+                    .map(|x| (x.map(|_| ()), None))
+                    .map_err(|e| e.to_string())
+            }
+            PoolInner::Tls(ref mut pool) => {
+                pool.start_send(codec)
+                    .map(|x| (x.map(|_| ()), None))
+                    .map_err(|e| e.to_string())
+            }
+            #[cfg(unix)]
+            PoolInner::Unix(ref mut pool) => {
+                pool.start_send(codec)
+                    .map(|x| (x.map(|_| ()), None))
+                    .map_err(|e| e.to_string())
+            }
+            PoolInner::BalancedPlain(ref mut balancer) => {
+                if balancer.queue_full() {
+                    return Ok((AsyncSink::NotReady(()), None));
+                }
+                if let Some((addr, pool, metrics)) =
+                    balancer.pick(key, exclude)
+                {
+                    return pool.start_send(Tracked::new(codec, metrics))
+                        .map(|x| (x.map(|_| ()), Some(addr)))
+                        .map_err(|e| e.to_string());
+                }
+                let waiting = balancer.wait_for_address(key,
+                    Box::new(move |pool: &mut PlainPool,
+                                   metrics: &AddrMetricsRef|
+                    {
+                        // on failure codec is dropped, so request gets 503
+                        pool.start_send(Tracked::new(codec, metrics)).ok();
+                    }));
+                if waiting {
+                    Ok((AsyncSink::Ready, None))
+                } else {
+                    Ok((AsyncSink::NotReady(()), None))
+                }
+            }
+            PoolInner::BalancedTls(ref mut balancer) => {
+                if balancer.queue_full() {
+                    return Ok((AsyncSink::NotReady(()), None));
+                }
+                if let Some((addr, pool, metrics)) =
+                    balancer.pick(key, exclude)
+                {
+                    return pool.start_send(Tracked::new(codec, metrics))
+                        .map(|x| (x.map(|_| ()), Some(addr)))
+                        .map_err(|e| e.to_string());
+                }
+                let waiting = balancer.wait_for_address(key,
+                    Box::new(move |pool: &mut TlsPool,
+                                   metrics: &AddrMetricsRef|
+                    {
+                        // on failure codec is dropped, so request gets 503
+                        pool.start_send(Tracked::new(codec, metrics)).ok();
+                    }));
+                if waiting {
+                    Ok((AsyncSink::Ready, None))
+                } else {
+                    Ok((AsyncSink::NotReady(()), None))
+                }
             }
         }
-     }
+    }
+    /// Returns true if request can be sent to an address not in `exclude`
+    ///
+    /// Always true for the `queue` pool, as it doesn't tell addresses, so
+    /// the request may be sent to the same address again.
+    pub fn has_address_except(&self, exclude: &[SocketAddr]) -> bool {
+        match *self {
+            PoolInner::BalancedPlain(ref balancer) => {
+                balancer.has_address_except(exclude)
+            }
+            PoolInner::BalancedTls(ref balancer) => {
+                balancer.has_address_except(exclude)
+            }
+            _ => true,
+        }
+    }
 }
 
 impl<'a> UpstreamRef<'a> {
     pub fn get_mut(&mut self) -> UpstreamGuard<'a> {
         UpstreamGuard {
-            guard: self.pools.plain.write().expect("pools not poisoned"),
+            guard: self.pools.pools.write().expect("pools not poisoned"),
             upstream: self.upstream,
         }
     }
@@ -283,6 +840,29 @@ impl<'a> UpstreamGuard<'a> {
     pub fn get_mut(&mut self) -> Option<&mut PoolInner> {
         self.guard.get_mut(self.upstream).map(|x| &mut x.pool)
     }
+    /// Accounts request in the retry budget
+    pub fn request_sent(&mut self) {
+        if let Some(pool) = self.guard.get_mut(self.upstream) {
+            pool.retry_budget.deposit();
+        }
+    }
+    /// Returns `true` and counts the retry if budget allows one
+    pub fn retry(&mut self) -> bool {
+        match self.guard.get_mut(self.upstream) {
+            Some(pool) => {
+                if pool.retry_budget.withdraw() {
+                    RETRIES.incr(1);
+                    pool.metrics.0.retries.incr(1);
+                    true
+                } else {
+                    RETRIES_OVER_BUDGET.incr(1);
+                    pool.metrics.0.retries_over_budget.incr(1);
+                    false
+                }
+            }
+            None => false,
+        }
+    }
 }
 
 pub fn metrics() -> List {
@@ -291,6 +871,7 @@ pub fn metrics() -> List {
         // obeys cantal-py.RequestTracker
         (Metric(base, "requests"), &*REQUESTS),
         (Metric(base, "backpressure_failures"), &*FAILED_503),
+        (Metric(base, "response_timeouts"), &*TIMEOUTS_504),
 
         (Metric(base, "connecting"), &*CONNECTING),
         (Metric(base, "connected"), &*CONNECTED),
@@ -306,6 +887,13 @@ pub fn metrics() -> List {
         (Metric(base, "blacklist_removed"), &*BLACKLIST_REMOVED),
         (Metric(base, "requests_queued"), &*REQUESTS_QUEUED),
         (Metric(base, "requests_forwarded"), &*REQUESTS_FORWARDED),
+        (Metric(base, "retries"), &*RETRIES),
+        (Metric(base, "retries_over_budget"), &*RETRIES_OVER_BUDGET),
+
+        (Metric(base, "health_checks"), &*health_check::CHECKS),
+        (Metric(base, "health_check_failures"),
+            &*health_check::CHECK_FAILURES),
+        (Metric(base, "unhealthy_addresses"), &*health_check::UNHEALTHY),
 
         (Metric(base, "pools"), &*POOLS),
         (Metric(base, "pools_started"), &*POOLS_STARTED),
@@ -314,7 +902,7 @@ pub fn metrics() -> List {
 }
 
 pub fn pool_metrics(h: &HttpPools) -> Vec<PoolMetrics> {
-    h.plain.read().expect("http pools are okay")
+    h.pools.read().expect("http pools are okay")
         .values()
         .map(|p| p.metrics.clone())
         .collect()
@@ -1,18 +1,171 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use futures::Future;
 use tk_http::server::{Error};
 
 use crate::incoming::{Input};
 use crate::config::{Authorizer};
 use crate::authorizers;
 
+
+/// Result of the authorization check
+pub enum Check {
+    Done(bool),
+    /// Credentials are missing or wrong, contains `WWW-Authenticate` value
+    Unauthorized(String),
+    /// Authorizer needs to ask some external service
+    Pending(AuthFuture),
+}
+
+/// Resolves when request is allowed, error contains the reason of denial
+pub type AuthFuture = Box<dyn Future<Item=(), Error=Deny>>;
+
+/// Denial by the asynchronous authorizer
+#[derive(Debug)]
+pub struct Deny {
+    /// Recorded in `X-Swindon-Deny`
+    pub reason: String,
+    /// `WWW-Authenticate` value if client may retry with credentials
+    pub challenge: Option<String>,
+}
+
+impl From<String> for Deny {
+    fn from(reason: String) -> Deny {
+        Deny {
+            reason: reason,
+            challenge: None,
+        }
+    }
+}
+
+/// Describes the authorized user
+///
+/// Filled by authorizer (possibly asynchronously). Consists of headers
+/// configured in the authorizer itself and of attributes (`login`,
+/// `network`, ...) which `!Proxy` maps to headers by `identity-headers`.
+#[derive(Debug, Clone)]
+pub struct Identity(Arc<Mutex<IdentityData>>);
+
+#[derive(Debug, Default)]
+struct IdentityData {
+    headers: Vec<(String, String)>,
+    attributes: Vec<(&'static str, String)>,
+}
+
+impl Identity {
+    pub fn new() -> Identity {
+        Identity(Arc::new(Mutex::new(IdentityData::default())))
+    }
+    /// Adds headers, replacing previously added ones with the same names
+    ///
+    /// Combined authorizers (`!AllOf`) may add headers one after another.
+    pub fn add(&self, headers: Vec<(String, String)>) {
+        let mut data = self.0.lock().expect("identity not poisoned");
+        data.headers.retain(|&(ref name, _)| {
+            !headers.iter().any(|&(ref n, _)| n.eq_ignore_ascii_case(name))
+        });
+        data.headers.extend(headers);
+    }
+    /// Sets attribute, replacing previous value
+    pub fn set_attribute<S: Into<String>>(&self, name: &'static str,
+                                          value: S)
+    {
+        let mut data = self.0.lock().expect("identity not poisoned");
+        let value = value.into();
+        data.attributes.retain(|&(n, _)| n != name);
+        data.attributes.push((name, value));
+    }
+    /// Returns headers to add to the forwarded request
+    ///
+    /// `mapping` is header name to attribute name, attributes which are
+    /// not set by authorizer are skipped.
+    pub fn headers(&self, mapping: &HashMap<String, String>)
+        -> Vec<(String, String)>
+    {
+        let data = self.0.lock().expect("identity not poisoned");
+        let mut result = data.headers.clone();
+        let mut mapped = mapping.iter()
+            .filter(|&(name, _)| {
+                !result.iter().any(|&(ref n, _)| n.eq_ignore_ascii_case(name))
+            })
+            .filter_map(|(name, attr)| {
+                data.attributes.iter()
+                    .find(|&&(n, _)| n == attr)
+                    .map(|&(_, ref value)| (name.clone(), value.clone()))
+            })
+            .collect::<Vec<_>>();
+        mapped.sort();
+        result.extend(mapped);
+        return result;
+    }
+}
+
+/// Returns true if client-supplied header must not be forwarded
+///
+/// These are headers set by identity, headers any authorizer of the route
+/// may set (see `Authorizer::header_names`), even if it didn't set them
+/// for this specific request, and headers of the `identity-headers`.
+pub fn is_identity_header(name: &str, identity: &[(String, String)],
+    route_headers: &[String], mapping: &HashMap<String, String>)
+    -> bool
+{
+    identity.iter().any(|&(ref n, _)| n.eq_ignore_ascii_case(name)) ||
+    route_headers.iter().any(|n| n.eq_ignore_ascii_case(name)) ||
+    mapping.keys().any(|n| n.eq_ignore_ascii_case(name))
+}
+
 // TODO(tailhook) this should eventually be a virtual method on Authorizer
 impl Authorizer {
-    pub fn check(&self, input: &mut Input) -> Result<bool, Error> {
+    /// Names of the headers this authorizer may add to the identity
+    ///
+    /// Combined authorizers return nothing, headers of the authorizers
+    /// they reference are collected by the routing table.
+    pub fn header_names(&self) -> Vec<&str> {
         match *self {
-            Authorizer::AllowAll => Ok(true),
+            Authorizer::Ldap(ref cfg) => {
+                cfg.login_header.iter().map(|x| &x[..])
+                .chain(cfg.additional_queries.keys().map(|x| &x[..]))
+                .collect()
+            }
+            Authorizer::Subrequest(ref cfg) => {
+                cfg.copy_headers.iter().map(|x| &x[..]).collect()
+            }
+            Authorizer::Jwt(ref cfg) => {
+                cfg.claim_headers.keys().map(|x| &x[..]).collect()
+            }
+            Authorizer::AllowAll | Authorizer::SourceIp(..) |
+            Authorizer::HtPasswd(..) | Authorizer::SignedUrl(..) |
+            Authorizer::AnyOf(..) | Authorizer::AllOf(..) => Vec::new(),
+        }
+    }
+    pub fn check(&self, input: &mut Input) -> Result<Check, Error> {
+        match *self {
+            Authorizer::AllowAll => Ok(Check::Done(true)),
             Authorizer::SourceIp(ref cfg) => {
-                authorizers::source_ip::check(cfg, input)
+                authorizers::source_ip::check(cfg, input).map(Check::Done)
+            }
+            Authorizer::Ldap(ref cfg) => {
+                authorizers::ldap::check(cfg, input)
+            }
+            Authorizer::HtPasswd(ref cfg) => {
+                authorizers::htpasswd::check(cfg, input)
+            }
+            Authorizer::Subrequest(ref cfg) => {
+                authorizers::subrequest::check(cfg, input)
+            }
+            Authorizer::Jwt(ref cfg) => {
+                authorizers::jwt::check(cfg, input)
+            }
+            Authorizer::SignedUrl(ref cfg) => {
+                authorizers::signed_url::check(cfg, input)
+            }
+            Authorizer::AnyOf(ref names) => {
+                authorizers::combine::any_of(names, input)
+            }
+            Authorizer::AllOf(ref names) => {
+                authorizers::combine::all_of(names, input)
             }
-            Authorizer::Ldap(_) => unimplemented!(),
         }
     }
 }
@@ -4,7 +4,7 @@ use std::path::{Path, PathBuf};
 
 use tk_http::server::Head;
 
-use crate::intern::{Authorizer};
+use crate::intern::{Authorizer, HandlerName};
 use crate::config::Config;
 use crate::routing::Route;
 use crate::request_id::RequestId;
@@ -13,6 +13,8 @@ pub struct Debug(Option<Box<DebugInfo>>);
 
 struct DebugInfo {
     route: Option<Route>,
+    /// Handler name and conditions of the route (if any)
+    route_name: String,
     fs_path: Option<PathBuf>,
     #[allow(dead_code)]
     config: Arc<Config>,
@@ -28,6 +30,7 @@ impl Debug {
         if cfg.debug_routing {
             Debug(Some(Box::new(DebugInfo {
                 route: None,
+                route_name: String::new(),
                 fs_path: None,
                 config: cfg.clone(),
                 request_id: request_id,
@@ -47,12 +50,25 @@ impl Debug {
         if let Some(ref mut dinfo) = self.0 {
             debug_assert!(dinfo.route.is_none());
             dinfo.route = Some(route.clone());
+            dinfo.route_name = if route.conditions.is_empty() {
+                route.handler_name.to_string()
+            } else {
+                format!("{} ({})", route.handler_name, route.conditions)
+            };
+        }
+    }
+
+    /// Add handler chosen by the `!Split` handler of the route
+    pub fn set_variant(&mut self, name: &HandlerName) {
+        if let Some(ref mut dinfo) = self.0 {
+            dinfo.route_name.push_str(" -> ");
+            dinfo.route_name.push_str(&name[..]);
         }
     }
 
     pub fn get_route(&self) -> Option<&str> {
         self.0.as_ref().map(|dinfo| {
-            dinfo.route.as_ref().map(|x| &x.handler_name[..])
+            dinfo.route.as_ref().map(|_| &dinfo.route_name[..])
             .unwrap_or("-- no route --")
         })
     }
@@ -55,6 +55,10 @@ impl Handler {
             Handler::SelfStatus(ref settings) => {
                 Ok(handlers::self_status::serve(settings, input))
             }
+            Handler::Split(..) => {
+                // variant is chosen by the router, so split is never served
+                Ok(serve_error_page(Status::InternalServerError, input))
+            }
         }
     }
 }
@@ -6,8 +6,10 @@ use tokio_core::reactor::Handle;
 
 use crate::config::Config;
 use crate::runtime::Runtime;
-use crate::incoming::{Debug, IntoContext};
+use crate::incoming::{Debug, IntoContext, Identity};
 use crate::request_id::RequestId;
+use crate::routing::Captures;
+use crate::config::routing::RouteOptions;
 
 
 pub struct Input<'a> {
@@ -20,6 +22,13 @@ pub struct Input<'a> {
     pub suffix: &'a str,
     pub handle: &'a Handle,
     pub request_id: RequestId,
+    pub identity: Identity,
+    /// Groups captured by the path pattern of the route
+    pub captures: Captures,
+    /// Settings set by `key=value` in the route definition
+    pub options: &'a RouteOptions,
+    /// Headers authorizer of the route may set, see `Route`
+    pub identity_headers: &'a [String],
 }
 
 impl<'a> IntoContext for Input<'a> {
@@ -11,14 +11,17 @@ mod encoder;
 mod quick_reply;
 mod handler;
 mod authorizer;
+mod pending_auth;
 
 pub type Request<S> = Box<dyn Codec<S, ResponseFuture=Reply<S>>>;
 pub type Reply<S> = Box<dyn Future<Item=EncoderDone<S>, Error=Error>>;
 
 pub use self::debug::Debug;
 pub use tk_http::server::EncoderDone;
-pub use self::encoder::{Encoder, IntoContext, Context};
+pub use self::encoder::{Encoder, WaitFlush, IntoContext, Context};
 pub use self::input::{Input};
+pub use self::authorizer::{Check, AuthFuture, Deny, Identity};
+pub use self::authorizer::is_identity_header;
 pub use self::quick_reply::reply;
 pub use self::router::Router;
 
@@ -0,0 +1,135 @@
+use std::cell::{Cell, RefCell};
+use std::mem;
+use std::rc::Rc;
+
+use futures::{Async, Future};
+use futures::future::FutureResult;
+use tk_bufstream::{ReadBuf, WriteBuf};
+use tk_http::Status;
+use tk_http::server::{Error, Codec, RecvMode, EncoderDone};
+use tk_http::server as http;
+
+use crate::default_error_page::{error_page, unauthorized_page};
+use crate::incoming::{Request, Reply, Encoder, Context};
+use crate::incoming::authorizer::AuthFuture;
+
+
+enum State {
+    Pending(AuthFuture),
+    Allowed,
+    /// Contains `WWW-Authenticate` value if credentials may be retried
+    Denied(Option<String>),
+    Void,
+}
+
+fn deny_page<S: 'static>(challenge: Option<String>, e: Encoder<S>)
+    -> FutureResult<EncoderDone<S>, Error>
+{
+    match challenge {
+        Some(challenge) => unauthorized_page(&challenge, e),
+        None => error_page(Status::Forbidden, e),
+    }
+}
+
+/// Holds the request until asynchronous authorizer makes a decision
+///
+/// Handler is created upfront, but it receives neither request body nor
+/// a chance to respond until request is allowed.
+pub struct PendingAuth<S> {
+    state: State,
+    allowed: Rc<Cell<bool>>,
+    inner: Rc<RefCell<Request<S>>>,
+    context: Option<Context>,
+}
+
+impl<S: 'static> PendingAuth<S> {
+    pub fn wrap(auth: AuthFuture, inner: Request<S>, context: Context)
+        -> Request<S>
+    {
+        Box::new(PendingAuth {
+            state: State::Pending(auth),
+            allowed: Rc::new(Cell::new(false)),
+            inner: Rc::new(RefCell::new(inner)),
+            context: Some(context),
+        })
+    }
+    fn poll_auth(&mut self) -> Async<()> {
+        self.state = match mem::replace(&mut self.state, State::Void) {
+            State::Pending(mut auth) => match auth.poll() {
+                Ok(Async::NotReady) => {
+                    self.state = State::Pending(auth);
+                    return Async::NotReady;
+                }
+                Ok(Async::Ready(())) => {
+                    self.allowed.set(true);
+                    State::Allowed
+                }
+                Err(deny) => {
+                    if let Some((_, ref mut debug)) = self.context {
+                        debug.set_deny(deny.reason);
+                    }
+                    State::Denied(deny.challenge)
+                }
+            },
+            state => state,
+        };
+        Async::Ready(())
+    }
+}
+
+impl<S: 'static> Codec<S> for PendingAuth<S> {
+    type ResponseFuture = Reply<S>;
+    fn recv_mode(&mut self) -> RecvMode {
+        self.inner.borrow_mut().recv_mode()
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        if self.poll_auth().is_not_ready() {
+            return Ok(Async::NotReady);
+        }
+        if self.allowed.get() {
+            self.inner.borrow_mut().data_received(data, end)
+        } else {
+            // Discard request body
+            Ok(Async::Ready(data.len()))
+        }
+    }
+    fn start_response(&mut self, e: http::Encoder<S>) -> Reply<S> {
+        let (config, mut debug) = self.context.take()
+            .expect("start response called once");
+        match mem::replace(&mut self.state, State::Void) {
+            State::Allowed => self.inner.borrow_mut().start_response(e),
+            State::Denied(challenge) => {
+                Box::new(deny_page(challenge,
+                    Encoder::new(e, (config, debug))))
+            }
+            // Hijacking codecs don't receive request body, so decision
+            // might still be unknown here
+            State::Pending(auth) => {
+                let allowed = self.allowed.clone();
+                let inner = self.inner.clone();
+                Box::new(auth.then(move |result| -> Reply<S> {
+                    match result {
+                        Ok(()) => {
+                            allowed.set(true);
+                            inner.borrow_mut().start_response(e)
+                        }
+                        Err(deny) => {
+                            debug.set_deny(deny.reason);
+                            Box::new(deny_page(deny.challenge,
+                                Encoder::new(e, (config, debug))))
+                        }
+                    }
+                }))
+            }
+            State::Void => unreachable!(),
+        }
+    }
+    fn hijack(&mut self, write_buf: WriteBuf<S>, read_buf: ReadBuf<S>) {
+        // Connection is closed if request was denied
+        if self.allowed.get() {
+            self.inner.borrow_mut().hijack(write_buf, read_buf);
+        }
+    }
+}
@@ -6,14 +6,18 @@ use tk_http::Status;
 use tk_http::server::{Dispatcher, Error as ServerError, Head};
 
 use crate::runtime::Runtime;
-use crate::incoming::{Request, Debug, Input, Transport};
+use crate::incoming::{Request, Debug, Input, Transport, Check, Identity};
+use crate::incoming::pending_auth::PendingAuth;
 use crate::routing::{parse_host, route};
-use crate::default_error_page::serve_error_page;
+use crate::config::Handler;
+use crate::handlers::split;
+use crate::default_error_page::{serve_error_page, serve_unauthorized};
 use crate::request_id;
 
 use crate::metrics::{Counter};
 use crate::logging;
 use crate::request_id::RequestId;
+use crate::intern::LogFormatName;
 
 
 lazy_static! {
@@ -29,6 +33,7 @@ pub struct Router {
 
 pub enum Error {
     Page(Status, Debug),
+    Unauthorized(String, Debug),
     Fallback(ServerError),
 }
 
@@ -46,8 +51,9 @@ impl Router {
 
 impl Router {
 
+    /// Sets `log_format` when request is routed to a route having one
     fn start_request<S: Transport>(&mut self, headers: &Head,
-        request_id: RequestId)
+        request_id: RequestId, log_format: &mut Option<LogFormatName>)
         -> Result<Request<S>, Error>
     {
         use self::Error::*;
@@ -83,14 +89,34 @@ impl Router {
         */
 
         let matched_route = parsed_host
-            .and_then(|host| route(host, &path, &cfg.routing));
+            .and_then(|host| route(host, &path, headers, &cfg.routing));
 
-        let (route, pref, suf) = if let Some((route, p, s)) = matched_route {
-            (route, p, s)
+        let (route, pref, suf, captures) = if let Some(m) = matched_route {
+            m
         } else {
             return Err(Page(Status::NotFound, debug));
         };
         debug.set_route(route);
+        *log_format = route.log_format.clone();
+
+        let variant = match route.handler {
+            Handler::Split(ref settings) => {
+                match split::select(settings, &route.variants, headers,
+                                    self.addr.ip())
+                {
+                    Some(variant) => {
+                        debug.set_variant(&variant.name);
+                        Some(variant)
+                    }
+                    // all weights are zero
+                    None => {
+                        return Err(Page(Status::ServiceUnavailable, debug));
+                    }
+                }
+            }
+            _ => None,
+        };
+        let handler = variant.map(|v| &v.handler).unwrap_or(&route.handler);
 
         let mut inp = Input {
             addr: self.addr,
@@ -102,17 +128,35 @@ impl Router {
             suffix: suf,
             handle: &self.handle,
             request_id: request_id,
+            identity: Identity::new(),
+            captures: captures,
+            options: &route.options,
+            identity_headers: &route.identity_headers,
         };
 
         match route.authorizer.check(&mut inp) {
-            Ok(true) => {}
-            Ok(false) => {
+            Ok(Check::Done(true)) => {}
+            Ok(Check::Done(false)) => {
                 return Err(Page(Status::Forbidden, inp.debug));
             }
+            Ok(Check::Unauthorized(challenge)) => {
+                return Err(Unauthorized(challenge, inp.debug));
+            }
+            Ok(Check::Pending(auth)) => {
+                let mut debug = Debug::new(headers, request_id, &cfg);
+                debug.set_route(route);
+                if let Some(variant) = variant {
+                    debug.set_variant(&variant.name);
+                }
+                let ctx = (cfg.clone(), debug);
+                return handler.serve(inp)
+                    .map(|codec| PendingAuth::wrap(auth, codec, ctx))
+                    .map_err(Fallback);
+            }
             Err(e) => return Err(Fallback(e)),
         }
 
-        route.handler.serve(inp).map_err(Fallback)
+        handler.serve(inp).map_err(Fallback)
     }
 }
 
@@ -122,10 +166,11 @@ impl<S: Transport> Dispatcher<S> for Router {
         -> Result<Self::Codec, ServerError>
     {
         let request_id = request_id::new();
-        match self.start_request(headers, request_id) {
+        let mut log_format = None;
+        match self.start_request(headers, request_id, &mut log_format) {
             Ok(x) => {
                 // TODO(tailhook) request is not done yet, just a fake
-                logging::log(&self.runtime,
+                logging::log(&self.runtime, log_format.as_ref(),
                     logging::http::FakePage {
                         request: logging::http::EarlyRequest {
                             addr: self.addr,
@@ -138,7 +183,7 @@ impl<S: Transport> Dispatcher<S> for Router {
                 Ok(x)
             }
             Err(Error::Page(status, debug)) => {
-                logging::log(&self.runtime,
+                logging::log(&self.runtime, log_format.as_ref(),
                     logging::http::EarlyError {
                         request: logging::http::EarlyRequest {
                             addr: self.addr,
@@ -152,6 +197,21 @@ impl<S: Transport> Dispatcher<S> for Router {
                 Ok(serve_error_page(status,
                     (self.runtime.config.get(), debug)))
             }
+            Err(Error::Unauthorized(challenge, debug)) => {
+                logging::log(&self.runtime, log_format.as_ref(),
+                    logging::http::EarlyError {
+                        request: logging::http::EarlyRequest {
+                            addr: self.addr,
+                            head: headers,
+                            request_id: request_id,
+                        },
+                        response: logging::http::EarlyResponse {
+                            status: Status::Unauthorized.into(),
+                        }
+                    });
+                Ok(serve_unauthorized(challenge,
+                    (self.runtime.config.get(), debug)))
+            }
             // Maybe return bad request?
             Err(Error::Fallback(e)) => Err(e),
         }
@@ -0,0 +1,225 @@
+//! Listeners that don't need name resolution: unix sockets and file
+//! descriptors inherited from the parent process (systemd socket activation)
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::{Future, Stream};
+use futures::sync::oneshot::{channel as oneshot, Sender};
+use tk_listen::ListenExt;
+use tokio_core::reactor::Handle;
+
+use crate::config::listen::{Listen, ListenSocket};
+use crate::incoming::Transport;
+
+
+pub type Connection = Box<dyn Future<Item=(), Error=()>>;
+type Incoming = Box<dyn Stream<Item=Connection, Error=io::Error>>;
+
+/// Starts protocol on the accepted connection
+///
+/// For unix sockets peer address is `unix_peer()`.
+pub trait Acceptor: Clone + 'static {
+    fn accept<S: Transport>(&self, socket: S, addr: SocketAddr)
+        -> Connection;
+}
+
+pub struct LocalListeners<A: Acceptor> {
+    acceptor: A,
+    handle: Handle,
+    listeners: HashMap<ListenSocket, Sender<()>>,
+}
+
+/// Peer address of connections accepted on unix sockets
+///
+/// It's `0.0.0.0:0` which is never an address of a TCP peer, so unix
+/// peers aren't mistaken for local TCP connections. Use `PeerIp` to show
+/// the address to users and backends.
+fn unix_peer() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 0))
+}
+
+pub fn is_unix_peer(addr: &SocketAddr) -> bool {
+    *addr == unix_peer()
+}
+
+/// Formats ip of the peer for logs and ``ip-header``, peers connected
+/// to unix sockets are shown as `unix`
+#[derive(Debug, Clone, Copy)]
+pub struct PeerIp(pub SocketAddr);
+
+impl fmt::Display for PeerIp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if is_unix_peer(&self.0) {
+            f.write_str("unix")
+        } else {
+            self.0.ip().fmt(f)
+        }
+    }
+}
+
+#[cfg(unix)]
+fn incoming<A: Acceptor>(sock: &ListenSocket, acceptor: A, handle: &Handle)
+    -> io::Result<Incoming>
+{
+    use std::fs::{metadata, remove_file};
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::io::{FromRawFd, RawFd};
+    use std::os::unix::net as std_unix;
+    use std::net as std_net;
+    use std::mem;
+    use libc;
+    use tokio_core::net::TcpListener;
+    use tokio_uds::UnixListener;
+
+    match *sock {
+        ListenSocket::Unix(ref path) => {
+            // Remove stale socket left from the previous run
+            if metadata(path).map(|m| m.file_type().is_socket())
+                .unwrap_or(false)
+            {
+                remove_file(path)?;
+            }
+            let listener = UnixListener::bind(path, handle)?;
+            Ok(Box::new(listener.incoming().map(move |(sock, _)| {
+                acceptor.accept(sock, unix_peer())
+            })))
+        }
+        ListenSocket::Fd(fd) => {
+            let fd = fd as RawFd;
+            let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+            let mut len = mem::size_of_val(&addr) as libc::socklen_t;
+            let res = unsafe {
+                libc::getsockname(fd,
+                    &mut addr as *mut _ as *mut libc::sockaddr, &mut len)
+            };
+            if res < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            match addr.ss_family as libc::c_int {
+                libc::AF_UNIX => {
+                    let std = unsafe {
+                        std_unix::UnixListener::from_raw_fd(fd)
+                    };
+                    let listener = UnixListener::from_listener(std, handle)?;
+                    Ok(Box::new(listener.incoming().map(move |(sock, _)| {
+                        acceptor.accept(sock, unix_peer())
+                    })))
+                }
+                libc::AF_INET | libc::AF_INET6 => {
+                    let std = unsafe {
+                        std_net::TcpListener::from_raw_fd(fd)
+                    };
+                    let addr = std.local_addr()?;
+                    let listener = TcpListener::from_listener(
+                        std, &addr, handle)?;
+                    Ok(Box::new(listener.incoming().map(move |(sock, a)| {
+                        acceptor.accept(sock, a)
+                    })))
+                }
+                _ => {
+                    Err(io::Error::new(io::ErrorKind::InvalidInput,
+                        format!("file descriptor {} is not a unix or \
+                                 tcp socket", fd)))
+                }
+            }
+        }
+        ListenSocket::Tcp(..) | ListenSocket::Tls(..) => unreachable!(),
+    }
+}
+
+#[cfg(not(unix))]
+fn incoming<A: Acceptor>(_: &ListenSocket, _: A, _: &Handle)
+    -> io::Result<Incoming>
+{
+    Err(io::Error::new(io::ErrorKind::Other,
+        "unix sockets and file descriptors are not supported \
+         on this platform"))
+}
+
+/// Checks that file descriptor was passed by systemd
+///
+/// It's only a warning, as file descriptors might be passed by
+/// some other supervisor.
+fn check_fd(fd: u32) {
+    use std::env;
+    use std::process;
+
+    let pid = env::var("LISTEN_PID").ok()
+        .and_then(|x| x.parse::<u32>().ok());
+    let fds = env::var("LISTEN_FDS").ok()
+        .and_then(|x| x.parse::<u32>().ok());
+    match (pid, fds) {
+        (Some(pid), Some(fds)) if pid == process::id() => {
+            // systemd passes sockets starting from fd 3
+            if fd < 3 || fd >= 3 + fds {
+                warn!("File descriptor {} is not in LISTEN_FDS range \
+                       3..{}", fd, 3 + fds);
+            }
+        }
+        _ => {
+            warn!("File descriptor {} is configured to listen on, \
+                   but no LISTEN_FDS passed for this process", fd);
+        }
+    }
+}
+
+impl<A: Acceptor> LocalListeners<A> {
+    pub fn new(acceptor: A, handle: &Handle) -> LocalListeners<A> {
+        LocalListeners {
+            acceptor: acceptor,
+            handle: handle.clone(),
+            listeners: HashMap::new(),
+        }
+    }
+    /// Starts listeners for new sockets and stops removed ones
+    ///
+    /// Note: file descriptor is closed when it's removed from the config,
+    /// so it can't be added back without restarting the process.
+    pub fn update(&mut self, listen: &Listen, max_connections: usize,
+        listen_error_timeout: Duration, verbose: bool)
+    {
+        let sockets = listen.local();
+        let to_delete = self.listeners.keys()
+            .filter(|k| !sockets.contains(k))
+            .cloned()
+            .collect::<Vec<_>>();
+        for sock in to_delete {
+            if let Some(tx) = self.listeners.remove(&sock) {
+                tx.send(()).ok();
+            }
+        }
+        for sock in sockets {
+            if self.listeners.contains_key(&sock) {
+                continue;
+            }
+            if let ListenSocket::Fd(fd) = sock {
+                check_fd(fd);
+            }
+            let stream = match
+                incoming(&sock, self.acceptor.clone(), &self.handle)
+            {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!("Can't listen {:?}: {}", sock, e);
+                    continue;
+                }
+            };
+            if verbose {
+                println!("Listening at {:?}", sock);
+            }
+            let (tx, rx) = oneshot();
+            let s1 = sock.clone();
+            let s2 = sock.clone();
+            self.handle.spawn(stream
+                .sleep_on_error(listen_error_timeout, &self.handle)
+                .listen(max_connections)
+                .select(rx.then(|_| Ok::<(), ()>(())))
+                .map(move |_| info!("Listener {:?} stopped", s1))
+                .map_err(move |_| error!("Listener {:?} errored", s2)));
+            self.listeners.insert(sock, tx);
+        }
+    }
+}
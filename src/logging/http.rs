@@ -5,6 +5,7 @@ use tk_http::Status;
 use tk_http::server::Head;
 use trimmer::{Variable, Var, DataError, Output};
 
+use crate::local_sockets::PeerIp;
 use crate::request_id::RequestId;
 use crate::logging::context::{Context, AsContext};
 
@@ -62,7 +63,7 @@ impl<'a> Variable<'a> for EarlyRequest<'a> {
     {
         match attr {
             // TODO(tailhook) return just IP when trimmer is updated
-            "client_ip" => Ok(Var::owned(self.addr.ip())),
+            "client_ip" => Ok(Var::owned(Display(PeerIp(self.addr)))),
             "host" => Ok(Var::owned(
                 self.head.host()
                 .unwrap_or("-")
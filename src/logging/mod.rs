@@ -8,26 +8,41 @@ pub use self::context::AsContext;
 use std::io::{stdout, Write};
 use std::sync::Arc;
 
+use crate::intern::LogFormatName;
 use crate::runtime::Runtime;
 
 
-pub fn log<C: AsContext>(runtime: &Arc<Runtime>, ctx: C) {
+/// Writes request to the debug log if enabled and using the format of the
+/// route if the route has one
+pub fn log<C: AsContext>(runtime: &Arc<Runtime>,
+    route_format: Option<&LogFormatName>, ctx: C)
+{
     let cfg = runtime.config.get();
-    if cfg.debug_logging {
-        if let Some(ref fmt) = cfg.log_formats.get("debug-log") {
-            let ctx = ctx.as_context();
-            match fmt.template.render(&ctx) {
-                Ok(mut line) => {
-                    line.push('\n');
-                    stdout().write_all(line.as_bytes())
-                        .map_err(|e| {
-                            warn!("Can't write debug log: {}", e)
-                        }).ok();
-                }
-                Err(e) => {
-                    warn!("Can't log request: {:?}", e);
-                }
-            };
-        }
+    let debug = if cfg.debug_logging {
+        cfg.log_formats.get("debug-log")
+    } else {
+        None
+    };
+    let route = route_format
+        // don't log the same line twice
+        .filter(|name| !(debug.is_some() && &name[..] == "debug-log"))
+        .and_then(|name| cfg.log_formats.get(name));
+    if debug.is_none() && route.is_none() {
+        return;
+    }
+    let ctx = ctx.as_context();
+    for fmt in debug.into_iter().chain(route) {
+        match fmt.template.render(&ctx) {
+            Ok(mut line) => {
+                line.push('\n');
+                stdout().write_all(line.as_bytes())
+                    .map_err(|e| {
+                        warn!("Can't write log: {}", e)
+                    }).ok();
+            }
+            Err(e) => {
+                warn!("Can't log request: {:?}", e);
+            }
+        };
     }
 }
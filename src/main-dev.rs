@@ -8,15 +8,19 @@
 #[macro_use] extern crate serde_json;
 
 mod authorizers;
+mod balancer;
 mod base64;
 mod chat;
 mod config;
+mod cookie;
 mod default_error_page;
 mod dev;
 mod handlers;
+mod health_check;
 mod http_pools;  // TODO(tailhook) move to proxy?
 mod incoming;
 mod intern;
+mod local_sockets;
 mod logging;
 mod metrics;
 mod proxy;
@@ -25,6 +29,7 @@ mod routing;
 mod runtime;
 mod startup;
 mod template;
+mod tls;
 
 use std::process::exit;
 use std::env;
@@ -6,14 +6,19 @@
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate serde_json;
 mod authorizers;
+mod balancer;
 mod base64;
 mod chat;
 mod config;
+mod cookie;
 mod default_error_page;
+mod explain_route;
 mod handlers;
+mod health_check;
 mod http_pools;  // TODO(tailhook) move to proxy?
 mod incoming;
 mod intern;
+mod local_sockets;
 mod logging;
 mod metrics;
 mod privileges;
@@ -23,6 +28,7 @@ mod routing;
 mod runtime;
 mod startup;
 mod template;
+mod tls;
 mod updater;
 
 use std::env;
@@ -31,6 +37,7 @@ use std::process::exit;
 
 use futures::stream::Stream;
 use argparse::{ArgumentParser, Parse, StoreTrue, Print};
+use argparse::{StoreOption, Collect};
 use tokio_core::reactor::Core;
 
 
@@ -43,6 +50,9 @@ pub fn main() {
     let mut config = String::from("/etc/swindon/main.yaml");
     let mut check = false;
     let mut verbose = false;
+    let mut explain = None::<String>;
+    let mut method = String::from("GET");
+    let mut headers = Vec::<String>::new();
     {
         let mut ap = ArgumentParser::new();
         ap.set_description("Runs a web server");
@@ -53,6 +63,18 @@ pub fn main() {
         ap.refer(&mut check)
           .add_option(&["-C", "--check-config"], StoreTrue,
             "Check configuration file and exit");
+        ap.refer(&mut explain)
+          .add_option(&["--explain-route"], StoreOption,
+            "Print how request to HOST/PATH is routed and exit")
+          .metavar("HOST/PATH");
+        ap.refer(&mut method)
+          .add_option(&["--method"], Parse,
+            "Request method for --explain-route (default GET)")
+          .metavar("METHOD");
+        ap.refer(&mut headers)
+          .add_option(&["--header"], Collect,
+            "Request header for --explain-route, may be repeated")
+          .metavar("NAME:VALUE");
         ap.add_option(&["--version"],
             Print(env!("CARGO_PKG_VERSION").to_string()),
             "Show version");
@@ -72,6 +94,18 @@ pub fn main() {
     };
     let cfg = configurator.config();
 
+    if let Some(target) = explain {
+        match explain_route::explain_route(&cfg.get(), &target,
+                                           &method, &headers)
+        {
+            Ok(()) => exit(0),
+            Err(e) => {
+                writeln!(&mut io::stderr(), "{}", e).ok();
+                exit(1);
+            }
+        }
+    }
+
     if check {
         if verbose {
             println!("Config fingerprint: {}", cfg.fingerprint());
@@ -1,4 +1,8 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
 
 use libcantal::{self, Name, NameVisitor, Value, Collection, Error};
 use owning_ref::OwningHandle;
@@ -16,6 +20,124 @@ struct Wrapper(libcantal::ActiveCollection<'static>);
 
 pub struct ActiveCollection(OwningHandle<Box<Vec<Box<dyn Collection>>>, Wrapper>);
 
+/// Metrics for keys that appear at runtime, like addresses of http
+/// destinations or variants of the `!Split` handler
+///
+/// Metrics are visited by libcantal by reference, so they are never freed.
+/// Instead, when the last `Shared` handle of the key is dropped, metrics are
+/// reset and kept until the same key is used again (e.g. an address
+/// disappears from DNS for a while). Counters can't be reset, so metrics are
+/// never reused for a different key.
+pub struct Registry<K, M: 'static>(Mutex<Entries<K, M>>);
+
+struct Entries<K, M: 'static> {
+    used: HashMap<K, (&'static M, usize)>,
+    free: HashMap<K, &'static M>,
+}
+
+/// Metrics which are kept in the `Registry` while their key is unused
+pub trait Reset {
+    /// Resets gauges, counters keep counting as only their rate matters
+    fn reset(&self);
+}
+
+impl Reset for Counter {
+    fn reset(&self) {}
+}
+
+/// Handle to the metrics of a single key in the `Registry`
+pub struct Shared<K: Hash + Eq + Clone + 'static, M: Reset + 'static> {
+    registry: &'static Registry<K, M>,
+    key: K,
+    metrics: &'static M,
+}
+
+impl<K: Hash + Eq + Clone, M: Reset> Registry<K, M> {
+    pub fn new() -> Registry<K, M> {
+        Registry(Mutex::new(Entries {
+            used: HashMap::new(),
+            free: HashMap::new(),
+        }))
+    }
+    /// Returns metrics of the key, `new` is called if there are no metrics
+    /// to reuse
+    pub fn get<F>(&'static self, key: &K, new: F) -> Shared<K, M>
+        where F: FnOnce() -> M
+    {
+        let mut entries = self.0.lock().expect("metrics not poisoned");
+        let metrics = match entries.used.get_mut(key) {
+            Some(&mut (metrics, ref mut refs)) => {
+                *refs += 1;
+                metrics
+            }
+            None => {
+                let metrics = entries.free.remove(key)
+                    .unwrap_or_else(|| Box::leak(Box::new(new())));
+                entries.used.insert(key.clone(), (metrics, 1));
+                metrics
+            }
+        };
+        Shared { registry: self, key: key.clone(), metrics }
+    }
+    /// Returns metrics of the keys that are currently used
+    pub fn used(&self) -> Vec<(K, &'static M)> {
+        self.0.lock().expect("metrics not poisoned")
+            .used.iter()
+            .map(|(key, &(metrics, _))| (key.clone(), metrics))
+            .collect()
+    }
+}
+
+impl<K: Hash + Eq + Clone, M: Reset> Deref for Shared<K, M> {
+    type Target = M;
+    fn deref(&self) -> &M {
+        self.metrics
+    }
+}
+
+impl<K: Hash + Eq + Clone, M: Reset> Clone for Shared<K, M> {
+    fn clone(&self) -> Shared<K, M> {
+        let mut entries = self.registry.0.lock()
+            .expect("metrics not poisoned");
+        if let Some(&mut (_, ref mut refs)) = entries.used.get_mut(&self.key)
+        {
+            *refs += 1;
+        }
+        Shared {
+            registry: self.registry,
+            key: self.key.clone(),
+            metrics: self.metrics,
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, M: Reset> Drop for Shared<K, M> {
+    fn drop(&mut self) {
+        let mut entries = self.registry.0.lock()
+            .expect("metrics not poisoned");
+        let unused = match entries.used.get_mut(&self.key) {
+            Some(&mut (_, ref mut refs)) => {
+                *refs -= 1;
+                *refs == 0
+            }
+            None => false,
+        };
+        if unused {
+            entries.used.remove(&self.key);
+            self.metrics.reset();
+            entries.free.insert(self.key.clone(), self.metrics);
+        }
+    }
+}
+
+impl<K, M> fmt::Debug for Shared<K, M>
+    where K: Hash + Eq + Clone + fmt::Debug, M: Reset
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Shared").field(&self.key).finish()
+    }
+}
+
 impl<'a> Name for Metric<'a> {
     fn get(&self, key: &str) -> Option<&str> {
         match key {
@@ -38,6 +160,7 @@ impl ::std::ops::Deref for Wrapper {
 pub fn all(runtime: &Arc<Runtime>) -> Box<Vec<Box<dyn Collection>>> {
     Box::new(vec![
         Box::new(crate::incoming::metrics()),
+        Box::new(crate::handlers::split::metrics()),
         Box::new(crate::chat::metrics()),
         Box::new(crate::http_pools::metrics()),
         Box::new(crate::http_pools::pool_metrics(&runtime.http_pools)),
@@ -49,3 +172,34 @@ pub fn start(runtime: &Arc<Runtime>) -> Result<ActiveCollection, Error> {
         libcantal::start(unsafe { &*m }).map(Wrapper)
     }).map(ActiveCollection)
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Registry, Reset, Integer};
+
+    struct Gauge(Integer);
+
+    impl Reset for Gauge {
+        fn reset(&self) {
+            self.0.set(0);
+        }
+    }
+
+    #[test]
+    fn reuse_metrics() {
+        let registry: &'static Registry<u32, Gauge> =
+            Box::leak(Box::new(Registry::new()));
+        let a = registry.get(&1, || Gauge(Integer::new()));
+        a.0.set(5);
+        let b = a.clone();
+        drop(a);
+        assert_eq!(registry.used().len(), 1);
+        drop(b);
+        assert_eq!(registry.used().len(), 0);
+        let c = registry.get(&1, || unreachable!());
+        assert_eq!(c.0.get(), 0);
+        let d = registry.get(&2, || Gauge(Integer::new()));
+        d.0.set(3);
+        assert_eq!(c.0.get(), 0);
+    }
+}
@@ -1,27 +1,31 @@
 use std::mem;
 use std::sync::Arc;
 
-use futures::Async;
-use futures::future::{FutureResult, ok};
-use futures::sync::oneshot;
+use futures::{Async, AsyncSink, Sink};
+use futures::future::ok;
+use futures::sync::{mpsc, oneshot};
 use tk_http::client as http;
+use tokio_io::AsyncWrite;
 
 use crate::config::http_destinations::Destination;
-use crate::proxy::{RepReq, HalfResp, Response};
+use crate::http_pools::HttpFuture;
+use crate::proxy::{RepReq, BodyStream, HalfResp, Response, Chunk};
 
 enum State {
     Init(RepReq),
     Wait,
-    Headers(HalfResp),
-    #[allow(dead_code)]
-    Done(Response),
+    Body(mpsc::Sender<Chunk>),
+    Done,
     Void,
 }
 
 
 pub struct Codec {
     state: State,
+    body: Option<BodyStream>,
     destination: Arc<Destination>,
+    /// Dropped without a reply if request fails (including timeout of the
+    /// backend connection), so response is canceled
     sender: Option<oneshot::Sender<Response>>,
 }
 
@@ -32,19 +36,35 @@ impl Codec {
     {
         Codec {
             state: State::Init(req),
+            body: None,
             destination: destination.clone(),
             sender: Some(tx),
         }
     }
+    /// Creates codec which forwards request body as it arrives
+    pub fn streaming(req: RepReq, body: BodyStream,
+        destination: &Arc<Destination>, tx: oneshot::Sender<Response>)
+        -> Codec
+    {
+        Codec {
+            body: Some(body),
+            .. Codec::new(req, destination, tx)
+        }
+    }
 }
 
-impl<S> http::Codec<S> for Codec {
-    type Future = FutureResult<http::EncoderDone<S>, http::Error>;
+impl<S: AsyncWrite + 'static> http::Codec<S> for Codec {
+    type Future = HttpFuture<S>;
 
     fn start_write(&mut self, e: http::Encoder<S>) -> Self::Future {
         if let State::Init(req) = mem::replace(&mut self.state, State::Void) {
             self.state = State::Wait;
-            ok(req.encode(e, &self.destination))
+            match self.body.take() {
+                Some(body) => {
+                    Box::new(req.encode_streaming(e, &self.destination, body))
+                }
+                None => Box::new(ok(req.encode(e, &self.destination))),
+            }
         } else {
             panic!("wrong state");
         }
@@ -53,9 +73,16 @@ impl<S> http::Codec<S> for Codec {
         -> Result<http::RecvMode, http::Error>
     {
         if let State::Wait = mem::replace(&mut self.state, State::Void) {
-            self.state = State::Headers(HalfResp::from_headers(headers));
-            // TODO(tailhook) limit and streaming
-            Ok(http::RecvMode::buffered(10_485_760))
+            let (tx, rx) = mpsc::channel(1);
+            let resp = HalfResp::from_headers(headers).stream(rx);
+            if self.sender.take().unwrap().send(resp).is_err() {
+                return Err(http::Error::custom(
+                    "client closed connection before response"));
+            }
+            self.state = State::Body(tx);
+            // Forward every chunk as soon as it arrives, this is crucial
+            // for long-polling and server-sent events
+            Ok(http::RecvMode::progressive(1))
         } else {
             panic!("wrong state");
         }
@@ -63,12 +90,34 @@ impl<S> http::Codec<S> for Codec {
     fn data_received(&mut self, data: &[u8], end: bool)
         -> Result<Async<usize>, http::Error>
     {
-        // TODO(tailhook) streaming
-        assert!(end);
         match mem::replace(&mut self.state, State::Void) {
-            State::Headers(hr) => {
-                let resp = hr.complete(data.to_vec());
-                self.sender.take().unwrap().send(resp).ok();
+            State::Body(mut tx) => {
+                match tx.poll_ready() {
+                    Ok(Async::Ready(())) => {}
+                    Ok(Async::NotReady) => {
+                        self.state = State::Body(tx);
+                        return Ok(Async::NotReady);
+                    }
+                    // Client doesn't need the body (e.g. response to
+                    // a HEAD request), but there is nothing to discard
+                    Err(_) if end && data.len() == 0 => {
+                        self.state = State::Done;
+                        return Ok(Async::Ready(0));
+                    }
+                    Err(_) => {
+                        return Err(http::Error::custom(
+                            "client closed connection"));
+                    }
+                }
+                match tx.start_send((data.to_vec(), end)) {
+                    Ok(AsyncSink::Ready) => {}
+                    Ok(AsyncSink::NotReady(_)) => unreachable!(),
+                    Err(_) => {
+                        return Err(http::Error::custom(
+                            "client closed connection"));
+                    }
+                }
+                self.state = if end { State::Done } else { State::Body(tx) };
             }
             _ => unreachable!(),
         }
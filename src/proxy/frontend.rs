@@ -1,27 +1,45 @@
 use std::sync::Arc;
 use std::mem;
+use std::time::Duration;
+use std::net::SocketAddr;
 
-use futures::{Async, Future, AsyncSink};
-use futures::future::{ok};
-use futures::sink::{Sink};
-use futures::sync::oneshot;
+use futures::{Async, Future, AsyncSink, Poll, Sink};
+use futures::future::Either;
+use futures::sync::{mpsc, oneshot};
+use futures::sync::oneshot::Canceled;
+use tokio_core::reactor::{Handle, Timeout};
 use tk_http::Status;
 use tk_http::server::{Error, RecvMode};
 use tk_http::server as http;
+use tokio_io::AsyncWrite;
 
+use crate::config::http_destinations::Destination;
 use crate::config::proxy::Proxy;
 use crate::incoming::{Input, Reply, Encoder, Context, IntoContext};
 use crate::default_error_page::error_page;
-use crate::http_pools::{HttpPools, REQUESTS, FAILED_503};
-use crate::proxy:: {RepReq, HalfReq, Response, backend};
+use crate::http_pools::{HttpPools, REQUESTS, FAILED_503, TIMEOUTS_504};
+use crate::intern::Upstream;
+use crate::proxy:: {RepReq, HalfReq, Response, BodyStream, Chunk, backend};
+use crate::proxy::PathMap;
+
+
+/// Hint for the size of request body chunks in streaming mode
+const STREAM_CHUNK_SIZE: usize = 65536;
 
 
 enum State {
     Headers(HalfReq),
     Sent {
-        /// Mostly to resend the request
-        #[allow(dead_code)]
+        /// Kept to resend the request
         request: RepReq,
+        destination: Arc<Destination>,
+        /// Address request is sent to, if load balancing tells it
+        address: Option<SocketAddr>,
+        response: oneshot::Receiver<Response>,
+    },
+    Streaming {
+        /// `None` when whole body is forwarded or backend has gone away
+        body: Option<mpsc::Sender<Chunk>>,
         response: oneshot::Receiver<Response>,
     },
     Error(Status),
@@ -31,103 +49,159 @@ enum State {
 
 pub struct Codec {
     settings: Arc<Proxy>,
+    /// Either the setting of the handler or overriden by the route
+    max_payload_size: usize,
+    /// Set when paths in response headers need to be rewritten
+    paths: Option<PathMap>,
+    /// Either the setting of the destination or overriden by the route,
+    /// `max-request-timeout` of the destination if neither is set
+    response_timeout: Option<Duration>,
+    /// Either the setting of the destination or overriden by the route,
+    /// `input-body-whole-timeout` of the server if neither is set
+    input_body_timeout: Option<Duration>,
+    /// Response timeout, started when request is sent to the backend
+    timeout: Option<Timeout>,
+    handle: Handle,
     pools: HttpPools,
     state: State,
     context: Option<Context>,
 }
 
-impl<S: 'static> http::Codec<S> for Codec {
+/// Waits for response, and resends idempotent request if connection to
+/// backend fails or request times out before response headers are received
+struct WaitResponse {
+    request: RepReq,
+    destination: Arc<Destination>,
+    response: oneshot::Receiver<Response>,
+    /// Key for `consistent_hash`, so retry goes to the next address on
+    /// the ring rather than to a random one
+    key: Option<u64>,
+    /// Addresses that failed, request isn't retried on them
+    tried: Vec<SocketAddr>,
+    retries: u32,
+    pools: HttpPools,
+    upstream: Upstream,
+    /// Response timeout of the current attempt, restarted on retry
+    timeout: Option<Timeout>,
+    response_timeout: Option<Duration>,
+    handle: Handle,
+}
+
+impl<S: AsyncWrite + 'static> http::Codec<S> for Codec {
     type ResponseFuture = Reply<S>;
     fn recv_mode(&mut self) -> RecvMode {
-        if self.settings.stream_requests {
-            unimplemented!();
+        let mode = if self.settings.stream_requests {
+            RecvMode::progressive(STREAM_CHUNK_SIZE)
         } else {
-            RecvMode::buffered_upfront(self.settings.max_payload_size)
+            RecvMode::buffered_upfront(self.max_payload_size)
+        };
+        match self.input_body_timeout {
+            Some(timeout) => mode.body_read_timeout(timeout),
+            None => mode,
         }
     }
     fn data_received(&mut self, data: &[u8], end: bool)
         -> Result<Async<usize>, Error>
     {
         if self.settings.stream_requests {
-            unimplemented!();
+            if let State::Headers(..) = self.state {
+                // Request is sent only when request body starts to arrive,
+                // so that request isn't sent until authorizer allows it
+                self.start_streaming();
+            }
         }
         self.state = match mem::replace(&mut self.state, State::Void) {
             State::Error(e) => State::Error(e),
             State::Headers(r) => {
                 assert!(end);
                 let r = r.upgrade(data.to_vec());
-                let dest_name = &self.settings.destination.upstream;
-                let mut up = self.pools.upstream(dest_name);
                 let (tx, rx) = oneshot::channel();
-                let ref cfg = self.context.as_ref().unwrap().0;
-                let opt_dest = cfg.http_destinations.get(dest_name);
-                if let Some(dest_settings) = opt_dest {
-                    let codec = Box::new(backend::Codec::new(r.clone(),
-                        dest_settings, tx));
-                    match up.get_mut().get_mut() {
-                        Some(pool) => {
-                            match pool.start_send(codec) {
-                                Ok(AsyncSink::NotReady(_)) => {
-                                    FAILED_503.incr(1);
-                                    State::Error(Status::ServiceUnavailable)
-                                }
-                                Ok(AsyncSink::Ready) => {
-                                    debug!("Sent request {:?} to proxy", r);
-                                    REQUESTS.incr(1);
-                                    State::Sent {
-                                        request: r,
-                                        response: rx,
-                                    }
-                                }
-                                Err(e) => {
-                                    error!("Error sending to pool {:?}: {}",
-                                        self.settings.destination.upstream, e);
-                                    State::Error(Status::InternalServerError)
-                                }
-                            }
+                match self.send(&r, tx, |r, dest, tx| {
+                    backend::Codec::new(r, dest, tx)
+                }) {
+                    Ok((dest, address)) => {
+                        self.start_timeout();
+                        State::Sent {
+                            request: r,
+                            destination: dest,
+                            address: address,
+                            response: rx,
                         }
-                        None => {
-                            error!("No such pool {:?}",
-                                self.settings.destination.upstream);
-                            State::Error(Status::NotFound)
+                    }
+                    Err(status) => State::Error(status),
+                }
+            }
+            State::Streaming { body: Some(mut tx), response } => {
+                match tx.poll_ready() {
+                    Ok(Async::NotReady) => {
+                        self.state = State::Streaming {
+                            body: Some(tx),
+                            response: response,
+                        };
+                        return Ok(Async::NotReady);
+                    }
+                    Ok(Async::Ready(())) => {
+                        let sent = tx.start_send((data.to_vec(), end)).is_ok();
+                        State::Streaming {
+                            body: if sent && !end { Some(tx) } else { None },
+                            response: response,
                         }
                     }
-                } else {
-                    error!("No such destination {:?}",
-                        self.settings.destination.upstream);
-                    State::Error(Status::NotFound)
+                    Err(_) => {
+                        // Backend connection is closed, error will be
+                        // reported via response channel
+                        State::Streaming { body: None, response }
+                    }
                 }
             }
+            State::Streaming { body: None, response } => {
+                // Discard the rest of the body
+                State::Streaming { body: None, response }
+            }
             State::Sent { .. } => unimplemented!(),
             State::Void => unreachable!(),
         };
         return Ok(Async::Ready(data.len()));
     }
     fn start_response(&mut self, e: http::Encoder<S>) -> Reply<S> {
-        if self.settings.stream_requests {
-            unimplemented!();
-        } else {
-            let ctx = self.context.take().unwrap();
-            match mem::replace(&mut self.state, State::Void) {
-                State::Sent { response, .. } => {
-                    Box::new(response.then(move |result| {
-                        let e = Encoder::new(e, ctx);
-                        match result {
-                            Ok(resp) => {
-                                ok(resp.encode(e))
-                            }
-                            Err(err) => {
-                                debug!("Proxy request error: {:?}", err);
-                                error_page(Status::BadGateway, e)
-                            }
-                        }
-                    }))
-                }
-                State::Error(status) => {
-                    Box::new(error_page(status, Encoder::new(e, ctx)))
-                }
-                _ => unreachable!(),
+        let ctx = self.context.take().unwrap();
+        let buffer_size = self.settings.response_buffer_size;
+        let paths = self.paths.take();
+        let timeout = self.timeout.take();
+        match mem::replace(&mut self.state, State::Void) {
+            State::Sent { request, destination, address, response } => {
+                let wait = WaitResponse {
+                    key: request.hash_key(&destination),
+                    request: request,
+                    destination: destination,
+                    response: response,
+                    tried: address.into_iter().collect(),
+                    retries: 0,
+                    pools: self.pools.clone(),
+                    upstream: self.settings.destination.upstream.clone(),
+                    timeout: timeout,
+                    response_timeout: self.response_timeout,
+                    handle: self.handle.clone(),
+                };
+                Box::new(wait.then(move |result| {
+                    respond(result, Encoder::new(e, ctx), buffer_size,
+                            paths.as_ref())
+                }))
+            }
+            State::Streaming { response, .. } => {
+                let response = response.map_err(|Canceled| {
+                    debug!("Proxy request error: canceled");
+                    Status::BadGateway
+                });
+                Box::new(deadline(response, timeout).then(move |result| {
+                    respond(result, Encoder::new(e, ctx), buffer_size,
+                            paths.as_ref())
+                }))
+            }
+            State::Error(status) => {
+                Box::new(error_page(status, Encoder::new(e, ctx)))
             }
+            _ => unreachable!(),
         }
     }
 }
@@ -136,9 +210,241 @@ impl Codec {
     pub fn new(settings: &Arc<Proxy>, inp: Input) -> Codec {
         Codec {
             state: State::Headers(HalfReq::from_input(&inp, &settings)),
+            paths: PathMap::from_input(&settings, &inp),
             pools: inp.runtime.http_pools.clone(),
             settings: settings.clone(),
+            max_payload_size: inp.options.max_payload_size
+                .unwrap_or(settings.max_payload_size),
+            response_timeout: inp.config.http_destinations
+                .get(&settings.destination.upstream)
+                .map(|dest| {
+                    inp.options.response_timeout
+                    .or(dest.response_timeout)
+                    .unwrap_or(dest.max_request_timeout)
+                }),
+            input_body_timeout: inp.options.input_body_whole_timeout
+                .or_else(|| {
+                    inp.config.http_destinations
+                    .get(&settings.destination.upstream)
+                    .and_then(|dest| dest.input_body_whole_timeout)
+                }),
+            timeout: None,
+            handle: inp.handle.clone(),
             context: Some(inp.into_context()),
         }
     }
+    fn start_timeout(&mut self) {
+        let handle = &self.handle;
+        self.timeout = self.response_timeout.map(|t| {
+            Timeout::new(t, handle).expect("timeout created")
+        });
+    }
+    /// Sends request to backend before request body is received
+    fn start_streaming(&mut self) {
+        self.state = match mem::replace(&mut self.state, State::Void) {
+            State::Headers(r) => {
+                let (body_tx, body_rx) = mpsc::channel(1);
+                let body = BodyStream::new(r.body_length(), body_rx);
+                let r = r.upgrade(Vec::new());
+                let (tx, rx) = oneshot::channel();
+                match self.send(&r, tx, |r, dest, tx| {
+                    backend::Codec::streaming(r, body, dest, tx)
+                }) {
+                    Ok(_) => {
+                        self.start_timeout();
+                        State::Streaming {
+                            body: Some(body_tx),
+                            response: rx,
+                        }
+                    }
+                    Err(status) => State::Error(status),
+                }
+            }
+            _ => unreachable!(),
+        };
+    }
+    fn send<F>(&self, r: &RepReq, tx: oneshot::Sender<Response>,
+        codec: F)
+        -> Result<(Arc<Destination>, Option<SocketAddr>), Status>
+        where F: FnOnce(RepReq, &Arc<Destination>,
+                        oneshot::Sender<Response>)
+                 -> backend::Codec
+    {
+        let dest_name = &self.settings.destination.upstream;
+        let ref cfg = self.context.as_ref().unwrap().0;
+        let opt_dest = cfg.http_destinations.get(dest_name);
+        if let Some(dest_settings) = opt_dest {
+            let codec = Box::new(codec(r.clone(), dest_settings, tx));
+            let key = r.hash_key(dest_settings);
+            let address = start_send(&self.pools, dest_name, codec, key)?;
+            debug!("Sent request {:?} to proxy", r);
+            Ok((dest_settings.clone(), address))
+        } else {
+            error!("No such destination {:?}", dest_name);
+            Err(Status::NotFound)
+        }
+    }
+}
+
+fn start_send(pools: &HttpPools, upstream: &Upstream,
+    codec: Box<backend::Codec>, key: Option<u64>)
+    -> Result<Option<SocketAddr>, Status>
+{
+    let mut up = pools.upstream(upstream);
+    let mut guard = up.get_mut();
+    let result = match guard.get_mut() {
+        Some(pool) => {
+            match pool.start_send_keyed(codec, key, &[]) {
+                Ok((AsyncSink::NotReady(_), _)) => {
+                    FAILED_503.incr(1);
+                    Err(Status::ServiceUnavailable)
+                }
+                Ok((AsyncSink::Ready, addr)) => {
+                    REQUESTS.incr(1);
+                    Ok(addr)
+                }
+                Err(e) => {
+                    error!("Error sending to pool {:?}: {}", upstream, e);
+                    Err(Status::InternalServerError)
+                }
+            }
+        }
+        None => {
+            error!("No such pool {:?}", upstream);
+            Err(Status::NotFound)
+        }
+    };
+    if result.is_ok() {
+        guard.request_sent();
+    }
+    return result;
+}
+
+/// Fails with `504 Gateway Timeout` if response headers are not received
+/// before timeout
+fn deadline<F>(response: F, timeout: Option<Timeout>)
+    -> Box<dyn Future<Item=Response, Error=Status>>
+    where F: Future<Item=Response, Error=Status> + 'static
+{
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Box::new(response),
+    };
+    Box::new(response.select2(timeout).then(|res| match res {
+        Ok(Either::A((resp, _))) => Ok(resp),
+        Ok(Either::B(((), _))) => {
+            debug!("Proxy request timed out");
+            TIMEOUTS_504.incr(1);
+            Err(Status::GatewayTimeout)
+        }
+        Err(Either::A((status, _))) => Err(status),
+        Err(Either::B((e, _))) => {
+            error!("Timer error: {}", e);
+            Err(Status::InternalServerError)
+        }
+    }))
+}
+
+fn respond<S>(result: Result<Response, Status>, e: Encoder<S>,
+    buffer_size: usize, paths: Option<&PathMap>)
+    -> Reply<S>
+    where S: AsyncWrite + 'static
+{
+    match result {
+        Ok(mut resp) => {
+            if let Some(paths) = paths {
+                resp.rewrite_paths(paths);
+            }
+            resp.encode(e, buffer_size)
+        }
+        Err(status) => {
+            Box::new(error_page(status, e))
+        }
+    }
+}
+
+impl WaitResponse {
+    fn retry(&mut self) -> bool {
+        if self.retries >= self.destination.max_retries ||
+            !self.destination.is_retriable(self.request.method())
+        {
+            return false;
+        }
+        let mut up = self.pools.upstream(&self.upstream);
+        let mut guard = up.get_mut();
+        match guard.get_mut() {
+            Some(ref pool) if pool.has_address_except(&self.tried) => {}
+            _ => {
+                debug!("No other address to retry {:?} on", self.request);
+                return false;
+            }
+        }
+        if !guard.retry() {
+            debug!("Retry budget for {:?} is exceeded", self.upstream);
+            return false;
+        }
+        self.retries += 1;
+        let (tx, rx) = oneshot::channel();
+        let codec = Box::new(backend::Codec::new(self.request.clone(),
+            &self.destination, tx));
+        // Retry doesn't deposit into the retry budget, so it's sent
+        // directly rather than via `start_send`
+        let pool = guard.get_mut().expect("pool checked above");
+        match pool.start_send_keyed(codec, self.key, &self.tried) {
+            Ok((AsyncSink::Ready, addr)) => {
+                debug!("Retrying request {:?} on {:?} (attempt {})",
+                    self.request, addr, self.retries);
+                self.tried.extend(addr);
+                self.response = rx;
+                self.timeout = self.response_timeout.map(|t| {
+                    Timeout::new(t, &self.handle).expect("timeout created")
+                });
+                true
+            }
+            Ok((AsyncSink::NotReady(_), _)) => false,
+            Err(e) => {
+                error!("Error retrying to pool {:?}: {}", self.upstream, e);
+                false
+            }
+        }
+    }
+    /// Returns true if response timeout of the current attempt is reached
+    fn timed_out(&mut self) -> Result<bool, Status> {
+        match self.timeout.as_mut().map(|t| t.poll()) {
+            Some(Ok(Async::Ready(()))) => {
+                TIMEOUTS_504.incr(1);
+                Ok(true)
+            }
+            Some(Ok(Async::NotReady)) | None => Ok(false),
+            Some(Err(e)) => {
+                error!("Timer error: {}", e);
+                Err(Status::InternalServerError)
+            }
+        }
+    }
+}
+
+impl Future for WaitResponse {
+    type Item = Response;
+    type Error = Status;
+    fn poll(&mut self) -> Poll<Response, Status> {
+        loop {
+            let status = match self.response.poll() {
+                Ok(Async::Ready(resp)) => return Ok(Async::Ready(resp)),
+                Ok(Async::NotReady) => {
+                    if self.timed_out()? {
+                        Status::GatewayTimeout
+                    } else {
+                        return Ok(Async::NotReady);
+                    }
+                }
+                // Connecting to backend or sending request failed
+                Err(Canceled) => Status::BadGateway,
+            };
+            if !self.retry() {
+                debug!("Proxy request error: {:?}", status);
+                return Err(status);
+            }
+        }
+    }
 }
@@ -1,7 +1,92 @@
 pub mod frontend;
 pub mod backend;
+pub mod websocket;
 mod response;
 mod request;
+mod rewrite;
 
 pub use self::response::{HalfResp, Response};
-pub use self::request::{HalfReq, RepReq};
+pub use self::request::{HalfReq, RepReq, BodyStream, Chunk, hash_key};
+pub use self::rewrite::PathMap;
+
+use std::str;
+
+use crate::config::proxy::Proxy;
+use crate::incoming::Input;
+
+
+/// Returns length of the body declared in `Content-Length` header
+///
+/// Returns `None` for chunked body, as chunked encoding has precedence over
+/// `Content-Length`, or if body is delimited by closing connection. Which
+/// one is used when there is no `Content-Length` is passed as `default`.
+pub fn body_length<'a, I>(headers: I, default: Option<u64>) -> Option<u64>
+    where I: Iterator<Item=(&'a str, &'a [u8])>
+{
+    let mut length = default;
+    for (name, value) in headers {
+        if name.eq_ignore_ascii_case("Content-Length") {
+            length = str::from_utf8(value).ok()
+                .and_then(|x| x.trim().parse().ok());
+        } else if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            return None;
+        }
+    }
+    return length;
+}
+
+/// Returns path of the request sent to the backend
+///
+/// Destination path may contain groups captured by the route pattern.
+/// Route prefix is stripped and rewrite rules are applied before
+/// destination path is prepended.
+pub fn upstream_path(settings: &Proxy, inp: &Input) -> String {
+    use tk_http::server::RequestTarget::*;
+    let path = if settings.strip_prefix {
+        inp.suffix
+    } else {
+        match *inp.headers.request_target() {
+            Origin(x) => x,
+            Absolute { path, ..} => path,
+            Authority(..) => unreachable!(),
+            Asterisk => "*",
+        }
+    };
+    let rewritten = settings.rewrite.iter()
+        .filter_map(|rule| rule.apply(path))
+        .next();
+    let path = rewritten.as_ref().map(|x| &x[..]).unwrap_or(path);
+    let prefix = inp.captures.expand(&settings.destination.path);
+    if prefix == "/" {
+        if path.starts_with('/') || path == "*" {
+            path.to_string()
+        } else {
+            // stripped prefix of the exact route, e.g. `/admin?x=1`
+            format!("/{}", path)
+        }
+    } else {
+        format!("{}{}", prefix, path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::body_length;
+
+    fn len(headers: &[(&'static str, &'static str)], default: Option<u64>)
+        -> Option<u64>
+    {
+        body_length(headers.iter().map(|&(k, v)| (k, v.as_bytes())),
+                    default)
+    }
+
+    #[test]
+    fn declared_length() {
+        assert_eq!(len(&[("content-length", " 12")], None), Some(12));
+        assert_eq!(len(&[], Some(0)), Some(0));
+        assert_eq!(len(&[], None), None);
+        assert_eq!(len(&[("Content-Length", "x")], Some(0)), None);
+        assert_eq!(len(&[("Content-Length", "12"),
+                         ("Transfer-Encoding", "chunked")], Some(0)), None);
+    }
+}
@@ -1,15 +1,57 @@
+use std::mem;
 use std::sync::Arc;
 use std::net::SocketAddr;
 
+use futures::{Async, Future, Poll, Stream};
+use futures::sync::mpsc;
 use tk_http::Version;
-use tk_http::client::{Encoder, EncoderDone};
+use tk_http::client::{Encoder, EncoderDone, WaitFlush, Error};
+use tokio_io::AsyncWrite;
 
-use crate::config::http_destinations::Destination;
+use crate::balancer;
+use crate::config::http_destinations::{Destination, LoadBalancing, HashKey};
 use crate::config::proxy::Proxy;
-use crate::incoming::{Input};
+use crate::cookie::cookie;
+use crate::incoming::{Input, Identity, is_identity_header};
+use crate::local_sockets::PeerIp;
+use crate::proxy::{upstream_path, body_length};
 use crate::request_id::RequestId;
 
 
+/// Don't read more request body from client until backend connection
+/// buffer is flushed below this size
+const MAX_BUFFERED_BODY: usize = 65536;
+
+
+/// Key for the `consistent_hash` load balancing
+///
+/// Returns `None` for other strategies or if request has no such key.
+pub fn hash_key<'a, I>(dest: &Destination, addr: &SocketAddr, headers: I)
+    -> Option<u64>
+    where I: Iterator<Item=(&'a str, &'a [u8])>
+{
+    if dest.load_balancing != LoadBalancing::consistent_hash {
+        return None;
+    }
+    match dest.hash_key {
+        None | Some(HashKey::ClientIp) => {
+            Some(balancer::hash(addr.ip()))
+        }
+        Some(HashKey::Header(ref name)) => {
+            headers
+            .filter(|&(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| balancer::hash(v))
+            .next()
+        }
+        Some(HashKey::Cookie(ref name)) => {
+            let values = headers
+                .filter(|&(k, _)| k.eq_ignore_ascii_case("Cookie"))
+                .map(|(_, v)| v);
+            cookie(values, name).map(|v| balancer::hash(v.as_bytes()))
+        }
+    }
+}
+
 /// A repeatable (so fully-buffered) request structure
 #[derive(Clone, Debug)]
 pub struct RepReq(Arc<ReqData>);
@@ -22,45 +64,81 @@ pub struct HalfReq {
     headers: Vec<(String, Vec<u8>)>,
     addr: SocketAddr,
     request_id: RequestId,
+    body_length: Option<u64>,
+    identity: Identity,
+    route_headers: Vec<String>,
+}
+
+/// A piece of request body, the flag marks the last chunk
+///
+/// If channel is closed before the last chunk, client has gone away and
+/// we need to drop the backend connection
+pub type Chunk = (Vec<u8>, bool);
+
+/// Request body that is forwarded to the backend as it arrives
+pub struct BodyStream {
+    /// Content length, or `None` for chunked encoding
+    length: Option<u64>,
+    chunks: mpsc::Receiver<Chunk>,
+}
+
+/// Writes streaming request body into backend connection
+pub struct SendBody<S> {
+    state: SendState<S>,
+    chunks: mpsc::Receiver<Chunk>,
+}
+
+enum SendState<S> {
+    Write(Encoder<S>),
+    Flush(WaitFlush<S>),
+    Void,
 }
 
 #[derive(Debug)]
 struct ReqData {
     settings: Arc<Proxy>,
     method: String,
+    /// Path sent to the backend, including destination path
     path: String,
     host: String,
     headers: Vec<(String, Vec<u8>)>,
     addr: SocketAddr,
     request_id: RequestId,
+    /// Headers set by authorizer
+    identity: Vec<(String, String)>,
+    /// Headers which authorizer of the route may set
+    route_headers: Vec<String>,
     body: Vec<u8>,
 }
 
 
 impl HalfReq {
     pub fn from_input(inp: &Input, settings: &Arc<Proxy>) -> HalfReq {
-        use tk_http::server::RequestTarget::*;
-        let path = match *inp.headers.request_target() {
-            Origin(x) => x.to_string(),
-            Absolute { path, ..} => path.to_string(),
-            Authority(..) => unreachable!(),
-            Asterisk => String::from("*"),
-        };
-
         HalfReq {
             settings: settings.clone(),
             method: inp.headers.method().to_string(),
-            path: path,
+            path: upstream_path(settings, inp),
             host: inp.headers.host().expect("host exists").to_string(),
             headers: inp.headers.headers().map(|(k, v)| {
                 (k.to_string(), v.to_vec())
             }).collect(),
             addr: inp.addr,
             request_id: inp.request_id,
+            // request without Content-Length has no body
+            body_length: body_length(inp.headers.all_headers().iter()
+                .map(|h| (h.name, h.value)), Some(0)),
+            identity: inp.identity.clone(),
+            route_headers: inp.identity_headers.to_vec(),
         }
     }
+    /// Body length declared by client, or `None` if body is chunked
+    pub fn body_length(&self) -> Option<u64> {
+        self.body_length
+    }
     pub fn upgrade(self, body: Vec<u8>) -> RepReq {
         RepReq(Arc::new(ReqData {
+            identity: self.identity.headers(
+                &self.settings.identity_headers),
             settings: self.settings,
             method: self.method,
             path: self.path,
@@ -68,22 +146,62 @@ impl HalfReq {
             headers: self.headers,
             addr: self.addr,
             request_id: self.request_id,
+            route_headers: self.route_headers,
             body: body,
         }))
     }
 }
+impl BodyStream {
+    pub fn new(length: Option<u64>, chunks: mpsc::Receiver<Chunk>)
+        -> BodyStream
+    {
+        BodyStream { length, chunks }
+    }
+}
+
 impl RepReq {
     pub fn encode<S>(&self, mut e: Encoder<S>, dest: &Arc<Destination>)
         -> EncoderDone<S>
     {
+        self.encode_headers(&mut e, dest);
         let ref r = *self.0;
-        if r.settings.destination.path == "/" {
-            e.request_line(&r.method, &r.path, Version::Http11);
-        } else {
-            e.request_line(&r.method,
-                &format!("{}{}", r.settings.destination.path, r.path),
-                Version::Http11);
+        e.add_length(r.body.len() as u64).unwrap();
+        e.done_headers().unwrap();
+        if r.body.len() != 0 {
+            e.write_body(&r.body);
+        }
+        return e.done();
+    }
+    pub fn method(&self) -> &str {
+        &self.0.method
+    }
+    /// Key for the `consistent_hash` load balancing
+    pub fn hash_key(&self, dest: &Destination) -> Option<u64> {
+        let ref r = *self.0;
+        hash_key(dest, &r.addr,
+            r.headers.iter().map(|&(ref k, ref v)| (&k[..], &v[..])))
+    }
+    /// Encodes headers and returns a future that forwards request body
+    ///
+    /// Buffered body of this request (if any) is ignored.
+    pub fn encode_streaming<S>(&self, mut e: Encoder<S>,
+        dest: &Arc<Destination>, body: BodyStream)
+        -> SendBody<S>
+    {
+        self.encode_headers(&mut e, dest);
+        match body.length {
+            Some(n) => e.add_length(n).unwrap(),
+            None => e.add_chunked().unwrap(),
+        }
+        e.done_headers().unwrap();
+        SendBody {
+            state: SendState::Write(e),
+            chunks: body.chunks,
         }
+    }
+    fn encode_headers<S>(&self, e: &mut Encoder<S>, dest: &Arc<Destination>) {
+        let ref r = *self.0;
+        e.request_line(&r.method, &r.path, Version::Http11);
 
         // Spec doesn't mandate, but recomments it to be first
         if let Some(ref header) = dest.override_host_header {
@@ -93,21 +211,66 @@ impl RepReq {
         }
         if let Some(ref h) = r.settings.ip_header {
             // NOTE: this can duplicate header value.
-            e.format_header(h, r.addr.ip()).unwrap();
+            e.format_header(h, PeerIp(r.addr)).unwrap();
         }
         if let Some(ref h) = dest.request_id_header {
             // NOTE: this can duplicate header value.
             e.format_header(h, r.request_id).unwrap();
         }
 
+        for &(ref k, ref v) in &r.identity {
+            e.add_header(k, v).unwrap();
+        }
         for &(ref k, ref v) in &r.headers {
+            if is_identity_header(k, &r.identity, &r.route_headers,
+                                  &r.settings.identity_headers)
+            {
+                // Don't let client spoof identity headers
+                continue;
+            }
             e.add_header(k, v).unwrap();
         }
-        e.add_length(r.body.len() as u64).unwrap();
-        e.done_headers().unwrap();
-        if r.body.len() != 0 {
-            e.write_body(&r.body);
+    }
+}
+
+impl<S: AsyncWrite> Future for SendBody<S> {
+    type Item = EncoderDone<S>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<EncoderDone<S>, Error> {
+        loop {
+            self.state = match mem::replace(&mut self.state, SendState::Void) {
+                SendState::Write(mut e) => {
+                    match self.chunks.poll() {
+                        Ok(Async::Ready(Some((data, end)))) => {
+                            if data.len() > 0 {
+                                e.write_body(&data);
+                            }
+                            if end {
+                                return Ok(Async::Ready(e.done()));
+                            }
+                            SendState::Flush(e.wait_flush(MAX_BUFFERED_BODY))
+                        }
+                        Ok(Async::Ready(None)) | Err(()) => {
+                            return Err(Error::custom(
+                                "request body is interrupted by client"));
+                        }
+                        Ok(Async::NotReady) => {
+                            self.state = SendState::Write(e);
+                            return Ok(Async::NotReady);
+                        }
+                    }
+                }
+                SendState::Flush(mut f) => {
+                    match f.poll().map_err(Error::custom)? {
+                        Async::Ready(e) => SendState::Write(e),
+                        Async::NotReady => {
+                            self.state = SendState::Flush(f);
+                            return Ok(Async::NotReady);
+                        }
+                    }
+                }
+                SendState::Void => unreachable!(),
+            }
         }
-        return e.done();
     }
 }
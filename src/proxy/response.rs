@@ -1,8 +1,16 @@
+use std::mem;
+use std::str;
+
+use futures::{Async, Future, Poll, Stream};
+use futures::future::ok;
+use futures::sync::mpsc;
 use tk_http::{Status};
 use tk_http::client::Head;
-use tk_http::server::{EncoderDone};
+use tk_http::server::{EncoderDone, Error};
+use tokio_io::AsyncWrite;
 
-use crate::incoming::Encoder;
+use crate::incoming::{Encoder, WaitFlush, Reply};
+use crate::proxy::{Chunk, PathMap, body_length};
 
 
 #[derive(Debug)]
@@ -15,12 +23,32 @@ pub enum RespStatus {
 pub struct HalfResp {
     status: RespStatus,
     headers: Vec<(String, Vec<u8>)>,
+    body_length: Option<u64>,
 }
 
+/// Response headers, body is received from the backend as it arrives
 pub struct Response {
     status: RespStatus,
     headers: Vec<(String, Vec<u8>)>,
-    body: Vec<u8>,
+    /// Content length, or `None` if we don't know it upfront
+    body_length: Option<u64>,
+    chunks: mpsc::Receiver<Chunk>,
+}
+
+/// Forwards response body to the client
+///
+/// Doesn't read next chunk until output buffer is flushed to the
+/// `response-buffer-size`
+pub struct WriteBody<S> {
+    state: WriteState<S>,
+    chunks: mpsc::Receiver<Chunk>,
+    buffer_size: usize,
+}
+
+enum WriteState<S> {
+    Write(Encoder<S>),
+    Flush(WaitFlush<S>),
+    Void,
 }
 
 impl HalfResp {
@@ -35,19 +63,45 @@ impl HalfResp {
             headers: head.headers().map(|(k, v)| {
                 (k.to_string(), v.to_vec())
             }).collect(),
+            // response without Content-Length is read until connection
+            // is closed
+            body_length: body_length(head.all_headers().iter()
+                .map(|h| (h.name, h.value)), None),
         }
     }
-    pub fn complete(self, body: Vec<u8>) -> Response {
+    pub fn stream(self, chunks: mpsc::Receiver<Chunk>) -> Response {
         Response {
             status: self.status,
             headers: self.headers,
-            body: body,
+            body_length: self.body_length,
+            chunks: chunks,
         }
     }
 }
 
 impl Response {
-    pub fn encode<S>(&self, mut e: Encoder<S>) -> EncoderDone<S>{
+    /// Rewrites `Location` and `Set-Cookie` paths of the backend to the
+    /// paths seen by the client
+    pub fn rewrite_paths(&mut self, paths: &PathMap) {
+        for &mut (ref name, ref mut value) in &mut self.headers {
+            let new = match str::from_utf8(value) {
+                Ok(v) if name.eq_ignore_ascii_case("Location") => {
+                    paths.location(v)
+                }
+                Ok(v) if name.eq_ignore_ascii_case("Set-Cookie") => {
+                    paths.set_cookie(v)
+                }
+                _ => None,
+            };
+            if let Some(new) = new {
+                *value = new.into_bytes();
+            }
+        }
+    }
+    pub fn encode<S: AsyncWrite + 'static>(self, mut e: Encoder<S>,
+        buffer_size: usize)
+        -> Reply<S>
+    {
         let body = match self.status {
             RespStatus::Normal(s) => {
                 e.status(s);
@@ -62,14 +116,67 @@ impl Response {
             e.add_header(k, v);
         }
         if body {
-            e.add_length(self.body.len() as u64);
+            match self.body_length {
+                Some(n) => e.add_length(n),
+                None => e.add_chunked(),
+            }
             if e.done_headers() {
-                e.write_body(&self.body);
+                return Box::new(WriteBody {
+                    state: WriteState::Write(e),
+                    chunks: self.chunks,
+                    buffer_size: buffer_size,
+                });
             }
         } else {
             let res = e.done_headers();
             assert!(res == false);
         }
-        return e.done();
+        // Nothing to send (e.g. response to a HEAD request), dropping the
+        // channel makes backend connection to be closed if there is
+        // some body still
+        return Box::new(ok(e.done()));
+    }
+}
+
+impl<S: AsyncWrite> Future for WriteBody<S> {
+    type Item = EncoderDone<S>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<EncoderDone<S>, Error> {
+        loop {
+            self.state = match mem::replace(&mut self.state, WriteState::Void)
+            {
+                WriteState::Write(mut e) => {
+                    match self.chunks.poll() {
+                        Ok(Async::Ready(Some((data, end)))) => {
+                            if data.len() > 0 {
+                                e.write_body(&data);
+                            }
+                            if end {
+                                return Ok(Async::Ready(e.done()));
+                            }
+                            WriteState::Flush(e.wait_flush(self.buffer_size))
+                        }
+                        Ok(Async::Ready(None)) | Err(()) => {
+                            return Err(Error::custom(
+                                "response body is interrupted by backend"));
+                        }
+                        Ok(Async::NotReady) => {
+                            self.state = WriteState::Write(e);
+                            return Ok(Async::NotReady);
+                        }
+                    }
+                }
+                WriteState::Flush(mut f) => {
+                    match f.poll().map_err(Error::custom)? {
+                        Async::Ready(e) => WriteState::Write(e),
+                        Async::NotReady => {
+                            self.state = WriteState::Flush(f);
+                            return Ok(Async::NotReady);
+                        }
+                    }
+                }
+                WriteState::Void => unreachable!(),
+            }
+        }
     }
 }
@@ -0,0 +1,144 @@
+//! Maps paths in response headers from the backend back to the route
+//!
+//! When `strip-prefix` is enabled the backend sees `{destination}/rest`
+//! for the client's `{route prefix}/rest`, so absolute paths in `Location`
+//! and in `Path` attribute of `Set-Cookie` are translated backwards.
+use crate::config::proxy::Proxy;
+use crate::incoming::Input;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathMap {
+    /// Prefix of the path seen by the client, without trailing slash
+    public: String,
+    /// Corresponding prefix sent to the backend, without trailing slash
+    upstream: String,
+    /// Host of the request, absolute URLs pointing to it are rewritten too
+    host: String,
+}
+
+impl PathMap {
+    /// Returns `None` if paths don't need to be rewritten
+    pub fn from_input(settings: &Proxy, inp: &Input) -> Option<PathMap> {
+        if !settings.strip_prefix {
+            return None;
+        }
+        let upstream = inp.captures.expand(&settings.destination.path);
+        PathMap::new(inp.prefix, &upstream,
+                     inp.headers.host().unwrap_or(""))
+    }
+    fn new(public: &str, upstream: &str, host: &str) -> Option<PathMap> {
+        let public = public.trim_end_matches('/');
+        let upstream = upstream.trim_end_matches('/');
+        if public == upstream {
+            return None;
+        }
+        Some(PathMap {
+            public: public.to_string(),
+            upstream: upstream.to_string(),
+            host: host.to_string(),
+        })
+    }
+    fn path(&self, path: &str) -> Option<String> {
+        if !path.starts_with(&self.upstream[..]) {
+            return None;
+        }
+        let rest = &path[self.upstream.len()..];
+        if !(rest == "" || rest.starts_with(|c| "/?#".contains(c))) {
+            return None;
+        }
+        if self.public == "" && !rest.starts_with('/') {
+            Some(format!("/{}", rest))
+        } else {
+            Some(format!("{}{}", self.public, rest))
+        }
+    }
+    /// Rewrites absolute path or URL pointing to the same host
+    pub fn location(&self, value: &str) -> Option<String> {
+        if value.starts_with('/') {
+            if value.starts_with("//") {
+                return None;
+            }
+            return self.path(value);
+        }
+        let scheme_end = value.find("://")? + 3;
+        let authority_end = value[scheme_end..].find('/')
+            .map(|x| scheme_end + x)?;
+        if !value[scheme_end..authority_end]
+            .eq_ignore_ascii_case(&self.host)
+        {
+            return None;
+        }
+        self.path(&value[authority_end..])
+            .map(|path| format!("{}{}", &value[..authority_end], path))
+    }
+    /// Rewrites `Path` attribute of the `Set-Cookie` header
+    pub fn set_cookie(&self, value: &str) -> Option<String> {
+        let mut changed = false;
+        let parts = value.split(';').map(|part| {
+            let attr = part.trim_start();
+            let is_path = attr.get(..5)
+                .map(|x| x.eq_ignore_ascii_case("path="))
+                .unwrap_or(false);
+            if is_path && attr.len() > 5 {
+                let path = if &attr[5..] == "/" && self.upstream == "" {
+                    // cookie for the whole app is for the whole prefix
+                    Some(self.public.clone())
+                } else {
+                    self.path(&attr[5..])
+                };
+                if let Some(path) = path {
+                    changed = true;
+                    let indent = &part[..part.len() - attr.len()];
+                    return format!("{}{}{}", indent, &attr[..5], path);
+                }
+            }
+            part.to_string()
+        }).collect::<Vec<_>>();
+        if changed {
+            Some(parts.join(";"))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PathMap;
+
+    #[test]
+    fn location() {
+        let map = PathMap::new("/admin", "/", "example.com").unwrap();
+        assert_eq!(map.location("/login?next=/"),
+                   Some("/admin/login?next=/".into()));
+        assert_eq!(map.location("/"), Some("/admin/".into()));
+        assert_eq!(map.location("http://example.com/x"),
+                   Some("http://example.com/admin/x".into()));
+        assert_eq!(map.location("http://other.com/x"), None);
+        assert_eq!(map.location("//other.com/x"), None);
+        assert_eq!(map.location("x/y"), None);
+
+        let map = PathMap::new("/admin", "/app", "example.com").unwrap();
+        assert_eq!(map.location("/app"), Some("/admin".into()));
+        assert_eq!(map.location("/app/x"), Some("/admin/x".into()));
+        assert_eq!(map.location("/apple"), None);
+        assert_eq!(map.location("/x"), None);
+
+        let map = PathMap::new("", "/app", "example.com").unwrap();
+        assert_eq!(map.location("/app"), Some("/".into()));
+        assert_eq!(map.location("/app?x"), Some("/?x".into()));
+
+        assert_eq!(PathMap::new("/app", "/app/", "example.com"), None);
+    }
+
+    #[test]
+    fn set_cookie() {
+        let map = PathMap::new("/admin", "/", "example.com").unwrap();
+        assert_eq!(map.set_cookie("sid=1; Path=/; HttpOnly"),
+                   Some("sid=1; Path=/admin; HttpOnly".into()));
+        assert_eq!(map.set_cookie("sid=1;path=/x"),
+                   Some("sid=1;path=/admin/x".into()));
+        assert_eq!(map.set_cookie("sid=1; HttpOnly"), None);
+    }
+}
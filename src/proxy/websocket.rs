@@ -0,0 +1,305 @@
+//! WebSocket pass-through for the `!Proxy` handler
+//!
+//! Handshake is forwarded to the backend over a dedicated connection (not
+//! a pooled one), to the address chosen by the destination's pool. When
+//! backend accepts it, frames are forwarded in both directions until either
+//! side closes the connection.
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use futures::{Async, Future, Sink, Stream};
+use futures::future::{Either, ok, err, lazy};
+use futures::sync::oneshot;
+use tk_bufstream::{ReadBuf, WriteBuf};
+use tk_http::Status;
+use tk_http::server::{self as http, Error, RecvMode};
+use tk_http::websocket::{ServerCodec, Accept, Packet};
+use tk_http::websocket::Error as WsError;
+use tk_http::websocket::client::{self as ws, Head, HandshakeProto};
+use tk_http::websocket::client::{Encoder as WsEncoder, EncoderDone};
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_rustls::ClientConfigExt;
+#[cfg(unix)] use tokio_uds::UnixStream;
+use webpki::DNSNameRef;
+
+use crate::config::http_destinations::Destination;
+use crate::config::proxy::Proxy;
+use crate::default_error_page::{serve_error_page, error_page};
+use crate::incoming::{Input, Request, Reply, Encoder, Context, IntoContext};
+use crate::incoming::{Transport, Identity, is_identity_header};
+use crate::intern::Upstream;
+use crate::http_pools::{HttpPools, Target};
+use crate::local_sockets::PeerIp;
+use crate::proxy::{upstream_path, hash_key};
+
+
+type PacketStream = Box<dyn Stream<Item=Packet, Error=io::Error>>;
+type PacketSink = Box<dyn Sink<SinkItem=Packet, SinkError=io::Error>>;
+/// Error contains status for the client and a message for the log
+type BackendFuture = Box<dyn Future<Item=Backend, Error=(Status, String)>>;
+
+/// Backend connection after successful handshake
+struct Backend {
+    output: PacketSink,
+    input: PacketStream,
+    /// Response headers that are forwarded to the client
+    headers: Vec<(String, Vec<u8>)>,
+}
+
+/// Writes handshake request to the backend
+struct Handshake {
+    path: String,
+    host: String,
+    headers: Vec<(String, Vec<u8>)>,
+    identity: Identity,
+    /// Headers which authorizer of the route may set
+    route_headers: Vec<String>,
+    identity_headers: HashMap<String, String>,
+    /// Status of the backend response, set when headers are received
+    status: Rc<Cell<Option<u16>>>,
+}
+
+struct WebsockProxy {
+    handle: Handle,
+    handshake: Option<BackendFuture>,
+    reply_data: Option<(Context, Accept)>,
+    backend: Option<oneshot::Receiver<(PacketSink, PacketStream)>>,
+}
+
+/// Headers that are negotiated separately on each side of the proxy
+fn is_handshake_header(name: &str) -> bool {
+    name.eq_ignore_ascii_case("Connection") ||
+    name.eq_ignore_ascii_case("Upgrade") ||
+    name.eq_ignore_ascii_case("Sec-WebSocket-Key") ||
+    name.eq_ignore_ascii_case("Sec-WebSocket-Version") ||
+    name.eq_ignore_ascii_case("Sec-WebSocket-Accept") ||
+    // frames are decoded and encoded again, so extensions
+    // can't be passed through
+    name.eq_ignore_ascii_case("Sec-WebSocket-Extensions") ||
+    name.eq_ignore_ascii_case("Content-Length") ||
+    name.eq_ignore_ascii_case("Transfer-Encoding") ||
+    name.eq_ignore_ascii_case("Date")
+}
+
+impl<S> ws::Authorizer<S> for Handshake {
+    type Result = Vec<(String, Vec<u8>)>;
+
+    fn write_headers(&mut self, mut e: WsEncoder<S>) -> EncoderDone<S> {
+        e.request_line(&self.path);
+        e.add_header("Host", &self.host).unwrap();
+        let identity = self.identity.headers(&self.identity_headers);
+        for &(ref k, ref v) in &identity {
+            e.add_header(k, v).unwrap();
+        }
+        for &(ref k, ref v) in &self.headers {
+            if is_identity_header(k, &identity, &self.route_headers,
+                                  &self.identity_headers)
+            {
+                // Don't let client spoof identity headers
+                continue;
+            }
+            e.add_header(k, v).unwrap();
+        }
+        e.done()
+    }
+
+    fn headers_received(&mut self, headers: &Head)
+        -> Result<Self::Result, WsError>
+    {
+        self.status.set(Some(headers.raw_status().0));
+        Ok(headers.all_headers().iter()
+            .filter(|h| !is_handshake_header(h.name))
+            .map(|h| (h.name.to_string(), h.value.to_vec()))
+            .collect())
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + 'static> http::Codec<S> for WebsockProxy {
+    type ResponseFuture = Reply<S>;
+    fn recv_mode(&mut self) -> RecvMode {
+        RecvMode::hijack()
+    }
+    fn data_received(&mut self, data: &[u8], end: bool)
+        -> Result<Async<usize>, Error>
+    {
+        assert!(end);
+        assert!(data.len() == 0);
+        Ok(Async::Ready(0))
+    }
+    fn start_response(&mut self, e: http::Encoder<S>) -> Reply<S> {
+        let (context, accept) = self.reply_data.take()
+            .expect("start response called only once");
+        let handshake = self.handshake.take()
+            .expect("start response called only once");
+        let (tx, rx) = oneshot::channel();
+        self.backend = Some(rx);
+        Box::new(handshake.then(move |result| {
+            let mut e = Encoder::new(e, context);
+            match result {
+                Ok(backend) => {
+                    e.status(Status::SwitchingProtocol);
+                    e.add_header("Connection", "upgrade");
+                    e.add_header("Upgrade", "websocket");
+                    e.format_header("Sec-Websocket-Accept", &accept);
+                    for &(ref k, ref v) in &backend.headers {
+                        e.add_header(k, v);
+                    }
+                    e.done_headers();
+                    tx.send((backend.output, backend.input)).ok();
+                    ok(e.done())
+                }
+                Err((status, msg)) => {
+                    info!("Websocket proxy error: {}", msg);
+                    // Client connection is closed when sender is dropped
+                    error_page(status, e)
+                }
+            }
+        }))
+    }
+    fn hijack(&mut self, write_buf: WriteBuf<S>, read_buf: ReadBuf<S>) {
+        let inp = read_buf.framed(ServerCodec);
+        let out = write_buf.framed(ServerCodec);
+        let backend = self.backend.take().expect("hijack called only once");
+        self.handle.spawn(backend
+            .map_err(|_| ())
+            .and_then(|(backend_out, backend_in)| {
+                let up = inp.forward(backend_out).map(|_| ());
+                let down = backend_in.forward(out).map(|_| ());
+                up.select(down)
+                .map(|_| ())
+                .map_err(|(e, _)| debug!("Websocket proxy closed: {}", e))
+            }));
+    }
+}
+
+fn handshake<T>(sock: T, handshake: Handshake) -> BackendFuture
+    where T: AsyncRead + AsyncWrite + 'static
+{
+    let status = handshake.status.clone();
+    Box::new(HandshakeProto::new(sock, handshake)
+        .then(move |result| match (status.get(), result) {
+            // Backend rejected the handshake, status is sent to the client
+            (Some(code), _) if code != 101 => {
+                let status = Status::from(code).unwrap_or(Status::BadGateway);
+                Err((status, format!("handshake rejected: {}", code)))
+            }
+            (_, Ok((output, input, headers))) => Ok(Backend {
+                output: Box::new(output),
+                input: Box::new(input),
+                headers: headers,
+            }),
+            (_, Err(e)) => {
+                Err((Status::BadGateway, format!("handshake error: {}", e)))
+            }
+        }))
+}
+
+/// Connects to the address chosen by the pool of the destination
+fn connect(dest: &Destination, hs: Handshake, pools: &HttpPools,
+    upstream: &Upstream, key: Option<u64>, handle: &Handle)
+    -> BackendFuture
+{
+    let (addr, tls) = match pools.connect_to(upstream, key) {
+        Ok(Target::Tcp(addr, tls)) => (addr, tls),
+        #[cfg(unix)]
+        Ok(Target::Unix(path)) => {
+            let sock = UnixStream::connect(&path, handle).map_err(|e| {
+                (Status::BadGateway, format!("{:?}: {}", path, e))
+            });
+            return Box::new(futures::future::result(sock)
+                .and_then(move |sock| handshake(sock, hs)));
+        }
+        Err(e) => return Box::new(err((Status::BadGateway, e))),
+    };
+    let server_name = dest.tls_server_name().map(|x| x.to_string());
+    Box::new(TcpStream::connect(&addr, handle)
+        .map_err(move |e| (Status::BadGateway, format!("{}: {}", addr, e)))
+        .and_then(move |sock| match (tls, server_name) {
+            (Some(config), Some(server_name)) => {
+                let name = DNSNameRef::try_from_ascii_str(&server_name)
+                    .expect("server name is validated in config");
+                Either::A(config.connect_async(name, sock)
+                    .map_err(|e| {
+                        (Status::BadGateway, format!("TLS error: {}", e))
+                    })
+                    .and_then(move |sock| handshake(sock, hs)))
+            }
+            _ => Either::B(handshake(sock, hs)),
+        }))
+}
+
+fn request(settings: &Proxy, dest: &Destination, inp: &Input) -> Handshake {
+    let path = upstream_path(settings, inp);
+    let host = match dest.override_host_header {
+        Some(ref host) => host.clone(),
+        None => inp.headers.host().expect("host exists").to_string(),
+    };
+    let mut headers = Vec::new();
+    if let Some(ref h) = settings.ip_header {
+        headers.push((h.clone(), PeerIp(inp.addr).to_string().into_bytes()));
+    }
+    if let Some(ref h) = dest.request_id_header {
+        headers.push((h.clone(), inp.request_id.to_string().into_bytes()));
+    }
+    headers.extend(inp.headers.headers()
+        .filter(|&(k, _)| !is_handshake_header(k))
+        .map(|(k, v)| (k.to_string(), v.to_vec())));
+    Handshake {
+        path, host, headers,
+        identity: inp.identity.clone(),
+        route_headers: inp.identity_headers.to_vec(),
+        identity_headers: settings.identity_headers.clone(),
+        status: Rc::new(Cell::new(None)),
+    }
+}
+
+/// Forwards websocket handshake to the backend
+pub fn serve<S: Transport>(settings: &Arc<Proxy>, accept: Accept, inp: Input)
+    -> Request<S>
+{
+    let upstream = &settings.destination.upstream;
+    let dest = match inp.config.http_destinations.get(upstream) {
+        Some(dest) => dest.clone(),
+        None => {
+            error!("No such destination {:?}", upstream);
+            return serve_error_page(Status::NotFound, inp);
+        }
+    };
+    let hs = request(settings, &dest, &inp);
+    let timeout = inp.options.response_timeout
+        .or(dest.response_timeout)
+        .unwrap_or(dest.max_request_timeout);
+    let timeout = Timeout::new(timeout, inp.handle)
+        .expect("timeout created");
+    let key = hash_key(&dest, &inp.addr, inp.headers.headers());
+    let pools = inp.runtime.http_pools.clone();
+    let upstream = upstream.clone();
+    let handle = inp.handle.clone();
+    // Connection is started when response is requested, i.e. after request
+    // is authorized
+    let handshake = lazy(move || {
+            connect(&dest, hs, &pools, &upstream, key, &handle)
+        })
+        .select2(timeout)
+        .then(|res| match res {
+            Ok(Either::A((backend, _))) => Ok(backend),
+            Ok(Either::B(((), _))) => {
+                Err((Status::GatewayTimeout, format!("handshake timed out")))
+            }
+            Err(Either::A((e, _))) => Err(e),
+            Err(Either::B((e, _))) => {
+                Err((Status::BadGateway, e.to_string()))
+            }
+        });
+    Box::new(WebsockProxy {
+        handle: inp.handle.clone(),
+        handshake: Some(Box::new(handshake)),
+        backend: None,
+        reply_data: Some((inp.into_context(), accept)),
+    })
+}
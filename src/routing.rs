@@ -1,12 +1,19 @@
 use std::collections::{HashMap};
+use std::str::from_utf8;
+use std::sync::Arc;
 
-use regex::{self, RegexSet};
+use regex::{self, Regex, RegexSet, SetMatches};
+use tk_http::server::Head;
 
 use crate::intern::{HandlerName, Authorizer as AuthorizerName};
+use crate::intern::LogFormatName;
 use crate::config::{ConfigSource, Error};
-use crate::config::routing::{Host, HostPath, RouteDef};
+use crate::config::routing::{Host, HostPath, RouteDef, RouteOptions};
+use crate::config::routing::{Conditions, Condition};
 use crate::config::handlers::Handler::{self, StripWWWRedirect};
 use crate::config::authorizers::Authorizer;
+use crate::cookie::cookie;
+use crate::handlers::split::Variant;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Route {
@@ -14,6 +21,23 @@ pub struct Route {
     pub handler: Handler,
     pub authorizer_name: AuthorizerName,
     pub authorizer: Authorizer,
+    /// Conditions of the route, if any
+    pub conditions: Conditions,
+    /// Log format the request is written with in addition to debug log
+    pub log_format: Option<LogFormatName>,
+    pub options: RouteOptions,
+    /// Handlers referenced by the `!Split` handler of the route
+    pub variants: Vec<Variant>,
+    /// Headers which authorizer of the route may set, these are never
+    /// forwarded from the client
+    pub identity_headers: Vec<String>,
+}
+
+/// Request properties checked by route conditions
+pub trait RequestInfo {
+    fn method(&self) -> &str;
+    /// Returns values of all headers having the name
+    fn header(&self, name: &str) -> Vec<&[u8]>;
 }
 
 #[derive(Debug)]
@@ -26,8 +50,30 @@ pub struct RoutingTable {
 pub struct PathTable {
     set: RegexSet,
     table: Vec<(String, Route)>,
+    /// Sorted by length of the literal prefix, longest first, then by
+    /// pattern source, this order resolves overlapping patterns
+    patterns: Vec<(Pattern, Route)>,
 }
 
+/// Path containing regex groups `(...)` or `{name}` placeholders
+///
+/// Everything outside of the groups is matched literally.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    literal: String,
+    /// Regex with placeholders replaced by unnamed groups, two patterns
+    /// having the same shape match exactly the same paths
+    shape: String,
+    regex: Arc<Regex>,
+}
+
+/// Groups captured by the path pattern of the route
+///
+/// Each group is accessible by its number, and named ones by name too.
+#[derive(Debug, Clone, Default)]
+pub struct Captures(Vec<(String, String)>);
+
 impl PartialEq for RoutingTable {
     fn eq(&self, other: &RoutingTable) -> bool {
         return self.table == other.table;
@@ -38,12 +84,236 @@ impl Eq for RoutingTable {}
 
 impl PartialEq for PathTable {
     fn eq(&self, other: &PathTable) -> bool {
-        return self.table == other.table;
+        return self.table == other.table && self.patterns == other.patterns;
     }
 }
 
 impl Eq for PathTable {}
 
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        return self.source == other.source;
+    }
+}
+
+impl Eq for Pattern {}
+
+fn is_placeholder_name(name: &str) -> bool {
+    name.len() > 0 &&
+        name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Pattern {
+    pub fn is_pattern(path: &str) -> bool {
+        path.contains(|c| c == '(' || c == '{')
+    }
+    pub fn new(path: &str) -> Result<Pattern, String> {
+        let mut literal = None;
+        let mut body = String::new();
+        let mut shape = String::new();
+        let mut chars = path.char_indices().peekable();
+        while let Some((idx, c)) = chars.next() {
+            match c {
+                '{' => {
+                    literal = literal.or(Some(idx));
+                    let end = path[idx..].find('}')
+                        .ok_or_else(|| format!("unclosed {{ in {:?}", path))?;
+                    let name = &path[idx+1..idx+end];
+                    if !is_placeholder_name(name) ||
+                        name.chars().all(|c| c.is_ascii_digit())
+                    {
+                        return Err(format!("invalid placeholder {:?} in {:?}",
+                            name, path));
+                    }
+                    body.push_str(&format!("(?P<{}>[^/]+)", name));
+                    shape.push_str("([^/]+)");
+                    while let Some(&(i, _)) = chars.peek() {
+                        if i > idx + end { break; }
+                        chars.next();
+                    }
+                }
+                '(' => {
+                    literal = literal.or(Some(idx));
+                    let mut depth = 0;
+                    let mut escaped = false;
+                    let mut group = String::from("(");
+                    for (_, c) in chars.by_ref() {
+                        group.push(c);
+                        match c {
+                            _ if escaped => escaped = false,
+                            '\\' => escaped = true,
+                            '(' => depth += 1,
+                            ')' if depth == 0 => break,
+                            ')' => depth -= 1,
+                            _ => {}
+                        }
+                    }
+                    if !group.ends_with(')') || group.len() < 2 {
+                        return Err(format!("unclosed ( in {:?}", path));
+                    }
+                    body.push_str(&group);
+                    shape.push_str(&group);
+                }
+                _ => {
+                    let escaped = regex::escape(&c.to_string());
+                    body.push_str(&escaped);
+                    shape.push_str(&escaped);
+                }
+            }
+        }
+        let literal = match literal {
+            Some(idx) => path[..idx].to_string(),
+            None => return Err(format!("{:?} is not a pattern", path)),
+        };
+        // The outer group is the matched prefix of the path
+        let regex = Regex::new(&format!("^({})(?:$|/|\\?|#)", body))
+            .map_err(|e| format!("invalid pattern {:?}: {}", path, e))?;
+        Ok(Pattern {
+            source: path.to_string(),
+            literal: literal,
+            shape: shape,
+            regex: Arc::new(regex),
+        })
+    }
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+    pub fn shape(&self) -> &str {
+        &self.shape
+    }
+    /// Returns names and numbers of the groups
+    pub fn groups(&self) -> Vec<String> {
+        self.regex.capture_names().enumerate().skip(2)
+            .flat_map(|(idx, name)| {
+                Some((idx - 1).to_string()).into_iter()
+                    .chain(name.map(|n| n.to_string()))
+            })
+            .collect()
+    }
+    fn find<'x>(&self, path: &'x str) -> Option<(&'x str, Captures)> {
+        if !path.starts_with(&self.literal) {
+            return None;
+        }
+        let m = self.regex.captures(path)?;
+        let prefix = m.get(1).expect("outer group always matches");
+        let mut captures = Vec::new();
+        for (idx, name) in self.regex.capture_names().enumerate().skip(2) {
+            if let Some(group) = m.get(idx) {
+                let value = group.as_str().to_string();
+                if let Some(name) = name {
+                    captures.push((name.to_string(), value.clone()));
+                }
+                captures.push(((idx - 1).to_string(), value));
+            }
+        }
+        Some((&path[..prefix.end()], Captures(captures)))
+    }
+}
+
+impl Captures {
+    /// Returns captured values by number and by name (if the group is named)
+    pub fn iter(&self) -> impl Iterator<Item=(&str, &str)> {
+        self.0.iter().map(|&(ref n, ref v)| (&n[..], &v[..]))
+    }
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.iter()
+            .find(|&&(ref n, _)| n == name)
+            .map(|&(_, ref value)| &value[..])
+    }
+    /// Replaces `{name}` and `{1}` placeholders by captured values
+    ///
+    /// Placeholders which weren't captured are left as is.
+    pub fn expand(&self, template: &str) -> String {
+        self.expand_with(template, |value| Ok(value.to_string()))
+            .expect("infallible")
+    }
+    /// Same as `expand` but `convert` may alter or reject each value
+    pub fn expand_with<F>(&self, template: &str, convert: F)
+        -> Result<String, ()>
+        where F: Fn(&str) -> Result<String, ()>
+    {
+        let mut result = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            result.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let value = rest.find('}')
+                .and_then(|end| self.get(&rest[1..end]).map(|v| (end, v)));
+            match value {
+                Some((end, value)) => {
+                    result.push_str(&convert(value)?);
+                    rest = &rest[end+1..];
+                }
+                None => {
+                    result.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        result.push_str(rest);
+        Ok(result)
+    }
+}
+
+/// Returns names of `{name}` placeholders used in the template
+pub fn placeholders(template: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start+1..];
+        if let Some(end) = rest.find('}') {
+            if is_placeholder_name(&rest[..end]) {
+                result.push(&rest[..end]);
+                rest = &rest[end+1..];
+            }
+        }
+    }
+    return result;
+}
+
+impl<'a> RequestInfo for Head<'a> {
+    fn method(&self) -> &str {
+        Head::method(self)
+    }
+    fn header(&self, name: &str) -> Vec<&[u8]> {
+        self.headers()
+            .filter(|&(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+            .collect()
+    }
+}
+
+fn value_matches(actual: &[u8], expected: &Option<String>) -> bool {
+    match *expected {
+        Some(ref expected) => {
+            from_utf8(actual).map(|x| x.trim() == expected).unwrap_or(false)
+        }
+        None => true,
+    }
+}
+
+fn conditions_match<R: RequestInfo>(cond: &Conditions, req: &R) -> bool {
+    if cond.methods.len() > 0 {
+        let method = req.method();
+        // `HEAD` is served by the same handlers as `GET`
+        if !cond.methods.iter()
+            .any(|m| m == method || m == "GET" && method == "HEAD")
+        {
+            return false;
+        }
+    }
+    cond.headers.iter().all(|c| match *c {
+        Condition::Header(ref name, ref value) => {
+            req.header(name).iter().any(|v| value_matches(v, value))
+        }
+        Condition::Cookie(ref name, ref value) => {
+            cookie(req.header("Cookie"), name)
+            .map(|v| value_matches(v.as_bytes(), value))
+            .unwrap_or(false)
+        }
+    })
+}
+
 fn inherit(to: &mut RouteDef, from: &RouteDef) {
     match (&mut to.authorizer, &from.authorizer) {
         (&mut ref mut dest @ None, &Some(ref x)) => {
@@ -51,25 +321,56 @@ fn inherit(to: &mut RouteDef, from: &RouteDef) {
         }
         _ => {}
     }
+    match (&mut to.log, &from.log) {
+        (&mut ref mut dest @ None, &Some(ref x)) => {
+            *dest = Some(x.clone());
+        }
+        _ => {}
+    }
+    to.options.inherit(&from.options);
 }
 fn is_done(item: &RouteDef) -> bool {
     matches!(*item, RouteDef {
         handler: _,
         authorizer: Some(_),
-    })
+        log: Some(_),
+        options: _,
+    }) && item.options.is_complete()
 }
 fn default() -> RouteDef {
     RouteDef {
         handler: HandlerName::from("default"),
         authorizer: None,
+        log: None,
+        options: RouteOptions::default(),
     }
 }
 
 impl PathTable {
-    fn new(mut table: Vec<(String, Route)>) -> Result<PathTable, Error> {
-        table.sort_by(|&(ref a, _), &(ref b, _)| {
+    fn new(all: Vec<(String, Route)>) -> Result<PathTable, Error> {
+        let mut table = Vec::new();
+        let mut patterns = Vec::new();
+        for (path, route) in all {
+            if Pattern::is_pattern(&path) {
+                patterns.push((Pattern::new(&path).map_err(Error::Routing)?,
+                               route));
+            } else {
+                table.push((path, route));
+            }
+        }
+        // conditional routes go first, as they are more specific
+        patterns.sort_by(|&(ref a, ref ra), &(ref b, ref rb)| {
+            b.literal.len().cmp(&a.literal.len())
+            .then(ra.conditions.is_empty().cmp(&rb.conditions.is_empty()))
+            .then(a.source.cmp(&b.source))
+            .then(ra.conditions.to_string().cmp(&rb.conditions.to_string()))
+        });
+        table.sort_by(|&(ref a, ref ra), &(ref b, ref rb)| {
             // sort by longest first, but then keep order reproducible
-            b.len().cmp(&a.len()).then(a.cmp(b))
+            b.len().cmp(&a.len())
+            .then(ra.conditions.is_empty().cmp(&rb.conditions.is_empty()))
+            .then(a.cmp(b))
+            .then(ra.conditions.to_string().cmp(&rb.conditions.to_string()))
         });
         let rset = RegexSet::new(
             table.iter().map(|&(ref path, _)| {
@@ -78,6 +379,7 @@ impl PathTable {
         Ok(PathTable {
             set: rset,
             table: table,
+            patterns: patterns,
         })
     }
 }
@@ -85,16 +387,67 @@ impl PathTable {
 trait Resolver {
     fn handler(&self, _: &HandlerName) -> Option<Handler>;
     fn authorizer(&self, _: &AuthorizerName) -> Option<Authorizer>;
+    /// Collects headers the authorizer, or any authorizer it references,
+    /// may add to the identity
+    fn identity_headers(&self, name: &AuthorizerName,
+        seen: &mut Vec<AuthorizerName>, result: &mut Vec<String>)
+    {
+        if seen.contains(name) {
+            // cycles are reported when authorizers are validated
+            return;
+        }
+        seen.push(name.clone());
+        match self.authorizer(name) {
+            Some(Authorizer::AnyOf(ref refs)) |
+            Some(Authorizer::AllOf(ref refs)) => {
+                for item in refs.iter() {
+                    self.identity_headers(item, seen, result);
+                }
+            }
+            Some(ref auth) => {
+                for header in auth.header_names() {
+                    if !result.iter().any(|h| h.eq_ignore_ascii_case(header))
+                    {
+                        result.push(header.to_string());
+                    }
+                }
+            }
+            None => {}
+        }
+    }
     fn route(&self, route: &RouteDef) -> Result<Route, Error> {
         let auth = route.authorizer.clone()
             .unwrap_or(AuthorizerName::from("default"));
+        let handler = self.handler(&route.handler)
+            .ok_or_else(|| Error::NoHandler(route.handler.clone()))?;
+        let mut variants = Vec::new();
+        if let Handler::Split(ref split) = handler {
+            for var in &split.variants {
+                let vhandler = self.handler(&var.handler)
+                    .ok_or_else(|| Error::NoHandler(var.handler.clone()))?;
+                if let Handler::Split(..) = vhandler {
+                    return Err(Error::Routing(format!(
+                        "Split {:?} refers to another split {:?}",
+                        &route.handler[..], &var.handler[..])));
+                }
+                variants.push(Variant::new(&route.handler, &var.handler,
+                                           vhandler, var.weight));
+            }
+        }
+        let mut identity_headers = Vec::new();
+        self.identity_headers(&auth, &mut Vec::new(), &mut identity_headers);
+        identity_headers.sort();
         Ok(Route {
-            handler: self.handler(&route.handler)
-                .ok_or_else(|| Error::NoHandler(route.handler.clone()))?,
+            handler: handler,
             handler_name: route.handler.clone(),
             authorizer: self.authorizer(&auth)
                 .ok_or_else(|| Error::NoAuthorizer(auth.clone()))?,
             authorizer_name: auth,
+            conditions: Conditions::default(),
+            log_format: route.log.clone(),
+            options: route.options.clone(),
+            variants: variants,
+            identity_headers: identity_headers,
         })
     }
 }
@@ -109,6 +462,13 @@ impl<'a> Resolver for &'a ConfigSource {
 }
 
 impl RoutingTable {
+    /// Iterates over all routes of all hosts
+    pub fn routes(&self) -> impl Iterator<Item=&Route> {
+        self.table.iter().flat_map(|&(_, ref paths)| {
+            paths.table.iter().map(|&(_, ref route)| route)
+            .chain(paths.patterns.iter().map(|&(_, ref route)| route))
+        })
+    }
     pub fn new(src: &ConfigSource)
         -> Result<RoutingTable, Error>
     {
@@ -127,6 +487,7 @@ impl RoutingTable {
         struct Domain {
             root: Option<RouteDef>,
             paths: HashMap<String, RouteDef>,
+            conditional: HashMap<(Option<String>, Conditions), RouteDef>,
         }
 
         fn update_by_path(ndef: &mut RouteDef, path: &str,
@@ -159,8 +520,49 @@ impl RoutingTable {
             }
         }
 
+        /// Conditional routes inherit from the unconditional route having
+        /// the same path first
+        fn add_conditional<R: Resolver>(path_table: &mut Vec<(String, Route)>,
+            domain: &Domain, name: &str, star: bool,
+            table: &HashMap<String, Host>, res: &R)
+            -> Result<(), Error>
+        {
+            for (&(ref path, ref cond), def) in &domain.conditional {
+                let mut ndef = def.clone();
+                match *path {
+                    Some(ref path) => {
+                        if let Some(same) = domain.paths.get(path) {
+                            inherit(&mut ndef, same);
+                        }
+                        update_by_path(&mut ndef, path, domain);
+                    }
+                    None => {
+                        if let Some(ref root) = domain.root {
+                            inherit(&mut ndef, root);
+                        }
+                    }
+                }
+                if !star {
+                    update_from_host(&mut ndef, table.get(name));
+                }
+                for (idx, _) in name.match_indices(".") {
+                    if is_done(&ndef) {
+                        break;
+                    }
+                    update_from_host(&mut ndef, table.get(&name[idx+1..]));
+                }
+                update_from_host(&mut ndef, table.get(""));
+                let mut route = res.route(&ndef)?;
+                route.conditions = cond.clone();
+                path_table.push((path.clone().unwrap_or_else(String::new),
+                                 route));
+            }
+            Ok(())
+        }
+
         let mut table = HashMap::new();
-        for (&HostPath(Host(star, ref host), ref path), rdef) in iter {
+        for (&HostPath(Host(star, ref host), ref path, ref cond), rdef) in iter
+        {
             let entry = table.entry(host.clone())
                 .or_insert(Host {
                     exact: None,
@@ -170,6 +572,7 @@ impl RoutingTable {
                 entry.star.get_or_insert(Domain {
                     root: None,
                     paths: HashMap::new(),
+                    conditional: HashMap::new(),
                 })
             } else {
 
@@ -186,9 +589,17 @@ impl RoutingTable {
                 entry.exact.get_or_insert(Domain {
                     root: None,
                     paths: HashMap::new(),
+                    conditional: HashMap::new(),
                 })
             };
-            if let Some(ref path) = *path {
+            if !cond.is_empty() {
+                let key = (path.clone(), cond.clone());
+                if dom.conditional.insert(key, rdef.clone()).is_some() {
+                    return Err(Error::Routing(
+                        format!("Duplicate entry {} {}{}", cond, host,
+                            path.as_ref().map(|x| &x[..]).unwrap_or("/"))));
+                }
+            } else if let Some(ref path) = *path {
                 let old = dom.paths.insert(path.clone(), rdef.clone());
                 if old.is_some() {
                     return Err(Error::Routing(
@@ -230,6 +641,8 @@ impl RoutingTable {
                 }
                 update_from_host(&mut ndef, table.get(""));
                 path_table.push((String::from(""), res.route(&ndef)?));
+                add_conditional(&mut path_table, exact, name, false,
+                    &table, &res)?;
 
                 Some(PathTable::new(path_table)?)
             } else {
@@ -260,6 +673,8 @@ impl RoutingTable {
                 }
                 update_from_host(&mut ndef, table.get(""));
                 path_table.push((String::from(""), res.route(&ndef)?));
+                add_conditional(&mut path_table, star, name, true,
+                    &table, &res)?;
 
                 PathTable::new(path_table)?
             } else {
@@ -286,6 +701,7 @@ impl RoutingTable {
         });
         let mut real_table = Vec::new();
         let mut regex_table = Vec::new();
+        // names are only used to explain routing decisions
         for (name, star, exact) in hosts_table.into_iter() {
             match exact {
                 Some(exact) => {
@@ -294,17 +710,18 @@ impl RoutingTable {
                     real_table.push((name.clone(), exact));
                     regex_table.push(
                         String::from(r"^.*\.") + &regex::escape(&name) + "$");
-                    real_table.push((name, star));
+                    real_table.push((format!("*.{}", name), star));
                 }
                 None => {
                     if name == "" {
                         regex_table.push(String::from(r"^.*$"));
+                        real_table.push((String::from("*"), star));
                     } else {
                         regex_table.push(
                             String::from(r"^(?:.*\.)?") +
                                 &regex::escape(&name) + "$");
+                        real_table.push((format!("*.{}", name), star));
                     }
-                    real_table.push((name, star));
                 }
             }
         }
@@ -322,27 +739,148 @@ impl RoutingTable {
     }
 }
 
+/// Result of checking a candidate route: matched prefix and captured
+/// groups, or the reason route is skipped
+type Verdict<'x> = Result<(&'x str, Captures), &'static str>;
+
+/// Routes of the host that may match the path, in the order they are checked
+///
+/// Patterns having the literal prefix of the path go first, then literal
+/// routes matching the path. Only the first matched route is `Ok`.
+struct Candidates<'x, 'r, R: 'r> {
+    path: &'x str,
+    request: &'r R,
+    table: &'x PathTable,
+    literals: SetMatches,
+    /// Literal route that is used if no pattern matches
+    literal: Option<usize>,
+    /// Patterns are only checked if they are more specific than the literal
+    /// route, i.e. have longer literal prefix
+    min_len: usize,
+    pattern_idx: usize,
+    literal_idx: usize,
+    matched: bool,
+}
+
+impl<'x, 'r, R: RequestInfo> Candidates<'x, 'r, R> {
+    fn new(path: &'x str, request: &'r R, table: &'x PathTable)
+        -> Candidates<'x, 'r, R>
+    {
+        let literals = table.set.matches(path);
+        let literal = literals.iter().find(|&idx| {
+            conditions_match(&table.table[idx].1.conditions, request)
+        });
+        let min_len = literal.map(|idx| table.table[idx].0.len() + 1)
+            .unwrap_or(0);
+        Candidates {
+            path, request, table, literals, literal, min_len,
+            pattern_idx: 0,
+            literal_idx: 0,
+            matched: false,
+        }
+    }
+    fn next_pattern(&mut self) -> Option<(&'x str, &'x Route, Verdict<'x>)> {
+        let table = self.table;
+        while let Some(&(ref pattern, ref route)) =
+            table.patterns.get(self.pattern_idx)
+        {
+            self.pattern_idx += 1;
+            if !self.path.starts_with(&pattern.literal) {
+                continue;
+            }
+            let verdict = if self.matched {
+                Err("another pattern matched")
+            } else if pattern.literal.len() < self.min_len {
+                Err("literal route is more specific")
+            } else if !conditions_match(&route.conditions, self.request) {
+                Err("conditions don't match")
+            } else if let Some(found) = pattern.find(self.path) {
+                self.matched = true;
+                Ok(found)
+            } else {
+                Err("pattern doesn't match")
+            };
+            return Some((pattern.source(), route, verdict));
+        }
+        None
+    }
+    fn next_literal(&mut self) -> Option<(&'x str, &'x Route, Verdict<'x>)> {
+        let table = self.table;
+        while self.literal_idx < table.table.len() {
+            let idx = self.literal_idx;
+            self.literal_idx += 1;
+            if !self.literals.matched(idx) {
+                continue;
+            }
+            let (ref rpath, ref route) = table.table[idx];
+            let conditions = conditions_match(&route.conditions, self.request);
+            let verdict = if !conditions {
+                Err("conditions don't match")
+            } else if self.literal != Some(idx) {
+                Err("shorter prefix")
+            } else if self.matched {
+                Err("pattern matched")
+            } else {
+                Ok((&rpath[..], Captures::default()))
+            };
+            return Some((rpath, route, verdict));
+        }
+        None
+    }
+}
+
+impl<'x, 'r, R: RequestInfo> Iterator for Candidates<'x, 'r, R> {
+    type Item = (&'x str, &'x Route, Verdict<'x>);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_pattern().or_else(|| self.next_literal())
+    }
+}
+
 /// Map host port to a route of arbitrary type
 ///
-/// Returns destination route and relative path
-pub fn route<'x>(host: &str, path: &'x str,
+/// Returns destination route, matched prefix, relative path and groups
+/// captured by the path pattern (if route has one)
+pub fn route<'x, R: RequestInfo>(host: &str, path: &'x str, request: &R,
     table: &'x RoutingTable)
-    -> Option<(&'x Route, &'x str, &'x str)>
+    -> Option<(&'x Route, &'x str, &'x str, Captures)>
 {
-    let set = table.set.matches(host);
-    if !set.matched_any() {
-        return None;
-    }
-    let idx = set.iter().next().unwrap();
+    let idx = table.set.matches(host).iter().next()?;
     let (_, ref sub_table) = table.table[idx];
+    Candidates::new(path, request, sub_table)
+        .filter_map(|(_, route, verdict)| verdict.ok().map(|(prefix, caps)| {
+            (route, prefix, &path[prefix.len()..], caps)
+        }))
+        .next()
+}
+
+/// Routes considered by `route`, used to explain routing decisions
+#[derive(Debug)]
+#[allow(dead_code)]  // only used by --explain-route of the main binary
+pub struct Explanation<'x> {
+    /// Entry of the routing table matching the host, like `*.example.com`
+    pub host: Option<&'x str>,
+    /// Path or pattern of each route, and the reason it's skipped,
+    /// `None` is for the matched route
+    pub candidates: Vec<(&'x str, &'x Route, Option<&'static str>)>,
+}
 
-    let set = sub_table.set.matches(path);
-    if !set.matched_any() {
-        return None;
+/// Same as `route` but returns all the candidates checked for the path
+#[allow(dead_code)]  // only used by --explain-route of the main binary
+pub fn explain<'x, R: RequestInfo>(host: &str, path: &'x str, request: &R,
+    table: &'x RoutingTable)
+    -> Explanation<'x>
+{
+    let idx = match table.set.matches(host).iter().next() {
+        Some(idx) => idx,
+        None => return Explanation { host: None, candidates: Vec::new() },
+    };
+    let (ref name, ref sub_table) = table.table[idx];
+    Explanation {
+        host: Some(name),
+        candidates: Candidates::new(path, request, sub_table)
+            .map(|(path, route, verdict)| (path, route, verdict.err()))
+            .collect(),
     }
-    let idx = set.iter().next().unwrap();
-    let (ref rpath, ref route) = sub_table.table[idx];
-    return Some((route, rpath, &path[rpath.len()..]));
 }
 
 /// Returns host with trimmed whitespace and without port number if exists
@@ -356,14 +894,33 @@ pub fn parse_host(host_header: &str) -> &str {
 #[cfg(test)]
 mod route_test {
     use std::str::FromStr;
-    use super::{route, RoutingTable, Resolver};
+    use super::{route, explain, RoutingTable, Resolver, RequestInfo};
+    use super::{Pattern, placeholders};
     use crate::intern::{HandlerName, Authorizer as AuthorizerName};
-    use crate::config::routing::{HostPath, RouteDef};
+    use crate::config::routing::{HostPath, RouteDef, RouteOptions};
     use crate::config::handlers::Handler;
     use crate::config::authorizers::Authorizer;
 
     struct Fake;
 
+    struct Req(&'static str, Vec<(&'static str, &'static str)>);
+
+    impl RequestInfo for Req {
+        fn method(&self) -> &str {
+            self.0
+        }
+        fn header(&self, name: &str) -> Vec<&[u8]> {
+            self.1.iter()
+                .filter(|&&(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|&(_, v)| v.as_bytes())
+                .collect()
+        }
+    }
+
+    fn get() -> Req {
+        Req("GET", Vec::new())
+    }
+
     impl Resolver for Fake {
         fn handler(&self, _: &HandlerName) -> Option<Handler> {
             Some(Handler::HttpBin)
@@ -380,7 +937,9 @@ mod route_test {
             (HostPath::from_str(r).unwrap(), RouteDef {
                 handler: HandlerName::from(h),
                 authorizer: if a == "" { None }
-                    else { Some(AuthorizerName::from(a)) }
+                    else { Some(AuthorizerName::from(a)) },
+                log: None,
+                options: RouteOptions::default(),
             })
         }).collect::<Vec<_>>();
         RoutingTable::_create(items.iter().map(|&(ref x, ref y)| (x, y)),
@@ -391,15 +950,15 @@ mod route_test {
         table: &'x RoutingTable)
         -> Option<(&'x str, &'x str, &'x str)>
     {
-        route(host, path, table)
-        .map(|(x, p, s)| (&x.handler_name[..], p, s))
+        route(host, path, &get(), table)
+        .map(|(x, p, s, _)| (&x.handler_name[..], p, s))
     }
 
     pub fn route_a<'x>(host: &str, path: &'x str,
         table: &'x RoutingTable) -> &'x str
     {
-        route(host, path, table)
-        .map(|(x, _, _)| &x.authorizer_name[..])
+        route(host, path, &get(), table)
+        .map(|(x, _, _, _)| &x.authorizer_name[..])
         .unwrap_or("default")
     }
 
@@ -544,4 +1103,164 @@ mod route_test {
         assert_eq!(route_h("example.org", "/two", &table), None);
     }
 
+    #[test]
+    fn route_pattern() {
+        let table = table(vec![
+            ("ex.com", "0", ""),
+            ("ex.com/api", "1", ""),
+            (r"ex.com/api/v(\d+)/users", "2", ""),
+            ("ex.com/u/{name}/avatar", "3", ""),
+            ("ex.com/u/admin", "4", ""),
+        ]);
+        assert_eq!(route_h("ex.com", "/api/v2/users/7", &table),
+                   Some(("2", "/api/v2/users", "/7")));
+        assert_eq!(route_h("ex.com", "/api/v2/users", &table),
+                   Some(("2", "/api/v2/users", "")));
+        assert_eq!(route_h("ex.com", "/api/vx/users", &table),
+                   Some(("1", "/api", "/vx/users")));
+        assert_eq!(route_h("ex.com", "/api/v2/usersx", &table),
+                   Some(("1", "/api", "/v2/usersx")));
+        assert_eq!(route_h("ex.com", "/u/john/avatar?s=1", &table),
+                   Some(("3", "/u/john/avatar", "?s=1")));
+        assert_eq!(route_h("ex.com", "/u/admin/avatar", &table),
+                   Some(("4", "/u/admin", "/avatar")));
+        assert_eq!(route_h("ex.com", "/u/a/b/avatar", &table),
+                   Some(("0", "", "/u/a/b/avatar")));
+    }
+
+    #[test]
+    fn overlapping_patterns() {
+        let table = table(vec![
+            ("ex.com/u/{name}/avatar", "1", ""),
+            ("ex.com/u/{name}", "2", ""),
+            ("ex.com/u/me/{file}", "3", ""),
+        ]);
+        // longest literal prefix wins
+        assert_eq!(route_h("ex.com", "/u/me/avatar", &table),
+                   Some(("3", "/u/me/avatar", "")));
+        // then alphabetical order of the source
+        assert_eq!(route_h("ex.com", "/u/john/avatar", &table),
+                   Some(("2", "/u/john", "/avatar")));
+    }
+
+    #[test]
+    fn captures() {
+        let table = table(vec![
+            (r"ex.com/api/v(\d+)/{kind}", "1", ""),
+        ]);
+        let (_, _, _, caps) = route("ex.com", "/api/v2/users/7", &get(),
+                                    &table).unwrap();
+        assert_eq!(caps.get("1"), Some("2"));
+        assert_eq!(caps.get("2"), Some("users"));
+        assert_eq!(caps.get("kind"), Some("users"));
+        assert_eq!(caps.expand("/v{1}/{kind}/{unknown}"),
+                   "/v2/users/{unknown}");
+    }
+
+    #[test]
+    fn pattern_shape() {
+        let a = Pattern::new("/u/{name}").unwrap();
+        let b = Pattern::new("/u/{id}").unwrap();
+        assert_eq!(a.shape(), b.shape());
+        assert_eq!(a.groups(), vec!["1", "name"]);
+        assert!(Pattern::new("/u/{}").is_err());
+        assert!(Pattern::new("/u/(x").is_err());
+        assert_eq!(placeholders("/data/{name}/{1}.png"), vec!["name", "1"]);
+    }
+
+    #[test]
+    fn route_conditions() {
+        let table = table(vec![
+            ("ex.com", "0", ""),
+            ("ex.com/upload", "1", "upload"),
+            ("POST ex.com/upload", "2", ""),
+            ("ex.com/app [cookie canary=yes]", "3", ""),
+            ("ex.com/app [header X-Beta]", "4", ""),
+            ("PUT ex.com", "5", ""),
+        ]);
+        let route_r = |path, req: Req| {
+            route("ex.com", path, &req, &table)
+            .map(|(x, p, _, _)| (&x.handler_name[..], p,
+                                 &x.authorizer_name[..]))
+        };
+        assert_eq!(route_r("/upload", get()),
+                   Some(("1", "/upload", "upload")));
+        assert_eq!(route_r("/upload", Req("HEAD", vec![])),
+                   Some(("1", "/upload", "upload")));
+        // authorizer is inherited from the same path
+        assert_eq!(route_r("/upload/x", Req("POST", vec![])),
+                   Some(("2", "/upload", "upload")));
+        assert_eq!(route_r("/app", get()),
+                   Some(("0", "", "default")));
+        assert_eq!(route_r("/app", Req("GET", vec![
+                ("Cookie", "a=b; canary=yes")])),
+                   Some(("3", "/app", "default")));
+        assert_eq!(route_r("/app", Req("GET", vec![
+                ("Cookie", "canary=no")])),
+                   Some(("0", "", "default")));
+        assert_eq!(route_r("/app", Req("GET", vec![("X-Beta", "1")])),
+                   Some(("4", "/app", "default")));
+        assert_eq!(route_r("/other", Req("PUT", vec![])),
+                   Some(("5", "", "default")));
+        assert_eq!(route_r("/upload", Req("PUT", vec![])),
+                   Some(("1", "/upload", "upload")));
+    }
+
+    #[test]
+    fn route_log_and_options() {
+        let items = vec![
+            ("*.ex.com", "h ->access max-payload-size=1k"),
+            ("ex.com/api", "h ->api"),
+            ("ex.com/api/upload", "h max-payload-size=1M"),
+        ].into_iter().map(|(r, d)| {
+            (HostPath::from_str(r).unwrap(), RouteDef::from_str(d).unwrap())
+        }).collect::<Vec<_>>();
+        let table = RoutingTable::_create(
+            items.iter().map(|&(ref x, ref y)| (x, y)), Fake).unwrap();
+        let route_l = |path| {
+            route("ex.com", path, &get(), &table)
+            .map(|(x, _, _, _)| (x.log_format.as_ref().map(|x| &x[..]),
+                                 x.options.max_payload_size))
+        };
+        assert_eq!(route_l("/api/upload"),
+                   Some((Some("api"), Some(1 << 20))));
+        assert_eq!(route_l("/api/x"), Some((Some("api"), Some(1024))));
+        assert_eq!(route_l("/x"), Some((Some("access"), Some(1024))));
+    }
+
+    #[test]
+    fn explain_candidates() {
+        let table = table(vec![
+            ("ex.com", "0", ""),
+            ("ex.com/api", "1", ""),
+            ("ex.com/api [header X-Beta]", "2", ""),
+            (r"ex.com/api/v(\d+)", "3", ""),
+            (r"ex.com/static/{name}", "4", ""),
+        ]);
+        let candidates = |path| {
+            let exp = explain("ex.com", path, &get(), &table);
+            assert_eq!(exp.host, Some("ex.com"));
+            exp.candidates.into_iter()
+                .map(|(p, r, reason)| (p, &r.handler_name[..], reason))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(candidates("/api/v2/users"), vec![
+            (r"/api/v(\d+)", "3", None),
+            ("/api", "2", Some("conditions don't match")),
+            ("/api", "1", Some("pattern matched")),
+            ("", "0", Some("shorter prefix")),
+        ]);
+        assert_eq!(candidates("/api/vx"), vec![
+            (r"/api/v(\d+)", "3", Some("pattern doesn't match")),
+            ("/api", "2", Some("conditions don't match")),
+            ("/api", "1", None),
+            ("", "0", Some("shorter prefix")),
+        ]);
+        assert_eq!(candidates("/other"), vec![
+            ("", "0", None),
+        ]);
+        let exp = explain("other.com", "/", &get(), &table);
+        assert_eq!(exp.host, None);
+        assert!(exp.candidates.is_empty());
+    }
 }
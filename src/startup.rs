@@ -1,10 +1,11 @@
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use abstract_ns::HostResolve;
 use async_slot as slot;
 use futures::Stream;
-use futures::future::{Future};
+use futures::future::{Either, Future};
 use futures_cpupool;
 use ns_router::{self, SubscribeExt};
 use ns_router::future::AddrStream;
@@ -13,17 +14,21 @@ use self_meter_http::Meter;
 use tk_http::server::Proto;
 use tk_http;
 use tk_listen::{BindMany, ListenExt};
-use tokio_core::reactor::{Handle};
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_rustls::ServerConfigExt;
 use void::Void;
 
 use crate::config::listen::Listen;
 use crate::config::{ConfigCell};
-use crate::incoming::Router;
+use crate::incoming::{Router, Transport};
+use crate::local_sockets::{Acceptor, Connection, LocalListeners};
 use crate::chat;
 use crate::runtime::Runtime;
 use crate::http_pools::{HttpPools};
 use crate::handlers::files::{DiskPools};
 use crate::request_id;
+use crate::tls::TlsConfig;
 
 
 pub struct State {
@@ -31,18 +36,34 @@ pub struct State {
     session_pools: chat::SessionPools,
     disk_pools: DiskPools,
     listener_channel: slot::Sender<Listen>,
+    tls_listener_channel: slot::Sender<Listen>,
+    tls_config: TlsConfig,
+    local_listeners: LocalListeners<HttpAcceptor>,
     replication_session: chat::ReplicationSession,
     pub runtime: Arc<Runtime>,
 }
 
-pub fn spawn_listener(addr_stream: AddrStream, handle: &Handle,
-    runtime: &Arc<Runtime>, verbose: bool)
-{
+#[derive(Clone)]
+pub struct HttpAcceptor {
+    runtime: Arc<Runtime>,
+    config: Arc<tk_http::server::Config>,
+    handle: Handle,
+}
+
+impl Acceptor for HttpAcceptor {
+    fn accept<S: Transport>(&self, socket: S, addr: SocketAddr)
+        -> Connection
+    {
+        Box::new(Proto::new(socket, &self.config,
+                Router::new(addr, self.runtime.clone(), self.handle.clone()),
+                &self.handle)
+            .map_err(|e| debug!("Http protocol error: {}", e)))
+    }
+}
+
+fn http_config(runtime: &Arc<Runtime>) -> Arc<tk_http::server::Config> {
     let root = runtime.config.get();
-    let r1 = runtime.clone();
-    let r2 = runtime.clone();
-    // TODO(tailhook) how to update?
-    let hcfg = tk_http::server::Config::new()
+    tk_http::server::Config::new()
         .inflight_request_limit(root.pipeline_depth)
         // TODO(tailhook) make it configurable?
         .inflight_request_prealoc(0)
@@ -53,25 +74,82 @@ pub fn spawn_listener(addr_stream: AddrStream, handle: &Handle,
         .input_body_whole_timeout(root.input_body_whole_timeout)
         .output_body_byte_timeout(root.output_body_byte_timeout)
         .output_body_whole_timeout(root.output_body_whole_timeout)
-        .done();
-    let h1 = handle.clone();
-    handle.spawn(
+        .done()
+}
+
+/// Accepts connections on TCP addresses, doing TLS handshake if `tls` is set
+fn accept_loop(addr_stream: AddrStream, tls: Option<TlsConfig>,
+    acceptor: HttpAcceptor, verbose: bool)
+    -> Box<dyn Stream<Item=Connection, Error=()>>
+{
+    let handle = acceptor.handle.clone();
+    let kind = if tls.is_some() { " (TLS)" } else { "" };
+    let listen_error_timeout =
+        acceptor.runtime.config.get().listen_error_timeout;
+    Box::new(
         BindMany::new(addr_stream.map(move |addr| {
                 if verbose {
-                    println!("Listening at {}",
+                    println!("Listening{} at {}", kind,
                         addr.addresses_at(0)
                             .map(|x| x.to_string())
                             .collect::<Vec<_>>().join(", "));
                 }
                 addr.addresses_at(0)
-            }), handle)
-        .sleep_on_error(r1.config.get().listen_error_timeout, &r1.handle)
-        .map(move |(socket, saddr)| {
-             Proto::new(socket, &hcfg,
-                Router::new(saddr, r2.clone(), h1.clone()), &h1)
-             .map_err(|e| debug!("Http protocol error: {}", e))
+            }), &handle)
+        .sleep_on_error(listen_error_timeout, &handle)
+        .map(move |(socket, saddr)| match tls {
+            Some(ref tls) => tls_handshake(tls, socket, saddr, &acceptor),
+            None => acceptor.accept(socket, saddr),
+        }))
+}
+
+fn tls_handshake(tls: &TlsConfig, socket: TcpStream, addr: SocketAddr,
+    acceptor: &HttpAcceptor)
+    -> Connection
+{
+    let acceptor = acceptor.clone();
+    // Client that doesn't finish handshake must not hold the connection
+    // forever, so first byte timeout applies to the whole handshake
+    let timeout = Timeout::new(
+        acceptor.runtime.config.get().first_byte_timeout, &acceptor.handle)
+        .expect("timeout created");
+    Box::new(tls.get().accept_async(socket)
+        .select2(timeout)
+        .then(|res| match res {
+            Ok(Either::A((socket, _))) => Ok(socket),
+            Ok(Either::B(((), _))) => {
+                debug!("TLS handshake timed out");
+                Err(())
+            }
+            Err(Either::A((e, _))) => {
+                debug!("TLS handshake error: {}", e);
+                Err(())
+            }
+            Err(Either::B((e, _))) => {
+                error!("Timeout error: {}", e);
+                Err(())
+            }
         })
-        .listen(root.max_connections)
+        .and_then(move |socket| acceptor.accept(socket, addr)))
+}
+
+/// Spawns listener of plain and TLS TCP sockets
+///
+/// Both kinds of sockets share `max-connections` limit.
+pub fn spawn_listener(addr_stream: AddrStream, tls_addr_stream: AddrStream,
+    tls: &TlsConfig, handle: &Handle, runtime: &Arc<Runtime>, verbose: bool)
+{
+    // TODO(tailhook) how to update?
+    let acceptor = HttpAcceptor {
+        runtime: runtime.clone(),
+        config: http_config(runtime),
+        handle: handle.clone(),
+    };
+    handle.spawn(
+        accept_loop(addr_stream, None, acceptor.clone(), verbose)
+        .select(accept_loop(tls_addr_stream, Some(tls.clone()),
+                            acceptor, verbose))
+        .listen(runtime.config.get().max_connections)
         .map(move |()| panic!("Main listener exited"))
         .map_err(move |()| panic!("Main listener errored"))
     );
@@ -132,12 +210,26 @@ pub fn populate_loop(handle: &Handle, cfg: &ConfigCell, verbose: bool)
         server_id, cfg.fingerprint());
 
     let (listen_tx, listen_rx) = slot::channel();
-    listen_tx.swap(root.listen.clone()).unwrap();
+    listen_tx.swap(root.listen.plain()).unwrap();
+
+    let tls_config = TlsConfig::new(&root);
+    let (tls_tx, tls_rx) = slot::channel();
+    tls_tx.swap(root.listen.tls()).unwrap();
 
     spawn_listener(
         resolver.subscribe_stream(
             listen_rx.map_err(|()| -> Void { unreachable!() }), 80),
-        handle, &runtime, verbose);
+        resolver.subscribe_stream(
+            tls_rx.map_err(|()| -> Void { unreachable!() }), 443),
+        &tls_config, handle, &runtime, verbose);
+
+    let mut local_listeners = LocalListeners::new(HttpAcceptor {
+        runtime: runtime.clone(),
+        config: http_config(&runtime),
+        handle: handle.clone(),
+    }, handle);
+    local_listeners.update(&root.listen,
+        root.max_connections, root.listen_error_timeout, verbose);
 
     disk_pools.update(&root.disk_pools);
     http_pools.update(&root.http_destinations, &resolver, handle);
@@ -148,6 +240,9 @@ pub fn populate_loop(handle: &Handle, cfg: &ConfigCell, verbose: bool)
         session_pools: session_pools,
         replication_session: replication_session,
         listener_channel: listen_tx,
+        tls_listener_channel: tls_tx,
+        tls_config: tls_config,
+        local_listeners: local_listeners,
         runtime: runtime,
         disk_pools: disk_pools,
     }
@@ -155,8 +250,13 @@ pub fn populate_loop(handle: &Handle, cfg: &ConfigCell, verbose: bool)
 
 #[allow(dead_code)]
 pub fn update_loop(state: &mut State, cfg: &ConfigCell, handle: &Handle) {
-    state.listener_channel.swap(cfg.get().listen.clone())
+    state.listener_channel.swap(cfg.get().listen.plain())
         .map_err(|_| error!("Can't update listening sockets")).ok();
+    state.tls_config.update(&cfg.get());
+    state.tls_listener_channel.swap(cfg.get().listen.tls())
+        .map_err(|_| error!("Can't update TLS listening sockets")).ok();
+    state.local_listeners.update(&cfg.get().listen,
+        cfg.get().max_connections, cfg.get().listen_error_timeout, false);
     state.disk_pools.update(&cfg.get().disk_pools);
     state.http_pools.update(&cfg.get().http_destinations,
         &state.runtime.resolver, handle);
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crossbeam::sync::ArcCell;
+use rustls::{ServerConfig, ClientConfig, NoClientAuth, ResolvesServerCert};
+use rustls::{SignatureScheme};
+use rustls::sign::{CertifiedKey, RSASigningKey, SigningKey};
+use webpki::DNSNameRef;
+use webpki_roots::TLS_SERVER_ROOTS;
+
+use crate::config::ConfigData;
+use crate::config::http_destinations::Tls as DestinationTls;
+
+
+/// Chooses certificate by the server name sent by client (SNI)
+///
+/// Names are looked up exactly first, then by `*.suffix` wildcards starting
+/// from the longest one. The `*` certificate (if exists) is used for clients
+/// that don't send SNI at all.
+pub struct CertResolver {
+    certs: HashMap<String, CertifiedKey>,
+}
+
+/// Current TLS settings for all TLS listeners
+///
+/// Cell is updated on config reload, every new connection picks
+/// the current value
+#[derive(Clone)]
+pub struct TlsConfig(Arc<ArcCell<ServerConfig>>);
+
+impl CertResolver {
+    fn new(cfg: &ConfigData) -> CertResolver {
+        let mut certs = HashMap::new();
+        for (name, cert) in &cfg.tls_certificates {
+            let key: Box<dyn SigningKey> = Box::new(
+                RSASigningKey::new(&cert.key)
+                .expect("key type is checked when config is read"));
+            certs.insert(name.to_lowercase(),
+                CertifiedKey::new(cert.chain.clone(), Arc::new(key)));
+        }
+        CertResolver { certs }
+    }
+    fn find(&self, name: &str) -> Option<&CertifiedKey> {
+        if let Some(cert) = self.certs.get(name) {
+            return Some(cert);
+        }
+        for (idx, _) in name.match_indices(".") {
+            if let Some(cert) = self.certs.get(&format!("*{}", &name[idx..]))
+            {
+                return Some(cert);
+            }
+        }
+        self.certs.get("*")
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, server_name: Option<DNSNameRef>,
+        _sigschemes: &[SignatureScheme])
+        -> Option<CertifiedKey>
+    {
+        match server_name {
+            Some(name) => {
+                let name: &str = name.into();
+                let cert = self.find(&name.to_lowercase()).cloned();
+                if cert.is_none() {
+                    debug!("No TLS certificate for {:?}", name);
+                }
+                cert
+            }
+            None => self.certs.get("*").cloned(),
+        }
+    }
+}
+
+/// Certificates are validated when config is read, so this never fails,
+/// and configs having invalid certificates are never applied
+fn server_config(cfg: &ConfigData) -> ServerConfig {
+    let mut tls = ServerConfig::new(NoClientAuth::new());
+    tls.cert_resolver = Arc::new(CertResolver::new(cfg));
+    tls.set_protocols(&[String::from("http/1.1")]);
+    tls
+}
+
+impl TlsConfig {
+    pub fn new(cfg: &ConfigData) -> TlsConfig {
+        TlsConfig(Arc::new(ArcCell::new(Arc::new(server_config(cfg)))))
+    }
+    pub fn get(&self) -> Arc<ServerConfig> {
+        self.0.get()
+    }
+    /// Replaces certificates with the ones from the new config
+    pub fn update(&self, cfg: &ConfigData) {
+        self.0.set(Arc::new(server_config(cfg)));
+    }
+}
+
+/// Creates client settings for connecting to `http-destination`
+pub fn client_config(cfg: &DestinationTls) -> Result<ClientConfig, String> {
+    let files = cfg.files.as_ref()
+        .ok_or_else(|| format!("TLS files are not loaded"))?;
+    let mut tls = ClientConfig::new();
+    if let Some(ref path) = cfg.ca_bundle {
+        for cert in &files.ca_bundle {
+            tls.root_store.add(cert)
+                .map_err(|e| format!("bad certificate in {:?}: {:?}",
+                                     path, e))?;
+        }
+    } else {
+        tls.root_store.add_server_trust_anchors(&TLS_SERVER_ROOTS);
+    }
+    if let Some(ref cert) = files.client_certificate {
+        tls.set_single_client_cert(cert.chain.clone(), cert.key.clone());
+    }
+    tls.set_protocols(&[String::from("http/1.1")]);
+    Ok(tls)
+}
+
+/// Checks that name can be used for SNI (i.e. it's not an IP address)
+pub fn valid_server_name(name: &str) -> bool {
+    DNSNameRef::try_from_ascii_str(name).is_ok()
+}
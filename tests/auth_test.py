@@ -1,4 +1,8 @@
 import aiohttp
+import base64
+import hashlib
+import hmac
+import time
 
 def assert_gif(resp, data, debug_routing):
     assert resp.status == 200
@@ -13,6 +17,14 @@ def assert_403(resp, data, debug_routing):
     assert resp.status == 403
 
 
+async def password_request(http_request, url, auth):
+    # Password is verified in a thread pool the first time, only cached
+    # credentials are allowed synchronously and shown in X-Swindon-Allow
+    resp, data = await http_request(url, auth=auth)
+    assert resp.status == 200
+    return await http_request(url, auth=auth)
+
+
 async def test_local_ok(swindon, http_request, debug_routing):
     resp, data = await http_request(swindon.url / 'auth/local')
     assert_gif(resp, data, debug_routing)
@@ -37,3 +49,219 @@ async def test_forwarded_bad(swindon, http_request, debug_routing):
     if debug_routing:
         assert resp.headers['X-Swindon-Authorizer'] == 'by-header'
         assert resp.headers['X-Swindon-Deny'] == 'source-ip 4.4.4.4'
+
+
+async def test_forwarded_chain_ok(swindon, http_request, debug_routing):
+    resp, data = await http_request(swindon.url / 'auth/by-header',
+        headers={"X-Real-Ip": "4.4.4.4, 8.8.8.8"})
+    assert_gif(resp, data, debug_routing)
+    if debug_routing:
+        assert resp.headers['X-Swindon-Allow'] == \
+            'forwarded-from 127.0.0.1/24, source-ip 8.0.0.0/8'
+
+
+async def test_forwarded_chain_trusted_hop(swindon, http_request,
+        debug_routing):
+    resp, data = await http_request(swindon.url / 'auth/by-header',
+        headers={"X-Real-Ip": "8.8.8.8, 127.0.0.5"})
+    assert_gif(resp, data, debug_routing)
+    if debug_routing:
+        assert resp.headers['X-Swindon-Allow'] == \
+            'forwarded-from 127.0.0.1/24, forwarded-from 127.0.0.1/24, ' \
+            'source-ip 8.0.0.0/8'
+
+
+async def test_forwarded_chain_bad(swindon, http_request, debug_routing):
+    resp, data = await http_request(swindon.url / 'auth/by-header',
+        headers={"X-Real-Ip": "8.8.8.8, 4.4.4.4"})
+    assert_403(resp, data, debug_routing)
+    if debug_routing:
+        assert resp.headers['X-Swindon-Deny'] == 'source-ip 4.4.4.4'
+
+
+async def test_htpasswd_no_credentials(swindon, http_request, debug_routing):
+    resp, data = await http_request(swindon.url / 'auth/htpasswd')
+    assert resp.status == 401
+    assert resp.headers['WWW-Authenticate'] == 'Basic realm="Test \\"realm\\""'
+    if debug_routing:
+        assert resp.headers['X-Swindon-Authorizer'] == 'htpasswd'
+        assert resp.headers['X-Swindon-Deny'] == 'htpasswd no-credentials'
+
+
+async def test_htpasswd_ok(swindon, http_request, debug_routing):
+    resp, data = await password_request(http_request,
+        swindon.url / 'auth/htpasswd', aiohttp.BasicAuth('john', 'secret'))
+    assert_gif(resp, data, debug_routing)
+    if debug_routing:
+        assert resp.headers['X-Swindon-Allow'] == 'htpasswd john'
+
+
+async def test_htpasswd_bad(swindon, http_request, debug_routing):
+    resp, data = await http_request(swindon.url / 'auth/htpasswd',
+        auth=aiohttp.BasicAuth('john', 'wrong'))
+    assert resp.status == 401
+    if debug_routing:
+        assert resp.headers['X-Swindon-Deny'] == 'htpasswd john'
+
+
+async def test_htpasswd_unknown_user(swindon, http_request, debug_routing):
+    resp, data = await http_request(swindon.url / 'auth/htpasswd',
+        auth=aiohttp.BasicAuth('nobody', 'secret'))
+    assert resp.status == 401
+    assert resp.headers['WWW-Authenticate'] == 'Basic realm="Test \\"realm\\""'
+    if debug_routing:
+        assert resp.headers['X-Swindon-Deny'] == 'htpasswd nobody'
+
+
+async def test_subrequest_ok(proxy_server, swindon):
+    url = swindon.url / 'auth/subrequest/hello'
+    async with proxy_server() as proxy:
+        handler = proxy.send('GET', url, timeout=5, headers={
+            'Cookie': 'session=123',
+            'X-User-Id': 'spoofed',
+        })
+        req = await handler.request()
+        assert req.path == '/auth-check/auth/subrequest/hello'
+        assert req.headers['Cookie'] == 'session=123'
+        assert req.headers['X-Real-Ip'] == '127.0.0.1'
+        await handler.handler.response(b'', headers={'X-User-Id': '42'})
+
+        req = await proxy.wait_request()
+        assert req.path == '/auth/subrequest/hello'
+        assert req.headers.getall('X-User-Id') == ['42']
+        await proxy.set_response((b'OK',), {})
+        resp, body = await handler.client_response
+        assert resp.status == 200
+        assert body == b'OK'
+
+
+async def test_subrequest_header_not_returned(proxy_server, swindon):
+    url = swindon.url / 'auth/subrequest/hello'
+    async with proxy_server() as proxy:
+        handler = proxy.send('GET', url, timeout=5, headers={
+            'X-User-Id': 'spoofed',
+        })
+        req = await handler.request()
+        await handler.handler.response(b'')
+
+        req = await proxy.wait_request()
+        assert 'X-User-Id' not in req.headers
+        await proxy.set_response((b'OK',), {})
+        resp, body = await handler.client_response
+        assert resp.status == 200
+
+
+async def test_identity_headers(proxy_server, swindon):
+    url = swindon.url / 'auth/identity'
+    async with proxy_server() as proxy:
+        handler = proxy.send('GET', url, timeout=5, headers={
+            'Authorization': 'Basic am9objpzZWNyZXQ=',
+            'X-Login': 'spoofed',
+            'X-Network': 'spoofed',
+        })
+        req = await handler.request()
+        assert req.headers.getall('X-Login') == ['john']
+        assert 'X-Network' not in req.headers
+        resp, _ = await handler.response('OK')
+        assert resp.status == 200
+
+        handler = proxy.send('GET', url, timeout=5, headers={
+            'X-Real-Ip': '8.8.8.8',
+            'X-Login': 'spoofed',
+        })
+        req = await handler.request()
+        assert 'X-Login' not in req.headers
+        assert req.headers.getall('X-Network') == ['goog']
+        resp, _ = await handler.response('OK')
+        assert resp.status == 200
+
+
+async def test_subrequest_denied(proxy_server, swindon, debug_routing):
+    url = swindon.url / 'auth/subrequest/hello'
+    async with proxy_server() as proxy:
+        handler = proxy.send('GET', url, timeout=5)
+        req = await handler.request()
+        assert req.path == '/auth-check/auth/subrequest/hello'
+        resp, body = await handler.response(b'', status=401)
+        assert resp.status == 403
+        if debug_routing:
+            assert resp.headers['X-Swindon-Authorizer'] == 'subrequest'
+            assert resp.headers['X-Swindon-Deny'] == 'subrequest 401'
+
+
+async def test_any_of(swindon, http_request, debug_routing):
+    url = swindon.url / 'auth/any-of'
+    resp, data = await http_request(url, headers={"X-Real-Ip": "8.8.8.8"})
+    assert_gif(resp, data, debug_routing)
+    if debug_routing:
+        assert resp.headers['X-Swindon-Allow'] == \
+            'forwarded-from 127.0.0.1/24, source-ip 8.0.0.0/8, ' \
+            'any-of by-header'
+        assert 'X-Swindon-Deny' not in resp.headers
+
+    resp, data = await password_request(http_request, url,
+        aiohttp.BasicAuth('john', 'secret'))
+    assert_gif(resp, data, debug_routing)
+    if debug_routing:
+        assert resp.headers['X-Swindon-Allow'].endswith(
+            'htpasswd john, any-of htpasswd')
+
+    resp, data = await http_request(url)
+    assert resp.status == 401
+    if debug_routing:
+        assert resp.headers['X-Swindon-Deny'] == \
+            'htpasswd: htpasswd no-credentials'
+
+
+async def test_all_of(swindon, http_request, debug_routing):
+    url = swindon.url / 'auth/all-of'
+    resp, data = await password_request(http_request, url,
+        aiohttp.BasicAuth('john', 'secret'))
+    assert_gif(resp, data, debug_routing)
+    if debug_routing:
+        assert resp.headers['X-Swindon-Allow'] == \
+            'source-ip 127.0.0.1/24, htpasswd john'
+
+    resp, data = await http_request(url,
+        auth=aiohttp.BasicAuth('john', 'wrong'))
+    assert resp.status == 401
+    if debug_routing:
+        assert resp.headers['X-Swindon-Deny'] == 'htpasswd: htpasswd john'
+
+
+def signature_params(path, secret, expires):
+    message = '{}\n{}'.format(expires, path).encode('ascii')
+    sig = hmac.new(secret, message, hashlib.sha256).digest()
+    sig = base64.urlsafe_b64encode(sig).decode('ascii').rstrip('=')
+    return {'expires': str(expires), 'signature': sig}
+
+
+async def test_signed_url_ok(swindon, http_request, debug_routing):
+    params = signature_params('/auth/signed/file.zip', b'old-secret',
+                              int(time.time()) + 60)
+    resp, data = await http_request(swindon.url / 'auth/signed/file.zip',
+        params=params)
+    assert_gif(resp, data, debug_routing)
+    if debug_routing:
+        assert resp.headers['X-Swindon-Authorizer'] == 'signed-url'
+        assert resp.headers['X-Swindon-Allow'] == 'signed-url'
+
+
+async def test_signed_url_expired(swindon, http_request, debug_routing):
+    params = signature_params('/auth/signed/file.zip', b'new-secret',
+                              int(time.time()) - 1)
+    resp, data = await http_request(swindon.url / 'auth/signed/file.zip',
+        params=params)
+    assert_403(resp, data, debug_routing)
+    if debug_routing:
+        assert resp.headers['X-Swindon-Deny'] == 'signed-url expired'
+
+
+async def test_signed_url_bad(swindon, http_request, debug_routing):
+    params = signature_params('/auth/signed/other.zip', b'new-secret',
+                              int(time.time()) + 60)
+    resp, data = await http_request(swindon.url / 'auth/signed/file.zip',
+        params=params)
+    assert_403(resp, data, debug_routing)
+    if debug_routing:
+        assert resp.headers['X-Swindon-Deny'] == 'signed-url bad-signature'
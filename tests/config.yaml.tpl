@@ -26,6 +26,11 @@ routing:
   localhost/empty.gif: empty_gif
   localhost/empty-w-headers.gif: empty_gif_w_headers
   localhost/empty-w-content-length.gif: empty_gif_w_clen
+  localhost/conditional: empty_gif
+  localhost/conditional [header X-Canary=1]: empty_gif_w_headers
+  DELETE localhost/conditional: default
+  localhost/split.gif: split_gif
+  localhost/split-sticky.gif: split_sticky
 
   ### !SingleFile routes ###
   localhost/static-file: single_file
@@ -34,6 +39,7 @@ routing:
   localhost/static-file-headers: extra_headers
   localhost/symlink: single_symlink
   localhost/dev-null: dev_null
+  "localhost/pattern-file/{name}": single_file_pattern
 
   ### !Static routes ###
   localhost/static: static
@@ -56,10 +62,15 @@ routing:
   ### !Proxy routes ###
   localhost/proxy: proxy
   localhost/proxy-w-prefix: proxy_w_prefix
+  localhost/proxy-strip: proxy_w_strip
+  'localhost/proxy-pattern/v(\d+)': proxy_w_pattern
   localhost/proxy-w-ip-header: proxy_w_ip_header
   localhost/proxy-w-request-id: proxy_w_request_id
   localhost/proxy-w-host: proxy_w_host
   localhost/proxy-w-timeout: proxy_w_timeout
+  localhost/proxy-w-timeout/fast: proxy_w_timeout response-timeout=300ms
+  localhost/proxy-w-timeout/slow: proxy_w_timeout response-timeout=3s
+  localhost/proxy-w-response-timeout: proxy_w_response_timeout
 
   ### !SwindonLattice compatibility routes ###
   localhost/swindon-chat: swindon_chat
@@ -83,6 +94,12 @@ routing:
   ### !Authorized routes ###
   localhost/auth/local: empty_gif @only-127-0-0-1
   localhost/auth/by-header: empty_gif @by-header
+  localhost/auth/htpasswd: empty_gif @htpasswd
+  localhost/auth/subrequest: proxy @subrequest
+  localhost/auth/any-of: empty_gif @header-or-password
+  localhost/auth/all-of: empty_gif @local-and-password
+  localhost/auth/signed: empty_gif @signed-url
+  localhost/auth/identity: proxy_w_identity @header-or-password
 
 # Configure all possible handlers?
 handlers:
@@ -94,6 +111,19 @@ handlers:
   empty_gif_w_headers: !EmptyGif
     extra-headers:
       X-Some-Header: some value
+  split_gif: !Split
+    variants:
+    - handler: empty_gif
+      weight: 0
+    - handler: empty_gif_w_headers
+      weight: 1
+  split_sticky: !Split
+    sticky: !Cookie uid
+    variants:
+    - handler: empty_gif
+      weight: 1
+    - handler: empty_gif_w_headers
+      weight: 1
   empty_gif_w_clen: !EmptyGif
     extra-headers:
       Content-Type: image/other
@@ -119,6 +149,9 @@ handlers:
   single_symlink: !SingleFile
     path: ${TESTS_DIR}/assets/link.txt
     content-type: text/plain
+  single_file_pattern: !SingleFile
+    path: ${TESTS_DIR}/assets/{name}.txt
+    content-type: text/plain
   dev_null: !SingleFile
     path: /dev/null
     content-type: text/plain
@@ -175,6 +208,19 @@ handlers:
     destination: proxy_dest/
   proxy_w_prefix: !Proxy
     destination: proxy_dest/prefix
+  proxy_w_strip: !Proxy
+    destination: proxy_dest/app
+    strip-prefix: true
+    rewrite:
+    - pattern: ^/old/(.*)$
+      replace: /new/$1
+  proxy_w_identity: !Proxy
+    destination: proxy_dest/
+    identity-headers:
+      X-Login: login
+      X-Network: network
+  proxy_w_pattern: !Proxy
+    destination: proxy_dest/api{1}
   proxy_w_ip_header: !Proxy
     destination: proxy_dest
     ip-header: X-Some-Header
@@ -184,6 +230,8 @@ handlers:
     destination: proxy_host
   proxy_w_timeout: !Proxy
     destination: proxy_timeout
+  proxy_w_response_timeout: !Proxy
+    destination: proxy_response_timeout
   swindon_proxy: !Proxy
     destination: swindon_http_dest
 
@@ -283,6 +331,10 @@ http-destinations:
     addresses:
     - *PROXY_ADDRESS
     max-request-timeout: 1s
+  proxy_response_timeout:
+    addresses:
+    - *PROXY_ADDRESS
+    response-timeout: 300ms
 
   ### SwindonLattice compatibility destinations ###
   swindon_http_dest:
@@ -329,6 +381,21 @@ authorizers:
     forwarded-ip-header: X-Real-Ip
     accept-forwarded-headers-from: only-127-0-0-1
 
+  htpasswd: !HtPasswd
+    file: ${TESTS_DIR}/htpasswd
+    realm: Test "realm"
+
+  header-or-password: !AnyOf [by-header, htpasswd]
+  local-and-password: !AllOf [only-127-0-0-1, htpasswd]
+
+  signed-url: !SignedUrl
+    secrets: [new-secret, old-secret]
+
+  subrequest: !Subrequest
+    destination: proxy_dest/auth-check
+    ip-header: X-Real-Ip
+    copy-headers: [X-User-Id]
+
 networks:
   only-127-0-0-1:
   - 127.0.0.1
@@ -348,3 +348,86 @@ def test_bad_mixins(check_config):
         '''))
     assert ('app1.yaml" has handler named "handler" without prefix "app1-"'
         ) in err
+
+
+def test_authorizer_cycle(check_config):
+    err = check_config("""
+        authorizers:
+            a: !AnyOf [b, c]
+            b: !AllOf [default, a]
+            c: !AllowAll
+    """)
+    assert "authorizers form a cycle: " in err
+
+
+def test_unknown_combined_authorizer(check_config):
+    err = check_config("""
+        authorizers:
+            a: !AnyOf [b]
+    """)
+    assert "unknown authorizer authorizer\\"b\\"" in err
+
+
+def test_ambiguous_patterns(check_config):
+    err = check_config("""
+        routing:
+            "localhost/u/{name}": default
+            "localhost/u/{id}": default
+    """)
+    assert "Ambiguous routes localhost/u/{id} and localhost/u/{name}" in err
+
+
+def test_unknown_capture(check_config):
+    err = check_config("""
+        routing:
+            "localhost/u/{name}": avatar
+        handlers:
+            avatar: !SingleFile
+                path: /var/avatars/{id}.png
+    """)
+    assert "uses {id}, but route localhost/u/{name} has no such group" in err
+
+
+def test_unknown_log_format(check_config):
+    err = check_config("""
+        routing:
+            localhost/api: default ->access
+    """)
+    assert 'Log format' in err and 'is not defined' in err
+
+
+def test_unknown_route_option(check_config):
+    err = check_config("""
+        routing:
+            localhost/api: default max-body=1
+    """)
+    assert 'Unknown route option' in err and 'max-body' in err
+
+
+def test_nested_split(check_config):
+    err = check_config("""
+        routing:
+            localhost/: outer
+        handlers:
+            outer: !Split
+                variants:
+                - handler: inner
+                  weight: 1
+            inner: !Split
+                variants:
+                - handler: default
+                  weight: 1
+    """)
+    assert 'refers to another split' in err
+
+
+def test_invalid_ca_bundle(check_config):
+    err = check_config("""
+        http-destinations:
+            dummy:
+                addresses:
+                - example.com:443
+                tls:
+                    ca-bundle: /dev/null
+    """)
+    assert '"dummy"' in err and 'no certificates found' in err
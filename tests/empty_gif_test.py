@@ -51,3 +51,50 @@ async def test_headers_override(swindon, http_request):
              if key == b'Content-Type']
     assert len(ctype) == 1
     assert ctype[0] == b'image/other'
+
+
+async def test_header_condition(swindon, http_request, debug_routing):
+    resp, data = await http_request(swindon.url / 'conditional')
+    assert resp.status == 200
+    assert 'X-Some-Header' not in resp.headers
+    if debug_routing:
+        assert resp.headers['X-Swindon-Route'] == 'empty_gif'
+
+    resp, data = await http_request(swindon.url / 'conditional',
+        headers={'X-Canary': '1'})
+    assert resp.status == 200
+    assert resp.headers['X-Some-Header'] == 'some value'
+    if debug_routing:
+        assert resp.headers['X-Swindon-Route'] == \
+            'empty_gif_w_headers ([header X-Canary=1])'
+
+
+async def test_method_condition(swindon, loop, debug_routing):
+    async with aiohttp.ClientSession(loop=loop) as s:
+        async with s.delete(swindon.url / 'conditional') as resp:
+            assert resp.status == 404
+            if debug_routing:
+                assert resp.headers['X-Swindon-Route'] == \
+                    'default (DELETE)'
+
+
+async def test_split(swindon, http_request, debug_routing):
+    resp, data = await http_request(swindon.url / 'split.gif')
+    assert resp.status == 200
+    assert resp.headers['X-Some-Header'] == 'some value'
+    if debug_routing:
+        assert resp.headers['X-Swindon-Route'] == \
+            'split_gif -> empty_gif_w_headers'
+
+
+async def test_split_sticky(swindon, http_request):
+    for uid in ['1', '2', '3', '4']:
+        resp, data = await http_request(swindon.url / 'split-sticky.gif',
+            headers={'Cookie': 'uid=' + uid})
+        assert resp.status == 200
+        first = resp.headers.get('X-Some-Header')
+        for i in range(5):
+            resp, data = await http_request(swindon.url / 'split-sticky.gif',
+                headers={'Cookie': 'uid=' + uid})
+            assert resp.status == 200
+            assert resp.headers.get('X-Some-Header') == first
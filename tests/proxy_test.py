@@ -1,4 +1,5 @@
 import asyncio
+import pytest
 import async_timeout
 
 from aiohttp import HttpVersion11
@@ -79,6 +80,61 @@ async def test_prefix(proxy_server, swindon):
         assert resp.status == 200
 
 
+async def test_strip_prefix(proxy_server, swindon):
+    url = swindon.url / 'proxy-strip/tail'
+    async with proxy_server() as proxy:
+        handler = proxy.send('GET', url, timeout=5)
+
+        req = await handler.request()
+        assert req.path == '/app/tail'
+
+        resp, _ = await handler.response('OK', headers={
+            'Location': '/app/login',
+            'Set-Cookie': 'sid=1; Path=/app; HttpOnly',
+            })
+        assert resp.status == 200
+        assert resp.headers['Location'] == '/proxy-strip/login'
+        assert resp.headers['Set-Cookie'] == \
+            'sid=1; Path=/proxy-strip; HttpOnly'
+
+
+async def test_strip_prefix_root(proxy_server, swindon):
+    url = swindon.url / 'proxy-strip'
+    async with proxy_server() as proxy:
+        handler = proxy.send('GET', url, timeout=5)
+
+        req = await handler.request()
+        assert req.path == '/app'
+
+        resp, _ = await handler.response('OK')
+        assert resp.status == 200
+
+
+async def test_path_rewrite(proxy_server, swindon):
+    url = (swindon.url / 'proxy-strip/old/page').with_query(x='1')
+    async with proxy_server() as proxy:
+        handler = proxy.send('GET', url, timeout=5)
+
+        req = await handler.request()
+        assert req.path == '/app/new/page'
+        assert req.query_string == 'x=1'
+
+        resp, _ = await handler.response('OK')
+        assert resp.status == 200
+
+
+async def test_pattern_capture(proxy_server, swindon):
+    url = swindon.url / 'proxy-pattern/v2/tail'
+    async with proxy_server() as proxy:
+        handler = proxy.send('GET', url, timeout=5)
+
+        req = await handler.request()
+        assert req.path == '/api2/proxy-pattern/v2/tail'
+
+        resp, _ = await handler.response('OK')
+        assert resp.status == 200
+
+
 async def test_ip_header(proxy_server, swindon, request):
     is_wsgi = request.node.keywords.get('wsgi') is not None
     url = swindon.url / 'proxy-w-ip-header'
@@ -138,6 +194,26 @@ async def test_post_form(proxy_server, swindon):
         assert resp.status == 200
 
 
+@pytest.mark.parametrize('path', [
+    'proxy-w-timeout/fast',
+    'proxy-w-response-timeout',
+])
+async def test_response_timeout(proxy_server, swindon, loop, path):
+    url = swindon.url / path
+    async with proxy_server() as proxy:
+        handler, client_resp = proxy.send('GET', url, timeout=5)
+        assert not client_resp.done(), await client_resp
+
+        req = await handler.request(timeout=5)
+        assert req.path == '/' + path
+        await asyncio.sleep(0.7, loop=loop)
+
+        assert client_resp.done()
+        with async_timeout.timeout(5, loop=loop):
+            resp, _ = await client_resp
+        assert resp.status == 504
+
+
 async def test_request_timeout(proxy_server, swindon, loop):
     url = swindon.url / 'proxy-w-timeout'
     async with proxy_server() as proxy:
@@ -151,4 +227,23 @@ async def test_request_timeout(proxy_server, swindon, loop):
         assert client_resp.done()
         with async_timeout.timeout(5, loop=loop):
             resp, _ = await client_resp
-        assert resp.status == 502
+        assert resp.status == 504
+
+
+async def test_response_timeout_above_max_request_timeout(
+        proxy_server, swindon, loop):
+    url = swindon.url / 'proxy-w-timeout/slow'
+    async with proxy_server() as proxy:
+        handler, client_resp = proxy.send('GET', url, timeout=5)
+        assert not client_resp.done(), await client_resp
+
+        req = await handler.request(timeout=5)
+        assert req.path == '/proxy-w-timeout/slow'
+        # longer than `max-request-timeout` of the destination
+        await asyncio.sleep(1.5, loop=loop)
+        assert not client_resp.done()
+
+        await handler.response('OK')
+        with async_timeout.timeout(5, loop=loop):
+            resp, _ = await client_resp
+        assert resp.status == 200
@@ -63,6 +63,16 @@ async def test_ok(swindon, get_request, static_request_method,
         assert 'X-Swindon-File-Path' not in resp.headers
 
 
+async def test_pattern_path(swindon, get_request, static_request_method,
+        debug_routing, TESTS_DIR):
+    resp, data = await get_request(swindon.url / 'pattern-file/static_file')
+    assert resp.status == 200
+    data_check(data, static_request_method, b'Static file test\n')
+    if debug_routing:
+        assert resp.headers['X-Swindon-File-Path'] == \
+            '"{}/assets/static_file.txt"'.format(TESTS_DIR)
+
+
 async def test_url_decoding(swindon, get_request, static_request_method,
         debug_routing, TESTS_DIR):
 